@@ -1,9 +1,9 @@
 use crate::proto::messages::horust_msg_message::MessageType;
 use crate::proto::messages::{
-    HorustMsgMessage, HorustMsgRequest, HorustMsgServiceStatusRequest, horust_msg_request,
-    horust_msg_response,
+    HorustMsgMessage, HorustMsgRequest, HorustMsgServiceLogsRequest, HorustMsgServiceStatusRequest,
+    horust_msg_request, horust_msg_response,
 };
-use crate::{HorustMsgServiceStatus, UdsConnectionHandler};
+use crate::{HorustMsgServiceStatus, HorustMsgServiceStatusResponse, UdsConnectionHandler};
 use anyhow::{Context, anyhow};
 use anyhow::{Result, bail};
 use log::{debug, info};
@@ -29,6 +29,7 @@ fn unwrap_response(response: HorustMsgMessage) -> Option<Result<horust_msg_respo
                 Some(Err(anyhow!("Error: {}", error.error_string)))
             }
             horust_msg_response::Response::StatusResponse(_status) => Some(Ok(v)),
+            horust_msg_response::Response::LogsResponse(_logs) => Some(Ok(v)),
         };
     }
     None
@@ -45,14 +46,12 @@ impl ClientHandler {
             ),
         })
     }
-    pub fn send_status_request(
+    fn send_request(
         &mut self,
-        service_name: String,
-    ) -> Result<(String, HorustMsgServiceStatus)> {
-        let status = new_request(horust_msg_request::Request::StatusRequest(
-            HorustMsgServiceStatusRequest { service_name },
-        ));
-        self.uds_connection_handler.send_message(status)?;
+        request: horust_msg_request::Request,
+    ) -> Result<horust_msg_response::Response> {
+        self.uds_connection_handler
+            .send_message(new_request(request))?;
         // server is waiting for EOF.
         self.uds_connection_handler
             .socket
@@ -60,12 +59,52 @@ impl ClientHandler {
         //Reads all bytes until EOF in this source, appending them to buf.
         let received = self.uds_connection_handler.receive_message()?;
         debug!("Client: received: {received:?}");
-        let response = unwrap_response(received).unwrap()?;
+        unwrap_response(received).unwrap()
+    }
+
+    pub fn send_status_request(
+        &mut self,
+        service_name: String,
+    ) -> Result<(String, HorustMsgServiceStatus)> {
+        self.send_status_details_request(service_name).map(|resp| {
+            let status = HorustMsgServiceStatus::try_from(resp.service_status).unwrap();
+            (resp.service_name, status)
+        })
+    }
+
+    /// Like `send_status_request`, but returns the whole response: with the last results of the
+    /// command health checks, whether the service is degraded and whether its last run was
+    /// OOM-killed.
+    pub fn send_status_details_request(
+        &mut self,
+        service_name: String,
+    ) -> Result<HorustMsgServiceStatusResponse> {
+        let response = self.send_request(horust_msg_request::Request::StatusRequest(
+            HorustMsgServiceStatusRequest { service_name },
+        ))?;
         if let horust_msg_response::Response::StatusResponse(resp) = response {
-            Ok((
-                resp.service_name,
-                HorustMsgServiceStatus::try_from(resp.service_status).unwrap(),
-            ))
+            Ok(resp)
+        } else {
+            bail!("Invalid response received: {:?}", response);
+        }
+    }
+
+    /// Returns the buffered output of the service, and the offset to use for the next request.
+    pub fn send_logs_request(
+        &mut self,
+        service_name: String,
+        tail: u32,
+        offset: Option<u64>,
+    ) -> Result<(Vec<u8>, u64)> {
+        let response = self.send_request(horust_msg_request::Request::LogsRequest(
+            HorustMsgServiceLogsRequest {
+                service_name,
+                tail,
+                offset,
+            },
+        ))?;
+        if let horust_msg_response::Response::LogsResponse(resp) = response {
+            Ok((resp.content, resp.next_offset))
         } else {
             bail!("Invalid response received: {:?}", response);
         }
@@ -5,7 +5,9 @@ mod client;
 mod proto;
 mod server;
 use crate::proto::messages::HorustMsgMessage;
-pub use crate::proto::messages::HorustMsgServiceStatus;
+pub use crate::proto::messages::{
+    HorustMsgHealthCheckResult, HorustMsgServiceStatus, HorustMsgServiceStatusResponse,
+};
 use anyhow::{Context, Result};
 pub use client::ClientHandler;
 use log::debug;
@@ -16,7 +16,7 @@ pub mod horust_msg_message {
 }
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct HorustMsgRequest {
-    #[prost(oneof = "horust_msg_request::Request", tags = "1, 2")]
+    #[prost(oneof = "horust_msg_request::Request", tags = "1, 2, 3")]
     pub request: ::core::option::Option<horust_msg_request::Request>,
 }
 /// Nested message and enum types in `HorustMsgRequest`.
@@ -27,11 +27,13 @@ pub mod horust_msg_request {
         StatusRequest(super::HorustMsgServiceStatusRequest),
         #[prost(message, tag = "2")]
         ChangeRequest(super::HorustMsgServiceChangeRequest),
+        #[prost(message, tag = "3")]
+        LogsRequest(super::HorustMsgServiceLogsRequest),
     }
 }
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct HorustMsgResponse {
-    #[prost(oneof = "horust_msg_response::Response", tags = "1, 2")]
+    #[prost(oneof = "horust_msg_response::Response", tags = "1, 2, 3")]
     pub response: ::core::option::Option<horust_msg_response::Response>,
 }
 /// Nested message and enum types in `HorustMsgResponse`.
@@ -42,6 +44,8 @@ pub mod horust_msg_response {
         Error(super::HorustMsgError),
         #[prost(message, tag = "2")]
         StatusResponse(super::HorustMsgServiceStatusResponse),
+        #[prost(message, tag = "3")]
+        LogsResponse(super::HorustMsgServiceLogsResponse),
     }
 }
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
@@ -60,6 +64,25 @@ pub struct HorustMsgServiceStatusResponse {
     pub service_name: ::prost::alloc::string::String,
     #[prost(enumeration = "HorustMsgServiceStatus", tag = "2")]
     pub service_status: i32,
+    #[prost(message, repeated, tag = "3")]
+    pub health_checks: ::prost::alloc::vec::Vec<HorustMsgHealthCheckResult>,
+    /// The health checks are failing, but the service is kept running (on-failure = "mark-unhealthy").
+    #[prost(bool, tag = "4")]
+    pub degraded: bool,
+    /// The last run of the service was killed by the kernel, out of memory in its cgroup.
+    #[prost(bool, tag = "5")]
+    pub oom_killed: bool,
+}
+/// Last run of a command health check.
+/// exit_code: unset if the command couldn't be run, timed out or was killed by a signal.
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct HorustMsgHealthCheckResult {
+    #[prost(string, tag = "1")]
+    pub command: ::prost::alloc::string::String,
+    #[prost(int32, optional, tag = "2")]
+    pub exit_code: ::core::option::Option<i32>,
+    #[prost(string, tag = "3")]
+    pub output: ::prost::alloc::string::String,
 }
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct HorustMsgServiceChangeRequest {
@@ -76,6 +99,27 @@ pub struct HorustMsgServiceChangeResponse {
     #[prost(enumeration = "HorustMsgServiceStatus", tag = "2")]
     pub service_status: i32,
 }
+/// tail: return only the last lines, 0 means the whole buffer.
+/// offset: return only the output produced after this offset, takes precedence over tail.
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct HorustMsgServiceLogsRequest {
+    #[prost(string, tag = "1")]
+    pub service_name: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "2")]
+    pub tail: u32,
+    #[prost(uint64, optional, tag = "3")]
+    pub offset: ::core::option::Option<u64>,
+}
+/// next_offset: offset to use for fetching the output produced after this response.
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct HorustMsgServiceLogsResponse {
+    #[prost(string, tag = "1")]
+    pub service_name: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub content: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint64, tag = "3")]
+    pub next_offset: u64,
+}
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
 #[repr(i32)]
 pub enum HorustMsgServiceStatus {
@@ -1 +1,3 @@
+// Generated by prost-build: oneof variants are named after the messages.
+#[allow(clippy::enum_variant_names)]
 pub mod messages;
@@ -1,7 +1,8 @@
 use crate::UdsConnectionHandler;
 use crate::proto::messages::horust_msg_message::MessageType::Request;
 use crate::proto::messages::{
-    HorustMsgError, HorustMsgMessage, HorustMsgRequest, HorustMsgResponse, HorustMsgServiceStatus,
+    HorustMsgError, HorustMsgHealthCheckResult, HorustMsgMessage, HorustMsgRequest,
+    HorustMsgResponse, HorustMsgServiceLogsResponse, HorustMsgServiceStatus,
     HorustMsgServiceStatusResponse, horust_msg_message, horust_msg_request, horust_msg_response,
 };
 use anyhow::{Result, anyhow};
@@ -51,11 +52,20 @@ pub trait CommandsHandlerTrait {
 
                     let service_status = self.get_service_status(&status_request.service_name);
                     service_status
-                        .map(|status| {
-                            new_horust_msg_service_status_response(
+                        .and_then(|status| {
+                            let health_checks =
+                                self.get_health_check_results(&status_request.service_name)?;
+                            let degraded =
+                                self.is_service_degraded(&status_request.service_name)?;
+                            let oom_killed =
+                                self.was_service_oom_killed(&status_request.service_name)?;
+                            Ok(new_horust_msg_service_status_response(
                                 status_request.service_name,
                                 status,
-                            )
+                                health_checks,
+                                degraded,
+                                oom_killed,
+                            ))
                         })
                         .unwrap_or_else(|err| {
                             new_horust_msg_error_response(format!(
@@ -78,12 +88,33 @@ pub trait CommandsHandlerTrait {
                         new_horust_msg_service_status_response(
                             change_request.service_name,
                             new_status,
+                            vec![],
+                            false,
+                            false,
                         )
                     })
                     .unwrap_or_else(|err| {
                         new_horust_msg_error_response(format!("Error from change handler: {err}"))
                     })*/
                 }
+                horust_msg_request::Request::LogsRequest(logs_request) => {
+                    info!("Requested logs for {}", logs_request.service_name);
+                    self.get_service_logs(
+                        &logs_request.service_name,
+                        logs_request.tail,
+                        logs_request.offset,
+                    )
+                    .map(|(content, next_offset)| {
+                        new_horust_msg_service_logs_response(
+                            logs_request.service_name,
+                            content,
+                            next_offset,
+                        )
+                    })
+                    .unwrap_or_else(|err| {
+                        new_horust_msg_error_response(format!("Error from logs handler: {err}"))
+                    })
+                }
             };
             uds_conn_handler.send_message(response)?;
         }
@@ -91,6 +122,28 @@ pub trait CommandsHandlerTrait {
     }
 
     fn get_service_status(&self, service_name: &str) -> Result<HorustMsgServiceStatus>;
+    /// Returns the last result of the command health checks of the service.
+    fn get_health_check_results(
+        &self,
+        _service_name: &str,
+    ) -> Result<Vec<HorustMsgHealthCheckResult>> {
+        Ok(vec![])
+    }
+    /// Whether the service is running, but marked unhealthy.
+    fn is_service_degraded(&self, _service_name: &str) -> Result<bool> {
+        Ok(false)
+    }
+    /// Whether the last run of the service was OOM-killed.
+    fn was_service_oom_killed(&self, _service_name: &str) -> Result<bool> {
+        Ok(false)
+    }
+    /// Returns the buffered output of the service, and the offset to use for the next request.
+    fn get_service_logs(
+        &self,
+        service_name: &str,
+        tail: u32,
+        offset: Option<u64>,
+    ) -> Result<(Vec<u8>, u64)>;
     fn update_service_status(
         &self,
         service_name: &str,
@@ -113,6 +166,9 @@ pub fn new_horust_msg_error_response(error: String) -> HorustMsgMessage {
 pub fn new_horust_msg_service_status_response(
     service_name: String,
     status: HorustMsgServiceStatus,
+    health_checks: Vec<HorustMsgHealthCheckResult>,
+    degraded: bool,
+    oom_killed: bool,
 ) -> HorustMsgMessage {
     HorustMsgMessage {
         message_type: Some(horust_msg_message::MessageType::Response(
@@ -121,6 +177,29 @@ pub fn new_horust_msg_service_status_response(
                     HorustMsgServiceStatusResponse {
                         service_name,
                         service_status: status.into(),
+                        health_checks,
+                        degraded,
+                        oom_killed,
+                    },
+                )),
+            },
+        )),
+    }
+}
+
+pub fn new_horust_msg_service_logs_response(
+    service_name: String,
+    content: Vec<u8>,
+    next_offset: u64,
+) -> HorustMsgMessage {
+    HorustMsgMessage {
+        message_type: Some(horust_msg_message::MessageType::Response(
+            HorustMsgResponse {
+                response: Some(horust_msg_response::Response::LogsResponse(
+                    HorustMsgServiceLogsResponse {
+                        service_name,
+                        content,
+                        next_offset,
                     },
                 )),
             },
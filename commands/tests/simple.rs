@@ -1,7 +1,9 @@
 use anyhow::Result;
 use std::os::unix::net::UnixListener;
 
-use horust_commands_lib::{ClientHandler, CommandsHandlerTrait, HorustMsgServiceStatus};
+use horust_commands_lib::{
+    ClientHandler, CommandsHandlerTrait, HorustMsgHealthCheckResult, HorustMsgServiceStatus,
+};
 use log::info;
 use std::path::PathBuf;
 use std::sync::{Arc, Barrier};
@@ -31,6 +33,35 @@ impl CommandsHandlerTrait for MockCommandsHandler {
         })
     }
 
+    fn get_health_check_results(
+        &self,
+        service_name: &str,
+    ) -> Result<Vec<HorustMsgHealthCheckResult>> {
+        Ok(vec![HorustMsgHealthCheckResult {
+            command: format!("check {service_name}"),
+            exit_code: Some(1),
+            output: "not yet".into(),
+        }])
+    }
+
+    fn is_service_degraded(&self, service_name: &str) -> Result<bool> {
+        Ok(service_name == "Started")
+    }
+
+    fn was_service_oom_killed(&self, service_name: &str) -> Result<bool> {
+        Ok(service_name == "Started")
+    }
+
+    fn get_service_logs(
+        &self,
+        service_name: &str,
+        tail: u32,
+        offset: Option<u64>,
+    ) -> Result<(Vec<u8>, u64)> {
+        let content = format!("{service_name}: tail {tail}, offset {offset:?}\n");
+        Ok((content.into_bytes(), offset.unwrap_or(0) + 1))
+    }
+
     fn update_service_status(
         &self,
         _service_name: &str,
@@ -60,6 +91,9 @@ fn test_simple() -> Result<()> {
         barrier_server.wait();
         uds.accept().unwrap();
         uds.accept().unwrap();
+        uds.accept().unwrap();
+        uds.accept().unwrap();
+        uds.accept().unwrap();
     });
 
     let c_handle = thread::spawn(move || {
@@ -69,6 +103,31 @@ fn test_simple() -> Result<()> {
 
         let client = ClientHandler::new_client(&socket_path).unwrap();
         client.client("Started".into()).unwrap();
+
+        let mut client = ClientHandler::new_client(&socket_path).unwrap();
+        let response = client
+            .send_status_details_request("Started".into())
+            .unwrap();
+        assert_eq!(response.service_name, "Started");
+        assert_eq!(response.service_status(), HorustMsgServiceStatus::Started);
+        assert_eq!(response.health_checks[0].command, "check Started");
+        assert_eq!(response.health_checks[0].exit_code, Some(1));
+        assert!(response.degraded);
+        assert!(response.oom_killed);
+
+        let mut client = ClientHandler::new_client(&socket_path).unwrap();
+        let (content, next_offset) = client
+            .send_logs_request("Running".into(), 10, None)
+            .unwrap();
+        assert_eq!(content, b"Running: tail 10, offset None\n");
+        assert_eq!(next_offset, 1);
+
+        let mut client = ClientHandler::new_client(&socket_path).unwrap();
+        let (content, next_offset) = client
+            .send_logs_request("Running".into(), 0, Some(next_offset))
+            .unwrap();
+        assert_eq!(content, b"Running: tail 0, offset Some(1)\n");
+        assert_eq!(next_offset, 2);
     });
     s_handle.join().unwrap();
     c_handle.join().unwrap();
@@ -1,9 +1,12 @@
 use crate::horust::Event;
 use crate::horust::bus::BusConnector;
-use crate::horust::formats::{ServiceName, ServiceStatus};
+use crate::horust::formats::{CommandCheckResult, ExitReason, ServiceName, ServiceStatus};
+use crate::horust::log_buffer;
 use anyhow::{Result, anyhow};
-use horust_commands_lib::{CommandsHandlerTrait, HorustMsgServiceStatus};
-use std::collections::HashMap;
+use horust_commands_lib::{
+    CommandsHandlerTrait, HorustMsgHealthCheckResult, HorustMsgServiceStatus,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::os::unix::net::UnixListener;
 use std::path::PathBuf;
 use std::thread::JoinHandle;
@@ -24,6 +27,12 @@ pub fn spawn(
 struct CommandsHandler {
     bus: BusConnector<Event>,
     services: HashMap<ServiceName, ServiceStatus>,
+    /// Services marked unhealthy, but still running.
+    degraded: HashSet<ServiceName>,
+    /// Services whose last run was OOM-killed.
+    oom_killed: HashSet<ServiceName>,
+    /// The last result of each command check of the services.
+    command_checks: HashMap<ServiceName, BTreeMap<String, CommandCheckResult>>,
     uds_listener: UnixListener,
     uds_path: PathBuf,
 }
@@ -36,6 +45,9 @@ impl CommandsHandler {
             bus,
             uds_path,
             uds_listener,
+            degraded: HashSet::new(),
+            oom_killed: HashSet::new(),
+            command_checks: HashMap::new(),
             services: services
                 .into_iter()
                 .map(|s| (s, ServiceStatus::Initial))
@@ -48,9 +60,30 @@ impl CommandsHandler {
             for ev in evs {
                 match ev {
                     Event::StatusChanged(name, status) => {
+                        if status == ServiceStatus::Started {
+                            self.degraded.remove(&name);
+                        }
                         let k = self.services.get_mut(&name).unwrap();
                         *k = status;
                     }
+                    Event::Degraded(name, true) => {
+                        self.degraded.insert(name);
+                    }
+                    Event::Degraded(name, false) => {
+                        self.degraded.remove(&name);
+                    }
+                    Event::ServiceExited(name, _, Some(ExitReason::OomKilled)) => {
+                        self.oom_killed.insert(name);
+                    }
+                    Event::ServiceExited(name, _, None) => {
+                        self.oom_killed.remove(&name);
+                    }
+                    Event::CommandChecked(name, result) => {
+                        self.command_checks
+                            .entry(name)
+                            .or_default()
+                            .insert(result.command.clone(), result);
+                    }
                     Event::ShuttingDownInitiated(_) => {
                         fs::remove_file(&self.uds_path).unwrap();
                         return;
@@ -74,6 +107,46 @@ impl CommandsHandlerTrait for CommandsHandler {
             .map(from_service_status)
             .ok_or_else(|| anyhow!("Error: service {service_name} not found."))
     }
+    fn get_health_check_results(
+        &self,
+        service_name: &str,
+    ) -> Result<Vec<HorustMsgHealthCheckResult>> {
+        Ok(self
+            .command_checks
+            .get(service_name)
+            .into_iter()
+            .flat_map(BTreeMap::values)
+            .map(|result| HorustMsgHealthCheckResult {
+                command: result.command.clone(),
+                exit_code: result.exit_code,
+                output: result.output.clone(),
+            })
+            .collect())
+    }
+    fn is_service_degraded(&self, service_name: &str) -> Result<bool> {
+        Ok(self.degraded.contains(service_name))
+    }
+    fn was_service_oom_killed(&self, service_name: &str) -> Result<bool> {
+        Ok(self.oom_killed.contains(service_name))
+    }
+    fn get_service_logs(
+        &self,
+        service_name: &str,
+        tail: u32,
+        offset: Option<u64>,
+    ) -> Result<(Vec<u8>, u64)> {
+        if !self.services.contains_key(service_name) {
+            return Err(anyhow!("Error: service {service_name} not found."));
+        }
+        let log_buffer = log_buffer::get(service_name).ok_or_else(|| {
+            anyhow!("Error: no output buffered for {service_name}, is `log-buffer-size` set?")
+        })?;
+        let log_buffer = log_buffer.lock().unwrap();
+        Ok(match offset {
+            Some(offset) => log_buffer.read_from(offset),
+            None => log_buffer.tail(tail as usize),
+        })
+    }
     fn update_service_status(
         &self,
         _service_name: &str,
@@ -28,4 +28,24 @@ pub enum ValidationError {
     MissingShutdownDependency { before: String, after: String },
     #[error("Command is defined, but it is empty for service: {service}")]
     CommandEmpty { service: String },
+    #[error("Service '{service}' has on-failure = 'run-command', but no on-failure-command.")]
+    OnFailureCommandMissing { service: String },
+    #[error(
+        "Service '{service}' has {capability} in both capabilities.ambient and capabilities.drop."
+    )]
+    AmbientCapabilityDropped { service: String, capability: String },
+    #[error("Service '{service}' sets namespaces.hostname, but not namespaces.uts.")]
+    HostnameWithoutUtsNamespace { service: String },
+    #[error(
+        "Service '{service}' has supplementary-groups, which can't be set in its user namespace."
+    )]
+    SupplementaryGroupsInUserNamespace { service: String },
+    #[error("Service '{service}' has {field} = {value}, but it must be between {min} and {max}.")]
+    OutOfRange {
+        service: String,
+        field: String,
+        value: i64,
+        min: i64,
+        max: i64,
+    },
 }
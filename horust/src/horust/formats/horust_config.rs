@@ -1,4 +1,5 @@
 use std::path::Path;
+use std::time::Duration;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
@@ -9,8 +10,47 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, clap::Parser, Serialize, Deserialize, Default)]
 pub struct HorustConfig {
     #[clap(long)]
+    #[serde(default)]
     /// Exits with an unsuccessful exit code if any process is in FinishedFailed state
     pub unsuccessful_exit_finished_failed: bool,
+    #[clap(long, value_enum)]
+    #[serde(default, alias = "log-format")]
+    /// Format of horust's own logs [default: text]
+    pub log_format: Option<LogFormat>,
+    #[clap(long, value_parser = humantime::parse_duration)]
+    #[serde(default, with = "humantime_serde", alias = "healthcheck-interval")]
+    /// Default time between two health checks, for services not setting `interval` [default: 1s]
+    pub healthcheck_interval: Option<Duration>,
+    #[clap(long, value_parser = humantime::parse_duration)]
+    #[serde(default, with = "humantime_serde", alias = "healthcheck-timeout")]
+    /// Default time after which a health check is considered failed [default: 1s]
+    pub healthcheck_timeout: Option<Duration>,
+    #[clap(long, value_parser = humantime::parse_duration)]
+    #[serde(default, with = "humantime_serde", alias = "healthcheck-start-period")]
+    /// Default grace period after a service started, during which failed health checks don't count [default: 0s]
+    pub healthcheck_start_period: Option<Duration>,
+    #[clap(long)]
+    #[serde(default, alias = "healthcheck-success-threshold")]
+    /// Default amount of successful health checks in a row needed to consider a service healthy [default: 1]
+    pub healthcheck_success_threshold: Option<u32>,
+    #[clap(long)]
+    #[serde(default, alias = "healthcheck-concurrency")]
+    /// Maximum amount of health checks running at the same time, across all the services [default: 8]
+    pub healthcheck_concurrency: Option<usize>,
+    #[clap(long)]
+    #[serde(default, alias = "systemd-cgroup")]
+    /// Let systemd create the cgroups of the services, as transient scopes. Requires cgroup v2
+    pub systemd_cgroup: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, with structured fields like `service` and `new_status`.
+    Json,
 }
 
 impl HorustConfig {
@@ -26,19 +66,41 @@ impl HorustConfig {
 
         let unsuccessful_exit_finished_failed = cmd_line.unsuccessful_exit_finished_failed
             || config_file.unsuccessful_exit_finished_failed;
+        let systemd_cgroup = cmd_line.systemd_cgroup || config_file.systemd_cgroup;
 
         Ok(HorustConfig {
             unsuccessful_exit_finished_failed,
+            log_format: cmd_line.log_format.or(config_file.log_format),
+            healthcheck_interval: cmd_line
+                .healthcheck_interval
+                .or(config_file.healthcheck_interval),
+            healthcheck_timeout: cmd_line
+                .healthcheck_timeout
+                .or(config_file.healthcheck_timeout),
+            healthcheck_start_period: cmd_line
+                .healthcheck_start_period
+                .or(config_file.healthcheck_start_period),
+            healthcheck_success_threshold: cmd_line
+                .healthcheck_success_threshold
+                .or(config_file.healthcheck_success_threshold),
+            healthcheck_concurrency: cmd_line
+                .healthcheck_concurrency
+                .or(config_file.healthcheck_concurrency),
+            systemd_cgroup,
         })
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::time::Duration;
+
     use anyhow::Result;
     use tempfile::TempDir;
 
     use crate::horust::HorustConfig;
+    use crate::horust::formats::LogFormat;
+
     #[test]
     fn test_load_and_merge() -> Result<()> {
         let tempdir = TempDir::with_prefix("load-and-merge")?;
@@ -46,8 +108,55 @@ mod test {
         std::fs::write(&config_path, "Not a toml file :( ")?;
         let config = HorustConfig {
             unsuccessful_exit_finished_failed: true,
+            ..Default::default()
         };
         HorustConfig::load_and_merge(&config, &config_path).unwrap_err();
         Ok(())
     }
+
+    #[test]
+    fn test_load_and_merge_log_format() -> Result<()> {
+        let tempdir = TempDir::with_prefix("load-and-merge")?;
+        let config_path = tempdir.path().join("config.toml");
+        std::fs::write(&config_path, r#"log-format = "json""#)?;
+        let from_file = HorustConfig::load_and_merge(&HorustConfig::default(), &config_path)?;
+        assert_eq!(from_file.log_format, Some(LogFormat::Json));
+        let cmd_line = HorustConfig {
+            log_format: Some(LogFormat::Text),
+            ..Default::default()
+        };
+        let merged = HorustConfig::load_and_merge(&cmd_line, &config_path)?;
+        assert_eq!(merged.log_format, Some(LogFormat::Text));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_and_merge_healthcheck_defaults() -> Result<()> {
+        let tempdir = TempDir::with_prefix("load-and-merge")?;
+        let config_path = tempdir.path().join("config.toml");
+        std::fs::write(
+            &config_path,
+            r#"
+healthcheck-interval = "10s"
+healthcheck-timeout = "3s"
+healthcheck-success-threshold = 2
+healthcheck-concurrency = 16
+"#,
+        )?;
+        let cmd_line = HorustConfig {
+            healthcheck_interval: Some(Duration::from_secs(5)),
+            healthcheck_start_period: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        let merged = HorustConfig::load_and_merge(&cmd_line, &config_path)?;
+        assert_eq!(merged.healthcheck_interval, Some(Duration::from_secs(5)));
+        assert_eq!(merged.healthcheck_timeout, Some(Duration::from_secs(3)));
+        assert_eq!(
+            merged.healthcheck_start_period,
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(merged.healthcheck_success_threshold, Some(2));
+        assert_eq!(merged.healthcheck_concurrency, Some(16));
+        Ok(())
+    }
 }
@@ -1,6 +1,6 @@
 use nix::unistd::Pid;
 
-pub use horust_config::HorustConfig;
+pub use horust_config::{HorustConfig, LogFormat};
 pub use service::*;
 
 mod horust_config;
@@ -14,23 +14,37 @@ pub enum ShuttingDown {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Event {
-    PidChanged(ServiceName, Pid),
+    /// The service has been spawned, along with the OOM kills counter of its cgroup before it ran,
+    /// if it has one.
+    PidChanged(ServiceName, Pid, Option<u64>),
     // This command updates the service status.
     StatusUpdate(ServiceName, ServiceStatus),
     // This event represents a status change.
     StatusChanged(ServiceName, ServiceStatus),
-    ServiceExited(ServiceName, i32),
+    ServiceExited(ServiceName, i32, Option<ExitReason>),
     ForceKill(ServiceName),
     Kill(ServiceName),
     SpawnFailed(ServiceName),
     Run(ServiceName),
     ShuttingDownInitiated(ShuttingDown),
     HealthCheck(ServiceName, HealthinessStatus),
+    ReadinessCheck(ServiceName, HealthinessStatus),
+    /// The service has been marked unhealthy (`on-failure = "mark-unhealthy"`), or has recovered.
+    Degraded(ServiceName, bool),
+    /// A command check of the service has run.
+    CommandChecked(ServiceName, CommandCheckResult),
+    /// The service is unhealthy (`on-failure = "run-command"`): its `on-failure-command` is run,
+    /// then it is killed.
+    RunOnFailureCommand(ServiceName),
 }
 
 impl Event {
-    pub(crate) fn new_pid_changed(service_name: ServiceName, pid: Pid) -> Self {
-        Self::PidChanged(service_name, pid)
+    pub(crate) fn new_pid_changed(
+        service_name: ServiceName,
+        pid: Pid,
+        oom_kills: Option<u64>,
+    ) -> Self {
+        Self::PidChanged(service_name, pid, oom_kills)
     }
     pub(crate) fn new_status_changed(service_name: &str, status: ServiceStatus) -> Self {
         Self::StatusChanged(service_name.to_string(), status)
@@ -38,12 +52,42 @@ impl Event {
     pub fn new_status_update(service_name: &str, status: ServiceStatus) -> Self {
         Self::StatusUpdate(service_name.to_string(), status)
     }
-    pub(crate) fn new_service_exited(service_name: ServiceName, exit_status: i32) -> Self {
-        Self::ServiceExited(service_name, exit_status)
+    pub(crate) fn new_service_exited(
+        service_name: ServiceName,
+        exit_status: i32,
+        reason: Option<ExitReason>,
+    ) -> Self {
+        Self::ServiceExited(service_name, exit_status, reason)
     }
     pub(crate) fn new_force_kill(service_name: &str) -> Self {
         Self::ForceKill(service_name.to_string())
     }
+    /// The service this event is about, if any.
+    pub(crate) fn service_name(&self) -> Option<&ServiceName> {
+        match self {
+            Self::PidChanged(service_name, ..)
+            | Self::StatusUpdate(service_name, _)
+            | Self::StatusChanged(service_name, _)
+            | Self::ServiceExited(service_name, ..)
+            | Self::ForceKill(service_name)
+            | Self::Kill(service_name)
+            | Self::SpawnFailed(service_name)
+            | Self::Run(service_name)
+            | Self::HealthCheck(service_name, _)
+            | Self::ReadinessCheck(service_name, _)
+            | Self::Degraded(service_name, _)
+            | Self::CommandChecked(service_name, _)
+            | Self::RunOnFailureCommand(service_name) => Some(service_name),
+            Self::ShuttingDownInitiated(_) => None,
+        }
+    }
+}
+
+/// Why a service has exited, when its exit code doesn't tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// Killed by the kernel, having run out of memory in its cgroup.
+    OomKilled,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -52,6 +96,16 @@ pub enum ExitStatus {
     SomeServiceFailed,
 }
 
+/// Outcome of a run of a command check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommandCheckResult {
+    pub command: String,
+    /// None if the command couldn't be run, timed out or was killed by a signal.
+    pub exit_code: Option<i32>,
+    /// The end of stdout followed by the end of stderr, or why the command couldn't be run.
+    pub output: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum HealthinessStatus {
     Healthy,
@@ -1,10 +1,10 @@
 use anyhow::{Context, Error, Result};
 use nix::sys::signal::Signal;
 use nix::unistd;
-use serde::de::{self, Visitor};
+use serde::de::{self, IntoDeserializer, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::collections::HashMap;
-use std::ffi::OsStr;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::{CString, OsStr};
 use std::fmt::{Debug, Formatter};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
@@ -12,6 +12,7 @@ use std::time::Duration;
 use std::{env, os::fd::RawFd};
 
 use crate::horust::error::{ValidationError, ValidationErrors};
+use crate::horust::formats::HorustConfig;
 
 pub fn get_sample_service() -> &'static str {
     include_str!("../../../example_services/sample_service.toml")
@@ -19,7 +20,7 @@ pub fn get_sample_service() -> &'static str {
 
 pub type ServiceName = String;
 
-#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Service {
     #[serde(default)]
@@ -28,6 +29,27 @@ pub struct Service {
     pub command: String,
     #[serde(default)]
     pub user: User,
+    /// Primary group. Defaults to the primary group of `user`.
+    pub group: Option<Group>,
+    /// Added to the groups `user` is a member of.
+    #[serde(default)]
+    pub supplementary_groups: Vec<Group>,
+    #[serde(default)]
+    pub capabilities: Capabilities,
+    /// Neither the service nor its children can gain privileges, e.g. through setuid binaries.
+    #[serde(default = "default_as_false")]
+    pub no_new_privileges: bool,
+    /// Niceness, from -20 (highest priority) to 19. Inherited from horust if unset.
+    pub nice: Option<i32>,
+    pub io_scheduling: Option<IoScheduling>,
+    /// Added to the badness score of the OOM killer, from -1000 (never killed) to 1000.
+    pub oom_score_adjust: Option<i32>,
+    /// The CPUs the service can run on.
+    pub cpu_affinity: Option<CpuList>,
+    /// File mode creation mask. Inherited from horust if unset.
+    pub umask: Option<Umask>,
+    /// Filters the syscalls of the service. Implies `no-new-privileges`.
+    pub seccomp: Option<Seccomp>,
     #[serde(default = "Service::default_working_directory")]
     pub working_directory: PathBuf,
     #[serde(default = "Service::default_stdout_log")]
@@ -39,6 +61,9 @@ pub struct Service {
     pub stdout_should_append_timestamp_to_filename: bool,
     #[serde(default = "Service::default_stderr_log")]
     pub stderr: LogOutput,
+    /// Keep the last `log_buffer_size` bytes of output in memory, for `horustctl logs`.
+    #[serde(default, skip_serializing, deserialize_with = "str_to_bytes")]
+    pub log_buffer_size: u64,
     #[serde(default, with = "humantime_serde")]
     pub start_delay: Duration,
     #[serde(default)]
@@ -49,8 +74,12 @@ pub struct Service {
     pub signal_rewrite: Option<String>,
     #[serde(default)]
     pub restart: Restart,
-    #[serde(default)]
+    /// Liveness checks: failing them kills the service.
+    #[serde(default, alias = "liveness")]
     pub healthiness: Healthiness,
+    /// Readiness checks: failing them only holds back the services starting after this one.
+    #[serde(default)]
+    pub readiness: Healthiness,
     #[serde(default)]
     pub failure: Failure,
     #[serde(default)]
@@ -59,6 +88,13 @@ pub struct Service {
     pub termination: Termination,
     #[serde(default)]
     pub resource_limit: ResourceLimit,
+    /// Limits set with `setrlimit`, by resource.
+    #[serde(default)]
+    pub rlimits: BTreeMap<RlimitResource, Rlimit>,
+    #[serde(default)]
+    pub sandbox: Sandbox,
+    #[serde(default)]
+    pub namespaces: Namespaces,
 }
 
 fn default_as_false() -> bool {
@@ -99,6 +135,28 @@ impl Service {
         ))
     }
 
+    /// The primary group of the service: `group`, or the primary group of `user`.
+    pub(crate) fn get_gid(&self) -> Result<unistd::Gid> {
+        match &self.group {
+            Some(group) => group.get_gid(),
+            None => Ok(self.user.get_raw_user()?.gid),
+        }
+    }
+
+    /// The groups of the service, as set by `initgroups`: the primary group and the groups `user`
+    /// is a member of in /etc/group, plus `supplementary-groups`.
+    pub(crate) fn get_groups(&self, gid: unistd::Gid) -> Result<Vec<unistd::Gid>> {
+        let name = CString::new(self.user.get_name()?)?;
+        let mut groups = unistd::getgrouplist(&name, gid)?;
+        for group in &self.supplementary_groups {
+            let gid = group.get_gid()?;
+            if !groups.contains(&gid) {
+                groups.push(gid);
+            }
+        }
+        Ok(groups)
+    }
+
     /// Wrapper for single command horust run
     pub fn from_command(command: String) -> Self {
         Service {
@@ -120,16 +178,31 @@ impl Default for Service {
             stdout_rotate_size: 0,
             stdout_should_append_timestamp_to_filename: Default::default(),
             stderr: Default::default(),
+            log_buffer_size: 0,
             user: Default::default(),
+            group: None,
+            supplementary_groups: Vec::new(),
+            capabilities: Default::default(),
+            no_new_privileges: false,
+            nice: None,
+            io_scheduling: None,
+            oom_score_adjust: None,
+            cpu_affinity: None,
+            umask: None,
+            seccomp: None,
             restart: Default::default(),
             start_delay: Duration::from_secs(0),
             command: "command".to_string(),
             healthiness: Default::default(),
+            readiness: Default::default(),
             signal_rewrite: None,
             environment: Default::default(),
             failure: Default::default(),
             termination: Default::default(),
             resource_limit: Default::default(),
+            rlimits: BTreeMap::new(),
+            sandbox: Default::default(),
+            namespaces: Default::default(),
         }
     }
 }
@@ -150,6 +223,17 @@ pub enum LogOutput {
     Stdout,
     Path(PathBuf),
     Pipe(RawFd),
+    /// RFC5424 messages, one per line of output.
+    Syslog(LogEndpoint),
+    /// One JSON object per line of output, e.g. for fluent-bit or Vector.
+    JsonLines(LogEndpoint),
+}
+
+impl LogOutput {
+    /// Whether horust needs to read the output to send it somewhere.
+    pub fn is_forwarded(&self) -> bool {
+        matches!(self, LogOutput::Syslog(_) | LogOutput::JsonLines(_))
+    }
 }
 
 impl Serialize for LogOutput {
@@ -177,13 +261,20 @@ impl Visitor<'_> for LogOutputVisitor {
     type Value = LogOutput;
 
     fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
-        formatter.write_str("a string with 'STDOUT', 'STDERR', or a full path. All as `String`s ")
+        formatter.write_str(
+            "a string with 'STDOUT', 'STDERR', 'SYSLOG', a forwarding URL like 'json+tcp://host:port', or a full path. All as `String`s ",
+        )
     }
     fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
     where
         E: de::Error,
     {
-        Ok(LogOutput::from(value))
+        match LogOutput::from(value) {
+            LogOutput::Path(_) if value.contains("://") => Err(E::custom(format!(
+                "unsupported log output: '{value}', expected one of syslog+udp://, syslog+tcp://, syslog+unix://, json+udp:// or json+tcp://"
+            ))),
+            output => Ok(output),
+        }
     }
 }
 
@@ -204,6 +295,8 @@ impl From<LogOutput> for String {
                 path.to_string()
             }
             Pipe(fd) => format!("{fd}"),
+            Syslog(endpoint) => format!("syslog+{endpoint}"),
+            JsonLines(endpoint) => format!("json+{endpoint}"),
         }
     }
 }
@@ -213,7 +306,57 @@ impl From<&str> for LogOutput {
         match strategy {
             "STDOUT" => LogOutput::Stdout,
             "STDERR" => LogOutput::Stderr,
-            path => LogOutput::Path(PathBuf::from(path)),
+            "SYSLOG" => LogOutput::Syslog(LogEndpoint::Unix(PathBuf::from(DEFAULT_SYSLOG_SOCKET))),
+            output => {
+                let forwarder = output.split_once("://").and_then(|(scheme, address)| {
+                    let (format, transport) = scheme.split_once('+')?;
+                    let endpoint = LogEndpoint::new(transport, address)?;
+                    match format {
+                        "syslog" => Some(LogOutput::Syslog(endpoint)),
+                        "json" => Some(LogOutput::JsonLines(endpoint)),
+                        _ => None,
+                    }
+                });
+                forwarder.unwrap_or_else(|| LogOutput::Path(PathBuf::from(output)))
+            }
+        }
+    }
+}
+
+/// Socket of the local syslog daemon, used by `stdout = "SYSLOG"`.
+pub const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Where forwarded output is sent.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LogEndpoint {
+    /// A unix datagram socket, like `/dev/log`.
+    Unix(PathBuf),
+    /// A `host:port` address.
+    Udp(String),
+    /// A `host:port` address.
+    Tcp(String),
+}
+
+impl LogEndpoint {
+    fn new(transport: &str, address: &str) -> Option<Self> {
+        if address.is_empty() {
+            return None;
+        }
+        match transport {
+            "unix" => Some(LogEndpoint::Unix(PathBuf::from(address))),
+            "udp" => Some(LogEndpoint::Udp(address.to_string())),
+            "tcp" => Some(LogEndpoint::Tcp(address.to_string())),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for LogEndpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogEndpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+            LogEndpoint::Udp(address) => write!(f, "udp://{address}"),
+            LogEndpoint::Tcp(address) => write!(f, "tcp://{address}"),
         }
     }
 }
@@ -303,24 +446,109 @@ impl Environment {
     }
 }
 
-#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Healthiness {
     pub http_endpoint: Option<String>,
+    /// How `http_endpoint` is checked.
+    #[serde(default)]
+    pub http: HttpHealthiness,
+    /// Endpoint implementing the gRPC Health Checking Protocol.
+    pub grpc_endpoint: Option<String>,
+    /// Service whose health is checked by `grpc_endpoint`. The server's overall health if unset.
+    pub grpc_service: Option<String>,
     pub file_path: Option<PathBuf>,
     pub command: Option<String>,
+    /// Environment variables added to the service's ones when running `command`.
+    #[serde(default)]
+    pub command_env: BTreeMap<String, String>,
+    pub tcp_endpoint: Option<String>,
+    pub unix_socket: Option<PathBuf>,
+    /// Regex matched against each line of the service's output.
+    pub ready_pattern: Option<String>,
+    /// File the service has to touch periodically.
+    pub heartbeat_file: Option<PathBuf>,
+    /// Maximum age of `heartbeat_file`'s last modification. Defaults to 30 seconds.
+    #[serde(default, with = "humantime_serde")]
+    pub max_age: Option<Duration>,
+    /// Soft limits on the resources used by the service's process.
+    #[serde(default)]
+    pub resources: ResourceHealthiness,
+    /// Parameters of the checks registered by the application embedding horust, by name.
+    #[serde(default)]
+    pub custom: BTreeMap<String, toml::Table>,
     #[serde(default = "Healthiness::default_max_failed")]
     // todo: use an u32
     pub max_failed: i32,
+    /// What to do once more than `max_failed` checks have failed.
+    #[serde(default)]
+    pub on_failure: HealthinessFailureAction,
+    /// The command run by `on-failure = "run-command"`.
+    pub on_failure_command: Option<String>,
+    /// Time between two checks. If unset, horust's `healthcheck-interval` is used.
+    #[serde(default, with = "humantime_serde")]
+    pub interval: Option<Duration>,
+    /// Time after which a check is considered failed. If unset, horust's `healthcheck-timeout` is used.
+    #[serde(default, with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+    /// Failed checks don't count during this period, unless a check succeeded already.
+    #[serde(default, with = "humantime_serde")]
+    pub start_period: Option<Duration>,
+    /// Successful checks in a row needed before the service is considered healthy.
+    #[serde(default)]
+    pub success_threshold: Option<u32>,
 }
 
+const DEFAULT_HEALTHCHECK_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_HEALTHCHECK_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_HEARTBEAT_MAX_AGE: Duration = Duration::from_secs(30);
+
 impl Healthiness {
     fn default_max_failed() -> i32 {
         3
     }
 
+    pub fn interval(&self) -> Duration {
+        self.interval.unwrap_or(DEFAULT_HEALTHCHECK_INTERVAL)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout.unwrap_or(DEFAULT_HEALTHCHECK_TIMEOUT)
+    }
+
+    pub fn start_period(&self) -> Duration {
+        self.start_period.unwrap_or_default()
+    }
+
+    pub fn max_age(&self) -> Duration {
+        self.max_age.unwrap_or(DEFAULT_HEARTBEAT_MAX_AGE)
+    }
+
+    pub fn success_threshold(&self) -> u32 {
+        self.success_threshold.unwrap_or(1).max(1)
+    }
+
+    /// Fills the timing settings that are not set with horust's defaults.
+    pub(crate) fn apply_defaults(&mut self, config: &HorustConfig) {
+        self.interval = self.interval.or(config.healthcheck_interval);
+        self.timeout = self.timeout.or(config.healthcheck_timeout);
+        self.start_period = self.start_period.or(config.healthcheck_start_period);
+        self.success_threshold = self
+            .success_threshold
+            .or(config.healthcheck_success_threshold);
+    }
+
     pub(crate) fn has_any_check_defined(&self) -> bool {
-        self.http_endpoint.is_some() || self.file_path.is_some() || self.command.is_some()
+        self.http_endpoint.is_some()
+            || self.grpc_endpoint.is_some()
+            || self.file_path.is_some()
+            || self.command.is_some()
+            || self.tcp_endpoint.is_some()
+            || self.unix_socket.is_some()
+            || self.ready_pattern.is_some()
+            || self.heartbeat_file.is_some()
+            || self.resources.has_any_limit()
+            || !self.custom.is_empty()
     }
 }
 
@@ -328,9 +556,239 @@ impl Default for Healthiness {
     fn default() -> Self {
         Self {
             http_endpoint: None,
+            http: Default::default(),
+            grpc_endpoint: None,
+            grpc_service: None,
             file_path: None,
             command: None,
+            command_env: BTreeMap::new(),
+            tcp_endpoint: None,
+            unix_socket: None,
+            ready_pattern: None,
+            heartbeat_file: None,
+            max_age: None,
+            resources: Default::default(),
+            custom: BTreeMap::new(),
             max_failed: 3,
+            on_failure: Default::default(),
+            on_failure_command: None,
+            interval: None,
+            timeout: None,
+            start_period: None,
+            success_threshold: None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq, Hash)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct HttpHealthiness {
+    /// Defaults to HEAD, or GET if the body has to be checked.
+    pub method: Option<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Accepted status codes. If empty, any 2xx is accepted.
+    #[serde(default)]
+    pub status: Vec<StatusRange>,
+    pub body_contains: Option<String>,
+    pub body_regex: Option<String>,
+    #[serde(default = "HttpHealthiness::default_tls_verify")]
+    pub tls_verify: bool,
+    #[serde(default = "HttpHealthiness::default_tls_verify")]
+    pub tls_verify_hostname: bool,
+    /// Additional root certificate (PEM) to trust, e.g. the one of a private CA.
+    pub tls_ca_cert: Option<PathBuf>,
+}
+
+impl HttpHealthiness {
+    fn default_tls_verify() -> bool {
+        true
+    }
+
+    pub fn method(&self) -> &str {
+        match &self.method {
+            Some(method) => method,
+            None if self.checks_body() => "GET",
+            None => "HEAD",
+        }
+    }
+
+    pub fn checks_body(&self) -> bool {
+        self.body_contains.is_some() || self.body_regex.is_some()
+    }
+
+    pub fn accepts_status(&self, status: u16) -> bool {
+        if self.status.is_empty() {
+            (200..300).contains(&status)
+        } else {
+            self.status.iter().any(|range| range.contains(status))
+        }
+    }
+
+    /// Whether the default TLS settings have been changed.
+    pub fn has_tls_options(&self) -> bool {
+        !self.tls_verify || !self.tls_verify_hostname || self.tls_ca_cert.is_some()
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, Default, Eq, PartialEq, Hash)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ResourceHealthiness {
+    /// Maximum resident memory, e.g. "512MiB".
+    #[serde(default, skip_serializing, deserialize_with = "str_to_optional_bytes")]
+    pub max_rss: Option<u64>,
+    /// Maximum amount of open file descriptors.
+    pub max_fds: Option<u64>,
+    /// Maximum amount of threads.
+    pub max_threads: Option<u64>,
+    /// Maximum CPU usage between two checks, in percent of one core.
+    pub max_cpu: Option<u32>,
+    /// How long a limit has to be exceeded before the check fails.
+    #[serde(default, with = "humantime_serde")]
+    pub sustained_for: Option<Duration>,
+}
+
+impl ResourceHealthiness {
+    pub fn has_any_limit(&self) -> bool {
+        self.max_rss.is_some()
+            || self.max_fds.is_some()
+            || self.max_threads.is_some()
+            || self.max_cpu.is_some()
+    }
+
+    pub fn sustained_for(&self) -> Duration {
+        self.sustained_for.unwrap_or_default()
+    }
+}
+
+impl Default for HttpHealthiness {
+    fn default() -> Self {
+        Self {
+            method: None,
+            headers: BTreeMap::new(),
+            status: vec![],
+            body_contains: None,
+            body_regex: None,
+            tls_verify: true,
+            tls_verify_hostname: true,
+            tls_ca_cert: None,
+        }
+    }
+}
+
+/// What happens to a service once its liveness checks have failed more than `max-failed` times.
+#[derive(Serialize, Clone, Copy, Deserialize, Debug, Default, Eq, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum HealthinessFailureAction {
+    /// Kill the service, then apply its restart strategy.
+    #[default]
+    Restart,
+    /// Kill the service, and don't restart it.
+    Stop,
+    /// Keep the service running, but report it as degraded until a check succeeds.
+    MarkUnhealthy,
+    /// Run `on-failure-command` in the service's context, then kill the service as `Restart` does.
+    RunCommand,
+    /// Send a signal to the service, e.g. to make it dump its state or reload.
+    Signal(TerminationSignal),
+}
+
+impl HealthinessFailureAction {
+    /// Whether the service is stopped by this action.
+    pub fn kills(&self) -> bool {
+        matches!(self, Self::Restart | Self::Stop | Self::RunCommand)
+    }
+}
+
+impl TryFrom<String> for HealthinessFailureAction {
+    type Error = String;
+
+    fn try_from(action: String) -> Result<Self, Self::Error> {
+        match action.as_str() {
+            "restart" => Ok(Self::Restart),
+            "stop" => Ok(Self::Stop),
+            "mark-unhealthy" => Ok(Self::MarkUnhealthy),
+            "run-command" => Ok(Self::RunCommand),
+            _ => {
+                let invalid = || {
+                    format!(
+                        "invalid on-failure action: '{action}', expected one of 'restart', 'stop', \
+                         'mark-unhealthy', 'run-command' or 'signal:<SIG>'"
+                    )
+                };
+                let signal = action.strip_prefix("signal:").ok_or_else(invalid)?;
+                let signal = signal.strip_prefix("SIG").unwrap_or(signal);
+                TerminationSignal::deserialize(signal.into_deserializer())
+                    .map(Self::Signal)
+                    .map_err(|_: de::value::Error| invalid())
+            }
+        }
+    }
+}
+
+impl From<HealthinessFailureAction> for String {
+    fn from(action: HealthinessFailureAction) -> Self {
+        match action {
+            HealthinessFailureAction::Restart => "restart".into(),
+            HealthinessFailureAction::Stop => "stop".into(),
+            HealthinessFailureAction::MarkUnhealthy => "mark-unhealthy".into(),
+            HealthinessFailureAction::RunCommand => "run-command".into(),
+            HealthinessFailureAction::Signal(signal) => format!("signal:{signal:?}"),
+        }
+    }
+}
+
+/// A status code (`200`) or an inclusive range of status codes (`"200-299"`).
+#[derive(Serialize, Clone, Copy, Deserialize, Debug, Eq, PartialEq, Hash)]
+#[serde(try_from = "StatusRangeRepr", into = "StatusRangeRepr")]
+pub struct StatusRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl StatusRange {
+    pub fn contains(&self, status: u16) -> bool {
+        (self.start..=self.end).contains(&status)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StatusRangeRepr {
+    Code(u16),
+    Range(String),
+}
+
+impl TryFrom<StatusRangeRepr> for StatusRange {
+    type Error = String;
+
+    fn try_from(repr: StatusRangeRepr) -> Result<Self, Self::Error> {
+        match repr {
+            StatusRangeRepr::Code(code) => Ok(StatusRange {
+                start: code,
+                end: code,
+            }),
+            StatusRangeRepr::Range(range) => {
+                let invalid =
+                    || format!("invalid status range: '{range}', expected e.g. '200-299'");
+                let (start, end) = range.split_once('-').unwrap_or((&range, &range));
+                let start = start.trim().parse().map_err(|_| invalid())?;
+                let end = end.trim().parse().map_err(|_| invalid())?;
+                if start > end {
+                    return Err(invalid());
+                }
+                Ok(StatusRange { start, end })
+            }
+        }
+    }
+}
+
+impl From<StatusRange> for StatusRangeRepr {
+    fn from(range: StatusRange) -> Self {
+        if range.start == range.end {
+            StatusRangeRepr::Code(range.start)
+        } else {
+            StatusRangeRepr::Range(format!("{}-{}", range.start, range.end))
         }
     }
 }
@@ -384,6 +842,393 @@ impl User {
     }
 }
 
+/// A group in the system.
+/// It can be either a gid or a group name (available in /etc/group)
+#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum Group {
+    Gid(u32),
+    Name(String),
+}
+
+impl Group {
+    pub(crate) fn get_gid(&self) -> Result<unistd::Gid> {
+        match &self {
+            Group::Name(name) => {
+                let group = unistd::Group::from_name(name)?
+                    .with_context(|| format!("Group `{}` not found", name))?;
+                Ok(group.gid)
+            }
+            Group::Gid(gid) => Ok(unistd::Gid::from_raw(*gid)),
+        }
+    }
+}
+
+/// The capabilities of the service. By default, the service inherits horust's ones.
+#[derive(Serialize, Clone, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Capabilities {
+    /// If not empty, the other capabilities are removed, except the `ambient` ones.
+    #[serde(default)]
+    pub keep: Vec<Capability>,
+    #[serde(default)]
+    pub drop: Vec<Capability>,
+    /// Kept by the service across `execve`, even when it doesn't run as root.
+    #[serde(default)]
+    pub ambient: Vec<Capability>,
+}
+
+impl Capabilities {
+    pub fn is_empty(&self) -> bool {
+        self.keep.is_empty() && self.drop.is_empty() && self.ambient.is_empty()
+    }
+}
+
+const CAPABILITY_NAMES: [&str; 41] = [
+    "CHOWN",
+    "DAC_OVERRIDE",
+    "DAC_READ_SEARCH",
+    "FOWNER",
+    "FSETID",
+    "KILL",
+    "SETGID",
+    "SETUID",
+    "SETPCAP",
+    "LINUX_IMMUTABLE",
+    "NET_BIND_SERVICE",
+    "NET_BROADCAST",
+    "NET_ADMIN",
+    "NET_RAW",
+    "IPC_LOCK",
+    "IPC_OWNER",
+    "SYS_MODULE",
+    "SYS_RAWIO",
+    "SYS_CHROOT",
+    "SYS_PTRACE",
+    "SYS_PACCT",
+    "SYS_ADMIN",
+    "SYS_BOOT",
+    "SYS_NICE",
+    "SYS_RESOURCE",
+    "SYS_TIME",
+    "SYS_TTY_CONFIG",
+    "MKNOD",
+    "LEASE",
+    "AUDIT_WRITE",
+    "AUDIT_CONTROL",
+    "SETFCAP",
+    "MAC_OVERRIDE",
+    "MAC_ADMIN",
+    "SYSLOG",
+    "WAKE_ALARM",
+    "BLOCK_SUSPEND",
+    "AUDIT_READ",
+    "PERFMON",
+    "BPF",
+    "CHECKPOINT_RESTORE",
+];
+
+/// A Linux capability, e.g. `CAP_NET_BIND_SERVICE` (or `net_bind_service`).
+#[derive(Serialize, Clone, Copy, Deserialize, Debug, Eq, PartialEq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct Capability(u8);
+
+impl Capability {
+    /// The number of the capability, as used by the kernel.
+    pub fn index(self) -> u8 {
+        self.0
+    }
+}
+
+impl TryFrom<String> for Capability {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        let upper = name.to_uppercase();
+        let short = upper.strip_prefix("CAP_").unwrap_or(&upper);
+        CAPABILITY_NAMES
+            .iter()
+            .position(|candidate| *candidate == short)
+            .map(|index| Self(index as u8))
+            .ok_or_else(|| format!("Unknown capability: {name}"))
+    }
+}
+
+impl From<Capability> for String {
+    fn from(capability: Capability) -> Self {
+        format!("CAP_{}", CAPABILITY_NAMES[capability.0 as usize])
+    }
+}
+
+/// The I/O scheduling class and priority of the service, as set by `ionice`.
+#[derive(Serialize, Clone, Copy, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct IoScheduling {
+    pub class: IoSchedulingClass,
+    /// From 0 (highest priority) to 7. Ignored by the `idle` class.
+    #[serde(default = "IoScheduling::default_priority")]
+    pub priority: u8,
+}
+
+impl IoScheduling {
+    fn default_priority() -> u8 {
+        4
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoSchedulingClass {
+    /// Always served first: it can starve the other services.
+    Realtime,
+    BestEffort,
+    /// Only served when no other process needs the disk.
+    Idle,
+}
+
+/// A list of CPUs, e.g. `"0-3,8"`.
+#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct CpuList(Vec<usize>);
+
+impl CpuList {
+    /// The CPUs, sorted and without duplicates.
+    pub fn cpus(&self) -> &[usize] {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for CpuList {
+    type Error = String;
+
+    fn try_from(list: String) -> Result<Self, Self::Error> {
+        let parse = |cpu: &str| {
+            cpu.trim()
+                .parse::<usize>()
+                .map_err(|error| format!("Invalid CPU list {list}: {error}"))
+        };
+        let mut cpus = BTreeSet::new();
+        for range in list.split(',') {
+            let (first, last) = match range.split_once('-') {
+                Some((first, last)) => (parse(first)?, parse(last)?),
+                None => (parse(range)?, parse(range)?),
+            };
+            if first > last {
+                return Err(format!("Invalid CPU list {list}: {range} is reversed"));
+            }
+            cpus.extend(first..=last);
+        }
+        Ok(Self(cpus.into_iter().collect()))
+    }
+}
+
+impl From<CpuList> for String {
+    fn from(list: CpuList) -> Self {
+        let mut ranges: Vec<(usize, usize)> = vec![];
+        for cpu in list.0 {
+            match ranges.last_mut() {
+                Some((_, last)) if *last + 1 == cpu => *last = cpu,
+                _ => ranges.push((cpu, cpu)),
+            }
+        }
+        ranges
+            .into_iter()
+            .map(|(first, last)| {
+                if first == last {
+                    first.to_string()
+                } else {
+                    format!("{first}-{last}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// A file mode creation mask, in octal, e.g. `"0027"`.
+#[derive(Serialize, Clone, Copy, Deserialize, Debug, Eq, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Umask(u32);
+
+impl Umask {
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl TryFrom<String> for Umask {
+    type Error = String;
+
+    fn try_from(umask: String) -> Result<Self, Self::Error> {
+        match u32::from_str_radix(umask.trim_start_matches("0o"), 8) {
+            Ok(bits) if bits <= 0o777 => Ok(Self(bits)),
+            _ => Err(format!(
+                "Invalid umask {umask}: expected an octal mode like 0022"
+            )),
+        }
+    }
+}
+
+impl From<Umask> for String {
+    fn from(umask: Umask) -> Self {
+        format!("{:04o}", umask.0)
+    }
+}
+
+/// Filesystem sandboxing: the service gets its own mount namespace, so the mounts below are only
+/// visible to it. The paths are inside `root-directory`, if set.
+#[derive(Serialize, Clone, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Sandbox {
+    /// Mounts an empty tmpfs on /tmp and /var/tmp.
+    #[serde(default = "default_as_false")]
+    pub private_tmp: bool,
+    #[serde(default)]
+    pub read_only_paths: Vec<PathBuf>,
+    /// Hidden behind an empty, read-only directory or file.
+    #[serde(default)]
+    pub inaccessible_paths: Vec<PathBuf>,
+    #[serde(default)]
+    pub bind_mounts: Vec<BindMount>,
+    /// The service is chrooted into this directory, after setting up the other mounts.
+    pub root_directory: Option<PathBuf>,
+}
+
+impl Sandbox {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// A seccomp filter: `seccomp = "default"`, or `seccomp = { profile = "default", action = "kill" }`.
+#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[serde(from = "RawSeccomp", into = "RawSeccomp")]
+pub struct Seccomp {
+    pub profile: SeccompProfile,
+    /// Replaces the action of the profile on the syscalls it doesn't allow.
+    pub action: Option<SeccompAction>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawSeccomp {
+    Profile(SeccompProfile),
+    Full {
+        profile: SeccompProfile,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        action: Option<SeccompAction>,
+    },
+}
+
+impl From<RawSeccomp> for Seccomp {
+    fn from(raw: RawSeccomp) -> Self {
+        match raw {
+            RawSeccomp::Profile(profile) => Seccomp {
+                profile,
+                action: None,
+            },
+            RawSeccomp::Full { profile, action } => Seccomp { profile, action },
+        }
+    }
+}
+
+impl From<Seccomp> for RawSeccomp {
+    fn from(seccomp: Seccomp) -> Self {
+        match seccomp.action {
+            None => RawSeccomp::Profile(seccomp.profile),
+            action => RawSeccomp::Full {
+                profile: seccomp.profile,
+                action,
+            },
+        }
+    }
+}
+
+/// A built-in seccomp profile, or the path of an OCI seccomp profile.
+#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum SeccompProfile {
+    /// Denies the syscalls administering the system, like `mount`, `reboot` or `ptrace`.
+    Default,
+    /// The default profile, and only allows sockets of the `AF_UNIX` family.
+    NoNetwork,
+    Path(PathBuf),
+}
+
+impl TryFrom<String> for SeccompProfile {
+    type Error = String;
+
+    fn try_from(profile: String) -> Result<Self, Self::Error> {
+        match profile.as_str() {
+            "default" => Ok(SeccompProfile::Default),
+            "no-network" => Ok(SeccompProfile::NoNetwork),
+            path if path.contains('/') => Ok(SeccompProfile::Path(path.into())),
+            _ => Err(format!(
+                "Unknown seccomp profile {profile}: expected default, no-network or a path"
+            )),
+        }
+    }
+}
+
+impl From<SeccompProfile> for String {
+    fn from(profile: SeccompProfile) -> Self {
+        match profile {
+            SeccompProfile::Default => "default".into(),
+            SeccompProfile::NoNetwork => "no-network".into(),
+            SeccompProfile::Path(path) => path.display().to_string(),
+        }
+    }
+}
+
+/// What happens when the service makes a syscall its seccomp profile doesn't allow.
+#[derive(Serialize, Clone, Copy, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SeccompAction {
+    /// The syscall fails, with `EPERM` unless the profile sets another errno.
+    Errno,
+    /// The service is killed with `SIGSYS`.
+    Kill,
+    /// The syscall is allowed, and logged by the kernel.
+    Log,
+}
+
+/// The namespaces created for the service, isolating it from the rest of the system.
+#[derive(Serialize, Clone, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Namespaces {
+    /// Only a loopback interface.
+    #[serde(default = "default_as_false")]
+    pub network: bool,
+    /// The service is PID 1, and only sees its own processes.
+    #[serde(default = "default_as_false")]
+    pub pid: bool,
+    #[serde(default = "default_as_false")]
+    pub ipc: bool,
+    #[serde(default = "default_as_false")]
+    pub uts: bool,
+    /// The hostname in the UTS namespace. Defaults to horust's one.
+    pub hostname: Option<String>,
+    /// The service runs as root in its own user namespace, mapped to `user` and `group` outside.
+    #[serde(default = "default_as_false")]
+    pub user: bool,
+}
+
+impl Namespaces {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Makes `source`, a path outside of the sandbox, available at `target`.
+#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BindMount {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    #[serde(default = "default_as_false")]
+    pub read_only: bool,
+}
+
 #[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq, Hash, Default)]
 pub enum ServiceStatus {
     /// The service will be started asap
@@ -433,6 +1278,9 @@ pub struct Restart {
     pub backoff: Duration,
     #[serde(default = "default_attempts")]
     pub attempts: u32,
+    /// Replaces `strategy` when the service is OOM-killed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_oom: Option<OomStrategy>,
 }
 
 fn default_attempts() -> u32 {
@@ -445,6 +1293,7 @@ impl Default for Restart {
             strategy: Default::default(),
             backoff: Duration::from_secs(0),
             attempts: default_attempts(),
+            on_oom: None,
         }
     }
 }
@@ -475,6 +1324,16 @@ impl From<&str> for RestartStrategy {
     }
 }
 
+/// What happens when the service is killed by the kernel, out of memory in its cgroup.
+#[derive(Serialize, Clone, Copy, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OomStrategy {
+    /// Start it again, even if the restart strategy wouldn't.
+    Restart,
+    /// Don't start it again, even if the restart strategy would.
+    Stop,
+}
+
 #[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Failure {
@@ -643,74 +1502,301 @@ pub struct ResourceLimit {
     #[serde(default)]
     /// The maximum number of processes/threads that the process can create
     pub(crate) pids_max: Option<u64>,
+    #[serde(default)]
+    /// The share of CPU time of the process relative to the other cgroups, from 1 to 10000
+    pub(crate) cpu_weight: Option<u64>,
+    #[serde(default)]
+    /// The CPUs that the process can run on
+    pub(crate) cpuset_cpus: Option<CpuList>,
+    #[serde(default)]
+    /// The NUMA nodes that the process can allocate memory from
+    pub(crate) cpuset_mems: Option<CpuList>,
+    #[serde(default, skip_serializing, deserialize_with = "str_to_optional_bytes")]
+    /// Above this amount of memory, the process is throttled and its memory reclaimed
+    pub(crate) memory_high: Option<u64>,
+    #[serde(default, skip_serializing, deserialize_with = "str_to_optional_bytes")]
+    /// The maximum amount of swap that the process can use
+    pub(crate) memory_swap: Option<u64>,
+    #[serde(default)]
+    /// The share of disk time of the process relative to the other cgroups, from 10 to 1000
+    pub(crate) io_weight: Option<u16>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// The bandwidth limits of the process, per device
+    pub(crate) io_max: Vec<IoMax>,
 }
 
 impl ResourceLimit {
+    pub(crate) fn has_no_limit(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The read and write bandwidth limits on a block device.
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct IoMax {
+    /// The path of the device, e.g. `/dev/sda`.
+    pub(crate) device: PathBuf,
+    #[serde(default, skip_serializing, deserialize_with = "str_to_optional_bytes")]
+    /// Bytes per second
+    pub(crate) read_bps: Option<u64>,
+    #[serde(default, skip_serializing, deserialize_with = "str_to_optional_bytes")]
+    /// Bytes per second
+    pub(crate) write_bps: Option<u64>,
+}
+
+impl IoMax {
+    /// The major and minor numbers of the device.
     #[cfg(target_os = "linux")]
-    fn has_no_limit(&self) -> bool {
-        self.cpu.is_none() && self.memory.is_none() && self.pids_max.is_none()
+    fn device_number(&self) -> anyhow::Result<(i64, i64)> {
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+        let metadata = std::fs::metadata(&self.device)
+            .with_context(|| format!("Invalid io-max device {}", self.device.display()))?;
+        if !metadata.file_type().is_block_device() {
+            anyhow::bail!(
+                "io-max device {} is not a block device",
+                self.device.display()
+            );
+        }
+        let rdev = metadata.rdev();
+        Ok((
+            nix::sys::stat::major(rdev) as i64,
+            nix::sys::stat::minor(rdev) as i64,
+        ))
     }
 }
 
 impl Eq for ResourceLimit {}
 
 impl ResourceLimit {
-    #[cfg(target_os = "macos")]
-    pub(crate) fn apply(&self, _name: &str, _pid: unistd::Pid) -> anyhow::Result<()> {
-        anyhow::bail!("not supported on macOS");
-    }
-
+    /// The OCI resources matching the limits, applied by the cgroup manager.
     #[cfg(target_os = "linux")]
-    pub(crate) fn apply(&self, name: &str, pid: unistd::Pid) -> anyhow::Result<()> {
-        use libcgroups::common::{
-            CgroupConfig, CgroupManager, ControllerOpt, DEFAULT_CGROUP_ROOT, create_cgroup_manager,
-        };
+    pub(crate) fn resources(&self) -> anyhow::Result<oci_spec::runtime::LinuxResources> {
         use oci_spec::runtime::{
-            LinuxCpuBuilder, LinuxMemoryBuilder, LinuxPidsBuilder, LinuxResources,
+            LinuxBlockIoBuilder, LinuxCpuBuilder, LinuxMemoryBuilder, LinuxPidsBuilder,
+            LinuxResources, LinuxThrottleDeviceBuilder,
         };
 
-        if self.has_no_limit() {
-            return Ok(());
-        }
-
-        // has to be an absolute path for cgroups v2
-        let cgroup_path = Path::new(DEFAULT_CGROUP_ROOT).join(format!("horust_{}", name));
-        let manager = create_cgroup_manager(CgroupConfig {
-            cgroup_path: cgroup_path.to_path_buf(),
-            systemd_cgroup: false,
-            container_name: name.to_string(),
-        })
-        .with_context(|| format!("Failed to create cgroup manager for {}", name))?;
         let mut resource = LinuxResources::default();
-        if let Some(cpu) = self.cpu {
-            let cpu = LinuxCpuBuilder::default()
-                .period(100_000u64)
-                .quota((cpu * 100_000.0) as i64)
-                .build()?;
-            resource.set_cpu(Some(cpu));
+        // The cgroup v2 files without an equivalent in cgroup v1, where they are ignored.
+        let mut unified = HashMap::new();
+        if self.cpu.is_some()
+            || self.cpu_weight.is_some()
+            || self.cpuset_cpus.is_some()
+            || self.cpuset_mems.is_some()
+        {
+            let mut cpu = LinuxCpuBuilder::default();
+            if let Some(quota) = self.cpu {
+                cpu = cpu.period(100_000u64).quota((quota * 100_000.0) as i64);
+            }
+            if let Some(weight) = self.cpu_weight {
+                // The default weight, 100, is worth 1024 cgroup v1 shares.
+                cpu = cpu.shares((weight * 1024 / 100).clamp(2, 262_144));
+                unified.insert("cpu.weight".to_string(), weight.to_string());
+            }
+            if let Some(cpus) = &self.cpuset_cpus {
+                cpu = cpu.cpus(String::from(cpus.clone()));
+            }
+            if let Some(mems) = &self.cpuset_mems {
+                cpu = cpu.mems(String::from(mems.clone()));
+            }
+            resource.set_cpu(Some(cpu.build()?));
+        }
+        if self.memory.is_some() || self.memory_swap.is_some() {
+            let limit = self.memory.map_or(-1, |mem| mem as i64);
+            let mut memory = LinuxMemoryBuilder::default().limit(limit);
+            if let Some(swap) = self.memory_swap {
+                // Like cgroup v1, the OCI swap limit is on the memory and the swap together.
+                memory = memory.swap(if limit == -1 {
+                    swap as i64
+                } else {
+                    limit + swap as i64
+                });
+            }
+            resource.set_memory(Some(memory.build()?));
         }
-        if let Some(mem) = self.memory {
-            let memory = LinuxMemoryBuilder::default().limit(mem as i64).build()?;
-            resource.set_memory(Some(memory));
+        if let Some(high) = self.memory_high {
+            unified.insert("memory.high".to_string(), high.to_string());
         }
         if let Some(pid_max) = self.pids_max {
             let pid = LinuxPidsBuilder::default().limit(pid_max as i64).build()?;
             resource.set_pids(Some(pid));
         }
+        if self.io_weight.is_some() || !self.io_max.is_empty() {
+            let mut block_io = LinuxBlockIoBuilder::default();
+            if let Some(weight) = self.io_weight {
+                block_io = block_io.weight(weight);
+            }
+            let (mut read_bps, mut write_bps) = (vec![], vec![]);
+            for io_max in &self.io_max {
+                let (major, minor) = io_max.device_number()?;
+                for (rate, devices) in [
+                    (io_max.read_bps, &mut read_bps),
+                    (io_max.write_bps, &mut write_bps),
+                ] {
+                    if let Some(rate) = rate {
+                        devices.push(
+                            LinuxThrottleDeviceBuilder::default()
+                                .major(major)
+                                .minor(minor)
+                                .rate(rate)
+                                .build()?,
+                        );
+                    }
+                }
+            }
+            if !read_bps.is_empty() {
+                block_io = block_io.throttle_read_bps_device(read_bps);
+            }
+            if !write_bps.is_empty() {
+                block_io = block_io.throttle_write_bps_device(write_bps);
+            }
+            resource.set_block_io(Some(block_io.build()?));
+        }
+        if !unified.is_empty() {
+            resource.set_unified(Some(unified));
+        }
 
-        manager
-            .add_task(pid)
-            .with_context(|| format!("Failed to add task to cgroup {}", name))?;
-        manager
-            .apply(&ControllerOpt {
-                resources: &resource,
-                disable_oom_killer: false,
-                oom_score_adj: None,
-                freezer_state: None,
-            })
-            .with_context(|| format!("Failed to apply resource limits to cgroup {}", name))?;
+        Ok(resource)
+    }
+}
+
+/// A resource limited with `setrlimit`.
+#[derive(Serialize, Clone, Copy, Deserialize, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum RlimitResource {
+    /// Address space, in bytes.
+    As,
+    /// Core dumps, in bytes.
+    Core,
+    /// CPU time, in seconds.
+    Cpu,
+    /// Data segment, in bytes.
+    Data,
+    /// Created files, in bytes.
+    Fsize,
+    Locks,
+    /// Locked memory, in bytes.
+    Memlock,
+    /// POSIX message queues, in bytes.
+    Msgqueue,
+    Nice,
+    /// Open file descriptors.
+    Nofile,
+    /// Processes (threads included) of the user.
+    Nproc,
+    Rss,
+    Rtprio,
+    /// Real time CPU time, in microseconds.
+    Rttime,
+    Sigpending,
+    /// Stack, in bytes.
+    Stack,
+}
+
+/// Soft and hard limits of a resource: `u64::MAX` is unlimited. An unset limit keeps horust's one,
+/// except for the soft limit which is lowered to the hard limit if needed.
+#[derive(Serialize, Clone, Copy, Deserialize, Debug, Eq, PartialEq)]
+#[serde(try_from = "RawRlimit", into = "RawRlimit")]
+pub struct Rlimit {
+    pub soft: Option<u64>,
+    pub hard: Option<u64>,
+}
+
+impl Rlimit {
+    pub const UNLIMITED: u64 = u64::MAX;
+
+    /// The soft and hard limits to set, given horust's current ones.
+    pub fn resolve(&self, (soft, hard): (u64, u64)) -> (u64, u64) {
+        let hard = self.hard.unwrap_or(hard);
+        (self.soft.unwrap_or(soft.min(hard)), hard)
+    }
+}
 
-        Ok(())
+/// `nofile = 65536` sets both limits, `nofile = { soft = 1024, hard = 65536 }` sets them separately.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawRlimit {
+    Both(RlimitValue),
+    Separate {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        soft: Option<RlimitValue>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hard: Option<RlimitValue>,
+    },
+}
+
+/// A number, a size (`"8 MiB"`) or `"unlimited"`.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RlimitValue {
+    Number(u64),
+    Text(String),
+}
+
+impl TryFrom<RlimitValue> for u64 {
+    type Error = String;
+
+    fn try_from(value: RlimitValue) -> Result<Self, Self::Error> {
+        match value {
+            RlimitValue::Number(number) => Ok(number),
+            RlimitValue::Text(text) if text == "unlimited" || text == "infinity" => {
+                Ok(Rlimit::UNLIMITED)
+            }
+            RlimitValue::Text(text) => {
+                bytefmt::parse(&text).map_err(|error| format!("Invalid rlimit {text}: {error}"))
+            }
+        }
+    }
+}
+
+impl From<u64> for RlimitValue {
+    fn from(value: u64) -> Self {
+        if value == Rlimit::UNLIMITED {
+            RlimitValue::Text("unlimited".into())
+        } else {
+            RlimitValue::Number(value)
+        }
+    }
+}
+
+impl TryFrom<RawRlimit> for Rlimit {
+    type Error = String;
+
+    fn try_from(raw: RawRlimit) -> Result<Self, Self::Error> {
+        let rlimit = match raw {
+            RawRlimit::Both(value) => {
+                let value = u64::try_from(value)?;
+                Rlimit {
+                    soft: Some(value),
+                    hard: Some(value),
+                }
+            }
+            RawRlimit::Separate { soft, hard } => Rlimit {
+                soft: soft.map(u64::try_from).transpose()?,
+                hard: hard.map(u64::try_from).transpose()?,
+            },
+        };
+        match (rlimit.soft, rlimit.hard) {
+            (None, None) => Err("An rlimit needs a soft or a hard limit".into()),
+            (Some(soft), Some(hard)) if soft > hard => Err(format!(
+                "The soft limit {soft} is above the hard limit {hard}"
+            )),
+            _ => Ok(rlimit),
+        }
+    }
+}
+
+impl From<Rlimit> for RawRlimit {
+    fn from(rlimit: Rlimit) -> Self {
+        match (rlimit.soft, rlimit.hard) {
+            (Some(soft), Some(hard)) if soft == hard => RawRlimit::Both(soft.into()),
+            (soft, hard) => RawRlimit::Separate {
+                soft: soft.map(Into::into),
+                hard: hard.map(Into::into),
+            },
+        }
     }
 }
 
@@ -739,6 +1825,75 @@ pub fn validate(services: Vec<Service>) -> Result<Vec<Service>, ValidationErrors
                 });
             }
         });
+        service
+            .capabilities
+            .ambient
+            .iter()
+            .filter(|capability| service.capabilities.drop.contains(capability))
+            .for_each(|capability| {
+                errors.push(ValidationError::AmbientCapabilityDropped {
+                    service: service.name.clone(),
+                    capability: String::from(*capability),
+                });
+            });
+        let ranges = [
+            ("nice", service.nice.map(i64::from), -20, 19),
+            (
+                "io-scheduling.priority",
+                service.io_scheduling.map(|io| io.priority.into()),
+                0,
+                7,
+            ),
+            (
+                "oom-score-adjust",
+                service.oom_score_adjust.map(i64::from),
+                -1000,
+                1000,
+            ),
+            (
+                "resource-limit.cpu-weight",
+                service
+                    .resource_limit
+                    .cpu_weight
+                    .map(|weight| weight.try_into().unwrap_or(i64::MAX)),
+                1,
+                10_000,
+            ),
+            (
+                "resource-limit.io-weight",
+                service.resource_limit.io_weight.map(i64::from),
+                10,
+                1000,
+            ),
+        ];
+        for (field, value, min, max) in ranges {
+            if let Some(value) = value.filter(|value| !(min..=max).contains(value)) {
+                errors.push(ValidationError::OutOfRange {
+                    service: service.name.clone(),
+                    field: field.into(),
+                    value,
+                    min,
+                    max,
+                });
+            }
+        }
+        if service.namespaces.hostname.is_some() && !service.namespaces.uts {
+            errors.push(ValidationError::HostnameWithoutUtsNamespace {
+                service: service.name.clone(),
+            });
+        }
+        if service.namespaces.user && !service.supplementary_groups.is_empty() {
+            errors.push(ValidationError::SupplementaryGroupsInUserNamespace {
+                service: service.name.clone(),
+            });
+        }
+        if service.healthiness.on_failure == HealthinessFailureAction::RunCommand
+            && service.healthiness.on_failure_command.is_none()
+        {
+            errors.push(ValidationError::OnFailureCommandMissing {
+                service: service.name.clone(),
+            });
+        }
         service.shutdown_after.iter().for_each(|name| {
             let passed = services.iter().any(|s| s.name == *name);
             if !passed {
@@ -777,13 +1932,17 @@ where
 
 #[cfg(test)]
 mod test {
+    use std::collections::BTreeMap;
     use std::str::FromStr;
     use std::time::Duration;
 
     use crate::horust::formats::ResourceLimit;
     use crate::horust::formats::{
-        Environment, Failure, FailureStrategy, Healthiness, Restart, RestartStrategy, Service,
-        Termination, TerminationSignal::TERM, validate,
+        BindMount, Capabilities, Capability, Environment, Failure, FailureStrategy, Healthiness,
+        HealthinessFailureAction, HttpHealthiness, IoMax, IoScheduling, IoSchedulingClass,
+        Namespaces, ResourceHealthiness, Restart, RestartStrategy, Rlimit, RlimitResource, Sandbox,
+        Seccomp, SeccompAction, SeccompProfile, Service, StatusRange, Termination,
+        TerminationSignal, TerminationSignal::TERM, validate,
     };
     use crate::horust::get_sample_service;
 
@@ -816,6 +1975,16 @@ mod test {
             name: "".to_string(),
             command: "/bin/bash -c \'echo hello world\'".to_string(),
             user: super::User::Name(current_user_name),
+            group: None,
+            supplementary_groups: Vec::new(),
+            capabilities: Default::default(),
+            no_new_privileges: false,
+            nice: None,
+            io_scheduling: None,
+            oom_score_adjust: None,
+            cpu_affinity: None,
+            umask: None,
+            seccomp: None,
             environment: Environment {
                 keep_env: false,
                 re_export: vec!["PATH".to_string(), "DB_PASS".to_string()],
@@ -828,6 +1997,7 @@ mod test {
             stdout_rotate_size: 100_000_000,
             stdout_should_append_timestamp_to_filename: false,
             stderr: "STDERR".into(),
+            log_buffer_size: 64 * 1024,
             start_delay: Duration::from_secs(2),
             start_after: vec!["database".into(), "backend.toml".into()],
             shutdown_after: vec![],
@@ -835,11 +2005,58 @@ mod test {
                 strategy: RestartStrategy::Never,
                 backoff: Duration::from_millis(0),
                 attempts: 0,
+                on_oom: None,
             },
             healthiness: Healthiness {
                 http_endpoint: Some("http://localhost:8080/healthcheck".into()),
+                http: HttpHealthiness {
+                    method: Some("GET".into()),
+                    headers: BTreeMap::from([("Authorization".into(), "Bearer token".into())]),
+                    status: vec![
+                        StatusRange {
+                            start: 200,
+                            end: 200,
+                        },
+                        StatusRange {
+                            start: 204,
+                            end: 206,
+                        },
+                    ],
+                    body_contains: Some(r#""status":"ok""#.into()),
+                    body_regex: None,
+                    tls_verify: true,
+                    tls_verify_hostname: true,
+                    tls_ca_cert: None,
+                },
+                grpc_endpoint: None,
+                grpc_service: None,
                 file_path: Some("/var/myservice/up".into()),
                 command: Some("curl -s http://localhost:8080/healthcheck".into()),
+                command_env: BTreeMap::from([("CURL_HOME".into(), "/etc/myservice".into())]),
+                tcp_endpoint: Some("localhost:5432".into()),
+                unix_socket: Some("/var/run/myservice.sock".into()),
+                ready_pattern: Some("ready to accept connections".into()),
+                heartbeat_file: Some("/var/myservice/heartbeat".into()),
+                max_age: Some(Duration::from_secs(60)),
+                resources: ResourceHealthiness {
+                    max_rss: Some(512 * 1024 * 1024),
+                    max_fds: Some(1000),
+                    max_threads: None,
+                    max_cpu: Some(150),
+                    sustained_for: Some(Duration::from_secs(60)),
+                },
+                custom: BTreeMap::new(),
+                max_failed: 3,
+                on_failure: HealthinessFailureAction::RunCommand,
+                on_failure_command: Some("/usr/local/bin/dump-threads.sh".into()),
+                interval: Some(Duration::from_secs(5)),
+                timeout: Some(Duration::from_secs(2)),
+                start_period: Some(Duration::from_secs(30)),
+                success_threshold: Some(1),
+            },
+            readiness: Healthiness {
+                http_endpoint: Some("http://localhost:8080/ready".into()),
+                interval: Some(Duration::from_secs(5)),
                 ..Default::default()
             },
             signal_rewrite: None,
@@ -856,6 +2073,48 @@ mod test {
                 cpu: Some(0.5),
                 memory: Some(100 * 1024 * 1024),
                 pids_max: Some(100),
+                ..Default::default()
+            },
+            rlimits: BTreeMap::from([
+                (
+                    RlimitResource::Nofile,
+                    Rlimit {
+                        soft: Some(65536),
+                        hard: Some(65536),
+                    },
+                ),
+                (
+                    RlimitResource::Core,
+                    Rlimit {
+                        soft: Some(0),
+                        hard: Some(0),
+                    },
+                ),
+                (
+                    RlimitResource::Stack,
+                    Rlimit {
+                        soft: Some(8 * 1024 * 1024),
+                        hard: Some(Rlimit::UNLIMITED),
+                    },
+                ),
+            ]),
+            sandbox: Sandbox {
+                private_tmp: true,
+                read_only_paths: vec!["/etc".into()],
+                inaccessible_paths: vec!["/root".into()],
+                bind_mounts: vec![BindMount {
+                    source: "/var/lib/myservice".into(),
+                    target: "/srv".into(),
+                    read_only: true,
+                }],
+                root_directory: None,
+            },
+            namespaces: Namespaces {
+                network: true,
+                ipc: true,
+                uts: true,
+                hostname: Some("myservice".into()),
+                ..Default::default()
             },
         };
 
@@ -929,6 +2188,9 @@ working-directory = "/tmp/"
             LogOutput::Stdout,
             LogOutput::Stderr,
             LogOutput::Path("/tmp/test.log".into()),
+            LogOutput::Syslog(LogEndpoint::Unix("/dev/log".into())),
+            LogOutput::Syslog(LogEndpoint::Udp("127.0.0.1:514".into())),
+            LogOutput::JsonLines(LogEndpoint::Tcp("localhost:24224".into())),
         ];
         for lo in cases {
             let s: String = lo.clone().into();
@@ -937,6 +2199,34 @@ working-directory = "/tmp/"
         }
     }
 
+    #[test]
+    fn test_log_output_forwarders() {
+        assert_eq!(
+            LogOutput::from("SYSLOG"),
+            LogOutput::Syslog(LogEndpoint::Unix(DEFAULT_SYSLOG_SOCKET.into()))
+        );
+        assert_eq!(
+            LogOutput::from("json+udp://[::1]:9000"),
+            LogOutput::JsonLines(LogEndpoint::Udp("[::1]:9000".into()))
+        );
+        let service = r#"
+command = "/bin/true"
+stdout = "syslog+tcp://logs:601"
+"#
+        .parse::<Service>()
+        .unwrap();
+        assert_eq!(
+            service.stdout,
+            LogOutput::Syslog(LogEndpoint::Tcp("logs:601".into()))
+        );
+        let unsupported = r#"
+command = "/bin/true"
+stdout = "gelf+udp://logs:12201"
+"#
+        .parse::<Service>();
+        assert!(unsupported.is_err());
+    }
+
     // --- Validate edge cases ---
 
     #[test]
@@ -990,5 +2280,309 @@ max-failed = 5
         assert_eq!(svc.healthiness.max_failed, 5);
     }
 
-    use super::LogOutput;
+    #[test]
+    fn test_liveness_and_readiness() {
+        let svc: Service = Service::from_str(
+            r#"
+command = "test"
+[liveness]
+command = "true"
+[readiness]
+tcp-endpoint = "localhost:5432"
+"#,
+        )
+        .unwrap();
+        assert_eq!(svc.healthiness.command.as_deref(), Some("true"));
+        assert_eq!(
+            svc.readiness.tcp_endpoint.as_deref(),
+            Some("localhost:5432")
+        );
+        assert!(svc.readiness.command.is_none());
+    }
+
+    #[test]
+    fn test_rlimits() {
+        let rlimit = |value: &str| {
+            toml::from_str::<Service>(&format!("command = \"true\"\n[rlimits]\nnofile = {value}"))
+                .map(|service| service.rlimits[&RlimitResource::Nofile])
+        };
+        let limits = |soft, hard| Rlimit { soft, hard };
+        assert_eq!(rlimit("1024").unwrap(), limits(Some(1024), Some(1024)));
+        assert_eq!(
+            rlimit(r#""unlimited""#).unwrap(),
+            limits(Some(Rlimit::UNLIMITED), Some(Rlimit::UNLIMITED))
+        );
+        assert_eq!(
+            rlimit(r#"{ soft = "1 KiB" }"#).unwrap(),
+            limits(Some(1024), None)
+        );
+        assert_eq!(rlimit("{ hard = 10 }").unwrap(), limits(None, Some(10)));
+        rlimit("{ soft = 10, hard = 1 }").unwrap_err();
+        rlimit("{}").unwrap_err();
+        rlimit(r#""lots""#).unwrap_err();
+        toml::from_str::<Service>("command = \"true\"\n[rlimits]\nfiles = 1").unwrap_err();
+
+        assert_eq!(limits(Some(1), None).resolve((10, 100)), (1, 100));
+        assert_eq!(limits(None, Some(5)).resolve((10, 100)), (5, 5));
+        assert_eq!(limits(None, Some(50)).resolve((10, 100)), (10, 50));
+
+        let service = Service {
+            rlimits: BTreeMap::from([
+                (RlimitResource::Nofile, limits(Some(1), Some(2))),
+                (RlimitResource::As, limits(None, Some(Rlimit::UNLIMITED))),
+            ]),
+            ..Service::from_name("svc")
+        };
+        let serialized = toml::to_string(&service).unwrap();
+        assert!(serialized.contains(r#"hard = "unlimited""#));
+        assert_eq!(
+            Service::from_str(&serialized).unwrap().rlimits,
+            service.rlimits
+        );
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities: Capabilities = toml::from_str(
+            r#"keep = ["CAP_NET_BIND_SERVICE", "chown"]
+drop = ["cap_sys_admin"]
+ambient = ["NET_RAW"]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            capabilities.keep,
+            vec![
+                Capability::try_from("CAP_NET_BIND_SERVICE".to_string()).unwrap(),
+                Capability::try_from("CAP_CHOWN".to_string()).unwrap(),
+            ]
+        );
+        assert_eq!(capabilities.keep[0].index(), 10);
+        assert_eq!(capabilities.keep[1].index(), 0);
+        assert_eq!(capabilities.drop[0].index(), 21);
+        assert_eq!(String::from(capabilities.ambient[0]), "CAP_NET_RAW");
+        assert!(toml::from_str::<Capabilities>(r#"keep = ["CAP_FLY"]"#).is_err());
+        assert!(Capabilities::default().is_empty());
+
+        let mut service = Service::from_name("svc");
+        service.command = "true".into();
+        service.capabilities = capabilities;
+        validate(vec![service.clone()]).unwrap();
+        service
+            .capabilities
+            .drop
+            .push(service.capabilities.ambient[0]);
+        validate(vec![service]).unwrap_err();
+    }
+
+    #[test]
+    fn test_scheduling() {
+        let service = Service::from_str(
+            r#"command = "true"
+nice = -5
+io-scheduling = { class = "best-effort" }
+oom-score-adjust = -900
+cpu-affinity = "4,0-2,1"
+umask = "0027""#,
+        )
+        .unwrap();
+        assert_eq!(
+            service.io_scheduling,
+            Some(IoScheduling {
+                class: IoSchedulingClass::BestEffort,
+                priority: 4
+            })
+        );
+        assert_eq!(service.cpu_affinity.as_ref().unwrap().cpus(), [0, 1, 2, 4]);
+        assert_eq!(service.umask.unwrap().bits(), 0o027);
+        validate(vec![service.clone()]).unwrap();
+        let serialized = toml::to_string(&service).unwrap();
+        assert!(serialized.contains(r#"cpu-affinity = "0-2,4""#));
+        assert!(serialized.contains(r#"umask = "0027""#));
+        assert_eq!(Service::from_str(&serialized).unwrap(), service);
+
+        for invalid in [r#""3-1""#, r#""a""#, r#""""#] {
+            toml::from_str::<Service>(&format!("command = \"true\"\ncpu-affinity = {invalid}"))
+                .unwrap_err();
+        }
+        for invalid in [r#""0999""#, r#""1000""#, r#""u=rwx""#] {
+            toml::from_str::<Service>(&format!("command = \"true\"\numask = {invalid}"))
+                .unwrap_err();
+        }
+
+        let out_of_range = [
+            Service {
+                nice: Some(20),
+                ..service.clone()
+            },
+            Service {
+                oom_score_adjust: Some(-1001),
+                ..service.clone()
+            },
+            Service {
+                io_scheduling: Some(IoScheduling {
+                    class: IoSchedulingClass::Realtime,
+                    priority: 8,
+                }),
+                ..service
+            },
+        ];
+        for service in out_of_range {
+            validate(vec![service]).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_resource_limit() {
+        let service = Service::from_str(
+            r#"command = "true"
+[resource-limit]
+cpu-weight = 50
+cpuset-cpus = "0-1"
+cpuset-mems = "0"
+memory-high = "80 MiB"
+memory-swap = "0"
+io-weight = 100
+io-max = [{ device = "/dev/sda", write-bps = "10 MiB" }]"#,
+        )
+        .unwrap();
+        let resource_limit = &service.resource_limit;
+        assert_eq!(resource_limit.cpu_weight, Some(50));
+        assert_eq!(resource_limit.cpuset_cpus.as_ref().unwrap().cpus(), [0, 1]);
+        assert_eq!(resource_limit.cpuset_mems.as_ref().unwrap().cpus(), [0]);
+        assert_eq!(resource_limit.memory_high, Some(80 * 1024 * 1024));
+        assert_eq!(resource_limit.memory_swap, Some(0));
+        assert_eq!(resource_limit.io_weight, Some(100));
+        assert_eq!(
+            resource_limit.io_max,
+            vec![IoMax {
+                device: "/dev/sda".into(),
+                read_bps: None,
+                write_bps: Some(10 * 1024 * 1024),
+            }]
+        );
+        validate(vec![service.clone()]).unwrap();
+
+        for (cpu_weight, io_weight) in [(Some(0), None), (Some(10_001), None), (None, Some(5))] {
+            let mut service = service.clone();
+            service.resource_limit.cpu_weight = cpu_weight;
+            service.resource_limit.io_weight = io_weight;
+            validate(vec![service]).unwrap_err();
+        }
+        toml::from_str::<Service>(
+            "command = \"true\"\n[resource-limit]\nio-max = [{ device = \"/dev/sda\", bps = 1 }]",
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_seccomp() {
+        let cases = [
+            (r#""default""#, SeccompProfile::Default, None),
+            (
+                r#"{ profile = "no-network", action = "kill" }"#,
+                SeccompProfile::NoNetwork,
+                Some(SeccompAction::Kill),
+            ),
+            (
+                r#"{ profile = "/etc/horust/seccomp.json" }"#,
+                SeccompProfile::Path("/etc/horust/seccomp.json".into()),
+                None,
+            ),
+        ];
+        for (seccomp, profile, action) in cases {
+            let service =
+                Service::from_str(&format!("command = \"true\"\nseccomp = {seccomp}")).unwrap();
+            assert_eq!(service.seccomp, Some(Seccomp { profile, action }));
+            let serialized = toml::to_string(&service).unwrap();
+            assert_eq!(Service::from_str(&serialized).unwrap(), service);
+        }
+        for invalid in [r#""strict""#, r#"{ profile = "default", action = "trap" }"#] {
+            toml::from_str::<Service>(&format!("command = \"true\"\nseccomp = {invalid}"))
+                .unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_healthiness_has_any_check_defined() {
+        assert!(!Healthiness::default().has_any_check_defined());
+        let grpc = Healthiness {
+            grpc_endpoint: Some("http://localhost:50051".into()),
+            ..Default::default()
+        };
+        assert!(grpc.has_any_check_defined());
+    }
+
+    #[test]
+    fn test_healthiness_on_failure() {
+        let on_failure = |action: &str| {
+            toml::from_str::<Healthiness>(&format!("on-failure = \"{action}\""))
+                .map(|healthiness| healthiness.on_failure)
+        };
+        assert_eq!(on_failure("stop").unwrap(), HealthinessFailureAction::Stop);
+        assert_eq!(
+            on_failure("mark-unhealthy").unwrap(),
+            HealthinessFailureAction::MarkUnhealthy
+        );
+        assert_eq!(
+            on_failure("signal:USR1").unwrap(),
+            HealthinessFailureAction::Signal(TerminationSignal::USR1)
+        );
+        assert_eq!(
+            on_failure("signal:SIGQUIT").unwrap(),
+            HealthinessFailureAction::Signal(TerminationSignal::QUIT)
+        );
+        assert_eq!(
+            String::from(HealthinessFailureAction::Signal(TerminationSignal::HUP)),
+            "signal:HUP"
+        );
+        assert_eq!(
+            Healthiness::default().on_failure,
+            HealthinessFailureAction::Restart
+        );
+        on_failure("signal:NOPE").unwrap_err();
+        on_failure("reboot").unwrap_err();
+
+        let mut service = Service::from_name("svc");
+        service.command = "true".into();
+        service.healthiness.on_failure = HealthinessFailureAction::RunCommand;
+        validate(vec![service.clone()]).unwrap_err();
+        service.healthiness.on_failure_command = Some("true".into());
+        validate(vec![service]).unwrap();
+    }
+
+    #[test]
+    fn test_http_healthiness_status() {
+        let healthiness: Healthiness = toml::from_str(
+            r#"
+[http]
+status = [200, "300-399", " 500 - 502 "]
+"#,
+        )
+        .unwrap();
+        let http = healthiness.http;
+        assert!(http.accepts_status(200));
+        assert!(!http.accepts_status(201));
+        assert!(http.accepts_status(302));
+        assert!(http.accepts_status(502));
+        assert!(!http.accepts_status(503));
+        assert!(HttpHealthiness::default().accepts_status(204));
+        assert!(!HttpHealthiness::default().accepts_status(302));
+
+        let invalid: Result<Healthiness, _> = toml::from_str("[http]\nstatus = [\"299-200\"]");
+        assert!(invalid.is_err());
+        let invalid: Result<Healthiness, _> = toml::from_str("[http]\nstatus = [\"2xx\"]");
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_http_healthiness_method() {
+        let mut http = HttpHealthiness::default();
+        assert_eq!(http.method(), "HEAD");
+        http.body_regex = Some("ok".into());
+        assert_eq!(http.method(), "GET");
+        http.method = Some("POST".into());
+        assert_eq!(http.method(), "POST");
+    }
+
+    use super::{DEFAULT_SYSLOG_SOCKET, LogEndpoint, LogOutput};
 }
@@ -1,76 +1,322 @@
-use std::collections::HashMap;
-use std::sync::{Mutex, OnceLock};
-use std::time::Duration;
+use std::ffi::OsStr;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
+use nix::errno::Errno;
+use nix::fcntl::{FcntlArg, OFlag, fcntl};
+use nix::poll::{PollFd, PollFlags, PollTimeout, poll};
+use nix::sys::signal::{self, Signal};
+use nix::sys::socket::{self, AddressFamily, SockFlag, SockType, UnixAddr, sockopt};
+use nix::sys::time::{TimeVal, TimeValLike};
+use nix::sys::wait::WaitStatus;
+use nix::unistd::{self, Pid};
+use regex::Regex;
+#[cfg(feature = "http-healthcheck")]
+use reqwest::Method;
 #[cfg(feature = "http-healthcheck")]
 use reqwest::blocking::Client;
+#[cfg(feature = "http-healthcheck")]
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 
-use crate::horust::formats::Healthiness;
-use crate::horust::supervisor::find_program;
+#[cfg(feature = "http-healthcheck")]
+use crate::horust::formats::HttpHealthiness;
+use crate::horust::formats::{CommandCheckResult, Healthiness, Service};
+#[cfg(feature = "grpc-healthcheck")]
+use crate::horust::healthcheck::grpc;
+use crate::horust::healthcheck::resources;
+use crate::horust::supervisor::{
+    Credentials, find_program, find_program_in, forget_waited, spawn_waited, try_wait_waited,
+};
 
 const FILE_CHECK: FilePathCheck = FilePathCheck {};
 const HTTP_CHECK: HttpCheck = HttpCheck {};
+const GRPC_CHECK: GrpcCheck = GrpcCheck {};
 const COMMAND_CHECK: CommandCheck = CommandCheck {};
-const CHECKS: [&dyn Check; 3] = [&FILE_CHECK, &HTTP_CHECK, &COMMAND_CHECK];
+const TCP_CHECK: TcpCheck = TcpCheck {};
+const UNIX_SOCKET_CHECK: UnixSocketCheck = UnixSocketCheck {};
+const READY_PATTERN_CHECK: ReadyPatternCheck = ReadyPatternCheck {};
+const HEARTBEAT_FILE_CHECK: HeartbeatFileCheck = HeartbeatFileCheck {};
+const RESOURCES_CHECK: ResourcesCheck = ResourcesCheck {};
+const CHECKS: [&dyn Check; 9] = [
+    &FILE_CHECK,
+    &HTTP_CHECK,
+    &GRPC_CHECK,
+    &COMMAND_CHECK,
+    &TCP_CHECK,
+    &UNIX_SOCKET_CHECK,
+    &READY_PATTERN_CHECK,
+    &HEARTBEAT_FILE_CHECK,
+    &RESOURCES_CHECK,
+];
 
-type ParsedCommands = Mutex<HashMap<String, Vec<String>>>;
-static PARSED_COMMANDS: OnceLock<ParsedCommands> = OnceLock::new();
+/// Only the end of the output of a command check is kept.
+const MAX_COMMAND_OUTPUT: usize = 4096;
 
-fn get_parsed_commands() -> &'static ParsedCommands {
-    PARSED_COMMANDS.get_or_init(|| Mutex::new(HashMap::new()))
+/// How often a running command is checked for having exited.
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A command check, ready to be run in the context of its service.
+#[derive(Clone, Debug)]
+struct PreparedCommand {
+    command: String,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    credentials: Credentials,
+    working_directory: PathBuf,
 }
 
-pub(crate) fn get_checks() -> [&'static dyn Check; 3] {
+pub(crate) fn get_checks() -> [&'static dyn Check; 9] {
     CHECKS
 }
 
 pub(crate) trait Check {
-    fn run(&self, healthiness: &Healthiness) -> bool;
-    fn prepare(&self, _healtiness: &Healthiness) -> Result<(), std::io::Error> {
+    fn run(&self, service: &Service, healthiness: &Healthiness, state: &ProbeState) -> bool;
+    fn prepare(&self, _service: &Service, _healtiness: &Healthiness) -> Result<(), std::io::Error> {
         Ok(())
     }
 }
 
-/// HTTP based healthcheck: will send an head request with 1 second timeout, and the test will be
-/// considered failed if the response is anything other than `200`.
+/// HTTP based healthcheck: will send a request (`HEAD` by default), and the test will be
+/// considered failed if the response status is not accepted (anything other than `2xx` by
+/// default), if the body doesn't match or if the response doesn't arrive within the timeout.
 pub(crate) struct HttpCheck;
 
-static HTTP_REQUEST_TIMEOUT: u64 = 1;
+/// The client and the parsed request options, built once when the probe is scheduled.
+#[cfg(feature = "http-healthcheck")]
+#[derive(Clone)]
+struct PreparedHttpCheck {
+    client: Client,
+    method: Method,
+    headers: HeaderMap,
+    body_regex: Option<Regex>,
+}
+
+/// State of the checks of a probe, built when the service starts, kept with the probe by the
+/// scheduler and dropped when the service exits.
+#[derive(Clone, Default)]
+pub(crate) struct ProbeState {
+    /// None without http endpoint, or if the client can't be built.
+    #[cfg(feature = "http-healthcheck")]
+    http: Option<PreparedHttpCheck>,
+    /// None without command, or if it can't be prepared.
+    command: Option<PreparedCommand>,
+    /// The command run by `on-failure = "run-command"`.
+    on_failure_command: Option<PreparedCommand>,
+    /// Result of the last run of the command check, until the scheduler takes it.
+    pub(crate) command_result: Arc<Mutex<Option<CommandCheckResult>>>,
+    /// A line of the output of the service has matched the `ready-pattern`.
+    pub(crate) pattern_matched: bool,
+}
 
-impl Check for HttpCheck {
-    fn run(&self, healthiness: &Healthiness) -> bool {
-        healthiness
-            .http_endpoint.as_ref()
-            .map(|endpoint| {
-                if cfg!(not(feature = "http-healthcheck")) {
-                    error!("There is an http based healthcheck, but horust was built without the http-healthcheck feature (thus it will never pass these checks).");
-                    return false;
-                }
-                #[cfg(feature = "http-healthcheck")]
-                    {
-                        let client = Client::builder()
-                            .timeout(Duration::from_secs(HTTP_REQUEST_TIMEOUT))
-                            .build().expect("Http client");
-                        let resp: Result<reqwest::blocking::Response, reqwest::Error> = client.head(endpoint).send();
-                        resp.map(|resp| resp.status().is_success()).unwrap_or(false)
-                    }
+impl ProbeState {
+    pub(crate) fn new(service: &Service, healthiness: &Healthiness) -> Self {
+        let prepare_command = |command: &Option<String>| {
+            command.as_ref().and_then(|command| {
+                CommandCheck::prepare_cmd(service, healthiness, command)
+                    .inspect_err(|error| error!("Invalid command `{command}`: {error:#}"))
+                    .ok()
             })
-            .unwrap_or(true)
+        };
+        Self {
+            #[cfg(feature = "http-healthcheck")]
+            http: healthiness.http_endpoint.as_ref().and_then(|endpoint| {
+                PreparedHttpCheck::new(&healthiness.http, healthiness.timeout())
+                    .inspect_err(|error| {
+                        error!("Invalid http healthcheck for {endpoint}: {error:#}")
+                    })
+                    .ok()
+            }),
+            command: prepare_command(&healthiness.command),
+            on_failure_command: prepare_command(&healthiness.on_failure_command),
+            command_result: Arc::default(),
+            pattern_matched: false,
+        }
+    }
+
+    /// Runs the `on-failure-command`, if any, for at most `timeout`.
+    pub(crate) fn run_on_failure_command(&self, timeout: Duration) -> Option<CommandCheckResult> {
+        self.on_failure_command
+            .as_ref()
+            .map(|prepared| run_with_timeout(prepared, timeout))
+    }
+}
+
+#[cfg(feature = "http-healthcheck")]
+impl PreparedHttpCheck {
+    fn new(http: &HttpHealthiness, timeout: Duration) -> anyhow::Result<Self> {
+        let method = Method::from_bytes(http.method().to_uppercase().as_bytes())
+            .context(format!("Invalid http method: {}", http.method()))?;
+        let mut headers = HeaderMap::new();
+        for (name, value) in &http.headers {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())
+                    .context(format!("Invalid http header name: {name}"))?,
+                HeaderValue::from_str(value)
+                    .context(format!("Invalid value for http header {name}"))?,
+            );
+        }
+        let body_regex = http
+            .body_regex
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .context("Invalid body-regex")?;
+        let builder = Client::builder().timeout(timeout);
+        #[cfg(feature = "http-healthcheck-tls")]
+        let builder = {
+            let mut builder = builder
+                .danger_accept_invalid_certs(!http.tls_verify)
+                .danger_accept_invalid_hostnames(!http.tls_verify_hostname);
+            if let Some(ca_cert) = &http.tls_ca_cert {
+                let pem = std::fs::read(ca_cert)
+                    .context(format!("Failed to read {}", ca_cert.display()))?;
+                builder = builder.add_root_certificate(
+                    reqwest::Certificate::from_pem(&pem)
+                        .context(format!("Invalid certificate: {}", ca_cert.display()))?,
+                );
+            }
+            builder
+        };
+        #[cfg(not(feature = "http-healthcheck-tls"))]
+        if http.has_tls_options() {
+            anyhow::bail!(
+                "TLS options are set, but horust was built without the http-healthcheck-tls feature"
+            );
+        }
+        Ok(Self {
+            client: builder.build().context("Failed to build the http client")?,
+            method,
+            headers,
+            body_regex,
+        })
+    }
+
+    fn run(&self, http: &HttpHealthiness, endpoint: &str) -> bool {
+        let resp = match self
+            .client
+            .request(self.method.clone(), endpoint)
+            .headers(self.headers.clone())
+            .send()
+        {
+            Ok(resp) => resp,
+            Err(error) => {
+                debug!("Http request to {endpoint} failed: {error}");
+                return false;
+            }
+        };
+        let status = resp.status().as_u16();
+        if !http.accepts_status(status) {
+            debug!("Http request to {endpoint} returned {status}");
+            return false;
+        }
+        if !http.checks_body() {
+            return true;
+        }
+        let body = match resp.text() {
+            Ok(body) => body,
+            Err(error) => {
+                debug!("Failed to read the response body of {endpoint}: {error}");
+                return false;
+            }
+        };
+        let contains = http
+            .body_contains
+            .as_ref()
+            .is_none_or(|needle| body.contains(needle.as_str()));
+        let matches = self
+            .body_regex
+            .as_ref()
+            .is_none_or(|regex| regex.is_match(&body));
+        contains && matches
+    }
+}
+
+impl Check for HttpCheck {
+    fn run(&self, _service: &Service, healthiness: &Healthiness, state: &ProbeState) -> bool {
+        let Some(endpoint) = &healthiness.http_endpoint else {
+            return true;
+        };
+        #[cfg(feature = "http-healthcheck")]
+        {
+            // The error was logged when the probe was scheduled.
+            state
+                .http
+                .as_ref()
+                .is_some_and(|http| http.run(&healthiness.http, endpoint))
+        }
+        #[cfg(not(feature = "http-healthcheck"))]
+        {
+            let _ = state;
+            error!(
+                "There is an http based healthcheck on {endpoint}, but horust was built without the http-healthcheck feature (thus it will never pass these checks)."
+            );
+            false
+        }
+    }
+    /// Only validates the options: the client used by the checks is built by the scheduler.
+    #[cfg(feature = "http-healthcheck")]
+    fn prepare(&self, _service: &Service, healthiness: &Healthiness) -> Result<(), std::io::Error> {
+        if healthiness.http_endpoint.is_none() {
+            return Ok(());
+        }
+        PreparedHttpCheck::new(&healthiness.http, healthiness.timeout())
+            .map(drop)
+            .map_err(|e| std::io::Error::other(format!("{e:#}")))
+    }
+}
+
+/// gRPC based healthcheck: the service is healthy if `grpc-endpoint` answers `SERVING` to the
+/// standard `grpc.health.v1.Health/Check` RPC within the timeout.
+pub(crate) struct GrpcCheck;
+
+impl Check for GrpcCheck {
+    fn run(&self, _service: &Service, healthiness: &Healthiness, _state: &ProbeState) -> bool {
+        let Some(endpoint) = &healthiness.grpc_endpoint else {
+            return true;
+        };
+        #[cfg(feature = "grpc-healthcheck")]
+        {
+            grpc::check(
+                endpoint,
+                healthiness.grpc_service.as_deref(),
+                healthiness.timeout(),
+            )
+        }
+        #[cfg(not(feature = "grpc-healthcheck"))]
+        {
+            error!(
+                "There is a gRPC based healthcheck on {endpoint}, but horust was built without the grpc-healthcheck feature (thus it will never pass these checks)."
+            );
+            false
+        }
+    }
+    #[cfg(feature = "grpc-healthcheck")]
+    fn prepare(&self, _service: &Service, healthiness: &Healthiness) -> Result<(), std::io::Error> {
+        match &healthiness.grpc_endpoint {
+            Some(endpoint) => grpc::prepare(endpoint, healthiness.timeout()),
+            None => Ok(()),
+        }
     }
 }
 
 pub(crate) struct FilePathCheck;
 
 impl Check for FilePathCheck {
-    fn run(&self, healthiness: &Healthiness) -> bool {
+    fn run(&self, _service: &Service, healthiness: &Healthiness, _state: &ProbeState) -> bool {
         healthiness
             .file_path
             .as_ref()
             .map(|file_path| file_path.exists())
             .unwrap_or(true)
     }
-    fn prepare(&self, healthiness: &Healthiness) -> Result<(), std::io::Error> {
+    fn prepare(&self, _service: &Service, healthiness: &Healthiness) -> Result<(), std::io::Error> {
         //TODO: check if user has permissions to remove the file.
         healthiness
             .file_path
@@ -81,56 +327,317 @@ impl Check for FilePathCheck {
     }
 }
 
+/// Command based healthcheck: the service is healthy if the command exits with status `0`.
+/// The command runs with the service's user, working directory and environment (plus
+/// `command-env`), and the program is searched in the service's `PATH`.
 pub(crate) struct CommandCheck {}
 
 impl CommandCheck {
-    fn prepare_cmd(&self, cmd: &str) -> anyhow::Result<()> {
+    fn prepare_cmd(
+        service: &Service,
+        healthiness: &Healthiness,
+        cmd: &str,
+    ) -> anyhow::Result<PreparedCommand> {
         let mut chunks = shlex::split(cmd).context(format!("Failed to split command: {}", cmd))?;
         let program = chunks
             .first()
             .context(format!("Failed to get program from command: {}", cmd))?;
+        let mut env: Vec<(String, String)> = service
+            .get_environment()?
+            .into_iter()
+            .filter_map(|var| {
+                var.split_once('=')
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+            })
+            .collect();
+        env.extend(
+            healthiness
+                .command_env
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
         let path = if program.contains('/') {
             program.to_string()
         } else {
-            find_program(program)?
+            // The last definition wins, as it happens when the variables are set.
+            match env.iter().rev().find(|(key, _)| key == "PATH") {
+                Some((_, path_var)) => find_program_in(program, OsStr::new(path_var))?,
+                None => find_program(program)?,
+            }
         };
         chunks[0] = path;
-        get_parsed_commands()
-            .lock()
-            .unwrap()
-            .insert(cmd.to_string(), chunks);
-        Ok(())
+        Ok(PreparedCommand {
+            command: cmd.to_string(),
+            args: chunks,
+            env,
+            credentials: Credentials::new(service)?,
+            working_directory: service.working_directory.clone(),
+        })
+    }
+}
+
+/// Keeps the end of the output, which usually explains why the check has failed.
+fn truncate_output(mut output: Vec<u8>) -> String {
+    output.drain(..output.len().saturating_sub(MAX_COMMAND_OUTPUT));
+    String::from_utf8_lossy(&output).trim_end().to_string()
+}
+
+/// Reads what the pipe holds, keeping only the end of the output. Returns false once it is closed.
+fn read_available(pipe: &OwnedFd, output: &mut Vec<u8>) -> bool {
+    let mut buffer = [0; MAX_COMMAND_OUTPUT];
+    // At most what a pipe holds by default, so that a command writing without a pause can't keep
+    // the thread reading.
+    for _ in 0..16 {
+        match unistd::read(pipe.as_raw_fd(), &mut buffer) {
+            Ok(0) => return false,
+            Ok(read) => {
+                output.extend_from_slice(&buffer[..read]);
+                output.drain(..output.len().saturating_sub(MAX_COMMAND_OUTPUT));
+            }
+            Err(Errno::EINTR) => (),
+            Err(error) => return error == Errno::EAGAIN,
+        }
+    }
+    true
+}
+
+/// Waits until one of the pipes can be read, for at most `wait`.
+fn wait_for_output(pipes: &[(Option<OwnedFd>, Vec<u8>)], wait: Duration) {
+    let mut fds: Vec<PollFd> = pipes
+        .iter()
+        .filter_map(|(pipe, _)| pipe.as_ref())
+        .map(|fd| PollFd::new(fd.as_fd(), PollFlags::POLLIN))
+        .collect();
+    if fds.is_empty() {
+        thread::sleep(wait);
+    } else {
+        let _ = poll(
+            &mut fds,
+            PollTimeout::try_from(wait).unwrap_or(PollTimeout::MAX),
+        );
+    }
+}
+
+/// Runs the command in a new process group, which is killed if the command is still running after
+/// `timeout`. Only the command is waited for: the processes it leaves behind can keep its output
+/// open.
+fn run_with_timeout(prepared: &PreparedCommand, timeout: Duration) -> CommandCheckResult {
+    let deadline = Instant::now() + timeout;
+    let command = &prepared.command;
+    let result = |exit_code: Option<i32>, output: String| CommandCheckResult {
+        command: command.clone(),
+        exit_code,
+        output,
+    };
+    let credentials = prepared.credentials.clone();
+    let mut child = Command::new(&prepared.args[0]);
+    child
+        .args(&prepared.args[1..])
+        .env_clear()
+        .envs(prepared.env.iter().map(|(key, value)| (key, value)))
+        .current_dir(&prepared.working_directory)
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Safety: only async-signal-safe calls are made.
+    unsafe {
+        child.pre_exec(move || credentials.apply().map_err(std::io::Error::from));
+    }
+    let mut child = match spawn_waited(&mut child) {
+        Ok(child) => child,
+        Err(error) => {
+            error!("Failed to execute command `{command}`: {error}");
+            return result(None, format!("Failed to execute command: {error}"));
+        }
+    };
+    let pid = Pid::from_raw(child.id() as i32);
+    let mut pipes = [
+        (child.stdout.take().map(OwnedFd::from), Vec::new()),
+        (child.stderr.take().map(OwnedFd::from), Vec::new()),
+    ];
+    for fd in pipes.iter().filter_map(|(pipe, _)| pipe.as_ref()) {
+        if let Err(error) = fcntl(fd.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK)) {
+            error!("Failed to read the output of `{command}`: {error}");
+        }
+    }
+    let read_pipes = |pipes: &mut [(Option<OwnedFd>, Vec<u8>)]| {
+        for (pipe, output) in pipes.iter_mut() {
+            if pipe.as_ref().is_some_and(|fd| !read_available(fd, output)) {
+                *pipe = None;
+            }
+        }
+    };
+    loop {
+        match try_wait_waited(pid) {
+            Ok(Some(status)) => {
+                read_pipes(&mut pipes);
+                let exit_code = match status {
+                    WaitStatus::Exited(_, exit_code) => Some(exit_code),
+                    _ => None,
+                };
+                let [(_, mut output), (_, stderr)] = pipes;
+                output.extend(stderr);
+                return result(exit_code, truncate_output(output));
+            }
+            Ok(None) => (),
+            Err(error) => {
+                forget_waited(pid);
+                return result(None, format!("Failed to wait for the command: {error}"));
+            }
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            warn!("Command `{command}` timed out after {timeout:?}, killing it.");
+            let _ = signal::killpg(pid, Signal::SIGKILL);
+            // The reaper collects it.
+            forget_waited(pid);
+            return result(None, format!("Timed out after {timeout:?}"));
+        }
+        // Woken up by the output, or to check whether the command has exited.
+        wait_for_output(&pipes, COMMAND_POLL_INTERVAL.min(deadline - now));
+        read_pipes(&mut pipes);
     }
 }
 
 impl Check for CommandCheck {
-    fn run(&self, healthiness: &Healthiness) -> bool {
+    fn run(&self, _service: &Service, healthiness: &Healthiness, state: &ProbeState) -> bool {
+        let Some(command) = &healthiness.command else {
+            return true;
+        };
+        let result = match &state.command {
+            Some(prepared) => run_with_timeout(prepared, healthiness.timeout()),
+            None => CommandCheckResult {
+                command: command.clone(),
+                exit_code: None,
+                output: "The command couldn't be prepared".to_string(),
+            },
+        };
+        let passed = result.exit_code == Some(0);
+        *state.command_result.lock().unwrap() = Some(result);
+        passed
+    }
+    fn prepare(&self, service: &Service, healthiness: &Healthiness) -> Result<(), std::io::Error> {
+        // The `on-failure-command` runs in the same context as the checks.
+        [&healthiness.command, &healthiness.on_failure_command]
+            .into_iter()
+            .flatten()
+            .try_for_each(|command| {
+                Self::prepare_cmd(service, healthiness, command)
+                    .map(drop)
+                    .map_err(|e| std::io::Error::other(e.to_string()))
+            })
+    }
+}
+
+/// TCP based healthcheck: the service is healthy if a connection to the endpoint can be
+/// established within the timeout.
+pub(crate) struct TcpCheck;
+
+impl Check for TcpCheck {
+    fn run(&self, _service: &Service, healthiness: &Healthiness, _state: &ProbeState) -> bool {
         healthiness
-            .command
+            .tcp_endpoint
             .as_ref()
-            .map(|command| {
-                let parsed_command = get_parsed_commands().lock().unwrap().get(command).cloned();
-                parsed_command
-                    .map(|cmds| {
-                        let output = std::process::Command::new(&cmds[0])
-                            .args(&cmds[1..])
-                            .output()
-                            .expect("Failed to execute command");
-                        output.status.success()
-                    })
-                    .unwrap_or(false)
+            .map(|endpoint| {
+                // The resolution and the connections to every address share the timeout.
+                let deadline = Instant::now() + healthiness.timeout();
+                let addresses = match endpoint.to_socket_addrs() {
+                    Ok(addresses) => addresses,
+                    Err(error) => {
+                        debug!("Cannot resolve {endpoint}: {error}");
+                        return false;
+                    }
+                };
+                addresses.into_iter().any(|address| {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    !left.is_zero() && TcpStream::connect_timeout(&address, left).is_ok()
+                })
+            })
+            .unwrap_or(true)
+    }
+}
+
+/// Unix socket based healthcheck: the service is healthy if a connection to the socket can be
+/// established within `timeout`.
+pub(crate) struct UnixSocketCheck;
+
+impl Check for UnixSocketCheck {
+    fn run(&self, _service: &Service, healthiness: &Healthiness, _state: &ProbeState) -> bool {
+        healthiness
+            .unix_socket
+            .as_ref()
+            .map(|path| {
+                unix_connect_timeout(path, healthiness.timeout())
+                    .inspect_err(|error| debug!("Cannot connect to {}: {error}", path.display()))
+                    .is_ok()
             })
             .unwrap_or(true)
     }
-    fn prepare(&self, healtiness: &Healthiness) -> Result<(), std::io::Error> {
-        healtiness
-            .command
+}
+
+/// Connecting to a listener whose backlog is full waits for the service to accept a connection:
+/// unlike TCP, the wait is bounded by the send timeout of the socket, and a non-blocking connect
+/// fails right away instead of completing later.
+fn unix_connect_timeout(path: &Path, timeout: Duration) -> nix::Result<OwnedFd> {
+    let stream = socket::socket(
+        AddressFamily::Unix,
+        SockType::Stream,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+    // A zero timeout would mean no timeout at all.
+    let timeout = TimeVal::microseconds(timeout.as_micros().clamp(1, i64::MAX as u128) as i64);
+    socket::setsockopt(&stream, sockopt::SendTimeout, &timeout)?;
+    socket::connect(stream.as_raw_fd(), &UnixAddr::new(path)?)?;
+    Ok(stream)
+}
+
+/// Output based healthcheck: the service is healthy once a line of its stdout or stderr has
+/// matched the `ready-pattern` regex, since it was started. The match is found by the
+/// `ReadyPatternReader`, which has the scheduler run the probe right away.
+pub(crate) struct ReadyPatternCheck;
+
+impl Check for ReadyPatternCheck {
+    fn run(&self, _service: &Service, healthiness: &Healthiness, state: &ProbeState) -> bool {
+        healthiness.ready_pattern.is_none() || state.pattern_matched
+    }
+    fn prepare(&self, _service: &Service, healthiness: &Healthiness) -> Result<(), std::io::Error> {
+        if let Some(pattern) = &healthiness.ready_pattern {
+            Regex::new(pattern)
+                .map_err(|e| std::io::Error::other(format!("Invalid ready-pattern: {e}")))?;
+        }
+        Ok(())
+    }
+}
+
+/// Watchdog healthcheck: the service is healthy while the `heartbeat-file` has been modified
+/// within `max-age`. A missing file, or one modified in the future, counts as failed.
+pub(crate) struct HeartbeatFileCheck;
+
+impl Check for HeartbeatFileCheck {
+    fn run(&self, _service: &Service, healthiness: &Healthiness, _state: &ProbeState) -> bool {
+        healthiness
+            .heartbeat_file
             .as_ref()
-            .map(|command| {
-                self.prepare_cmd(command)
-                    .map_err(|e| std::io::Error::other(e.to_string()))?;
-                Ok(())
+            .map(|heartbeat_file| {
+                std::fs::metadata(heartbeat_file)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    .and_then(|modified| modified.elapsed().ok())
+                    .is_some_and(|age| age <= healthiness.max_age())
             })
-            .unwrap_or(Ok(()))
+            .unwrap_or(true)
+    }
+}
+
+/// Resource usage healthcheck: the service is unhealthy once its main process has exceeded one of
+/// the `[resources]` soft limits for `sustained-for`.
+pub(crate) struct ResourcesCheck;
+
+impl Check for ResourcesCheck {
+    fn run(&self, service: &Service, healthiness: &Healthiness, _state: &ProbeState) -> bool {
+        !healthiness.resources.has_any_limit()
+            || resources::check(&service.name, &healthiness.resources)
     }
 }
@@ -0,0 +1,122 @@
+//! Health checks provided by the application embedding horust, referenced by the services as
+//! `[healthiness.custom.<name>]`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use crate::horust::formats::{Healthiness, Service};
+
+/// The free-form parameters of a `[healthiness.custom.<name>]` section.
+pub type CustomCheckParams = toml::Table;
+
+/// An application-specific health check, registered with [`crate::Horust::register_check`].
+pub trait CustomCheck: Send + Sync {
+    /// Returns true if the service is healthy. It runs on horust's pool of health check threads,
+    /// so it must give up after the service's `timeout`: past it, the check is considered failed,
+    /// and its thread is replaced in the pool until it returns.
+    fn run(&self, service: &Service, params: &CustomCheckParams) -> bool;
+
+    /// Called before starting the service, e.g. to validate `params`. An error shuts horust down.
+    fn prepare(&self, _service: &Service, _params: &CustomCheckParams) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The custom checks, by name.
+#[derive(Clone, Default)]
+pub(crate) struct CustomChecks(HashMap<String, Arc<dyn CustomCheck>>);
+
+impl fmt::Debug for CustomChecks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+impl CustomChecks {
+    pub(crate) fn register(&mut self, name: String, check: Arc<dyn CustomCheck>) {
+        self.0.insert(name, check);
+    }
+
+    /// Returns true if every custom check of `healthiness` passes.
+    pub(crate) fn run(&self, service: &Service, healthiness: &Healthiness) -> bool {
+        healthiness
+            .custom
+            .iter()
+            .all(|(name, params)| match self.0.get(name) {
+                Some(check) => check.run(service, params),
+                None => {
+                    error!("{}: no custom check registered as '{name}'.", service.name);
+                    false
+                }
+            })
+    }
+
+    /// Fails if `healthiness` references a custom check which isn't registered.
+    pub(crate) fn prepare(&self, service: &Service, healthiness: &Healthiness) -> io::Result<()> {
+        healthiness.custom.iter().try_for_each(|(name, params)| {
+            let check = self.0.get(name).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no custom check registered as '{name}'"),
+                )
+            })?;
+            check.prepare(service, params)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::sync::Arc;
+
+    use crate::horust::formats::{Healthiness, Service};
+    use crate::horust::healthcheck::custom::{CustomCheck, CustomCheckParams, CustomChecks};
+
+    /// Healthy if the `lag` parameter is below `max-lag`.
+    struct LagCheck;
+
+    impl CustomCheck for LagCheck {
+        fn run(&self, _service: &Service, params: &CustomCheckParams) -> bool {
+            params["lag"].as_integer() < params["max-lag"].as_integer()
+        }
+
+        fn prepare(&self, _service: &Service, params: &CustomCheckParams) -> io::Result<()> {
+            match params.get("max-lag") {
+                Some(max_lag) if max_lag.is_integer() => Ok(()),
+                _ => Err(io::Error::other("max-lag must be an integer")),
+            }
+        }
+    }
+
+    fn healthiness(custom: &str) -> Healthiness {
+        toml::from_str(custom).unwrap()
+    }
+
+    #[test]
+    fn test_custom_checks() {
+        let mut checks = CustomChecks::default();
+        checks.register("lag".into(), Arc::new(LagCheck));
+        let service = Service::from_name("replica");
+
+        let healthy = healthiness("[custom.lag]\nlag = 1\nmax-lag = 10");
+        // The probe is scheduled, even without built-in checks.
+        assert!(healthy.has_any_check_defined());
+        checks.prepare(&service, &healthy).unwrap();
+        assert!(checks.run(&service, &healthy));
+
+        let unhealthy = healthiness("[custom.lag]\nlag = 11\nmax-lag = 10");
+        assert!(!checks.run(&service, &unhealthy));
+
+        let invalid = healthiness("[custom.lag]\nlag = 1\nmax-lag = \"10\"");
+        assert!(checks.prepare(&service, &invalid).is_err());
+
+        let unknown = healthiness("[custom.unknown]");
+        assert!(checks.prepare(&service, &unknown).is_err());
+        assert!(!checks.run(&service, &unknown));
+
+        assert!(checks.run(&service, &Healthiness::default()));
+    }
+}
@@ -0,0 +1,135 @@
+//! gRPC health checks: the `grpc.health.v1.Health/Check` RPC is sent to `grpc-endpoint`, and the
+//! service is healthy if it answers `SERVING`.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::runtime::Runtime;
+use tonic::transport::{Channel, Endpoint};
+use tonic_health::pb::HealthCheckRequest;
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::health_client::HealthClient;
+
+type Channels = Mutex<HashMap<(String, Duration), Channel>>;
+static CHANNELS: OnceLock<Channels> = OnceLock::new();
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+fn get_channels() -> &'static Channels {
+    CHANNELS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// The checks are blocking: the RPCs are run on the calling thread, a single worker drives the
+/// connections.
+fn get_runtime() -> &'static Runtime {
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("horust-grpc")
+            .enable_all()
+            .build()
+            .expect("Failed to build the gRPC runtime")
+    })
+}
+
+/// The connection is established on the first check, and re-established when it is lost.
+fn connect(endpoint: &str, timeout: Duration) -> io::Result<Channel> {
+    let endpoint = Endpoint::from_shared(endpoint.to_string())
+        .map_err(|error| io::Error::other(format!("Invalid grpc-endpoint {endpoint}: {error}")))?
+        .connect_timeout(timeout)
+        .timeout(timeout);
+    let _guard = get_runtime().enter();
+    Ok(endpoint.connect_lazy())
+}
+
+/// Validates `endpoint`, and prepares its channel.
+pub(super) fn prepare(endpoint: &str, timeout: Duration) -> io::Result<()> {
+    let channel = connect(endpoint, timeout)?;
+    get_channels()
+        .lock()
+        .unwrap()
+        .insert((endpoint.to_string(), timeout), channel);
+    Ok(())
+}
+
+/// Returns true if `service` (the server's overall health if None) is `SERVING`.
+pub(super) fn check(endpoint: &str, service: Option<&str>, timeout: Duration) -> bool {
+    let key = (endpoint.to_string(), timeout);
+    let channel = get_channels().lock().unwrap().get(&key).cloned();
+    let channel = match channel.map_or_else(|| connect(endpoint, timeout), Ok) {
+        Ok(channel) => channel,
+        Err(error) => {
+            error!("{error}");
+            return false;
+        }
+    };
+    let request = HealthCheckRequest {
+        service: service.unwrap_or_default().to_string(),
+    };
+    let response = get_runtime().block_on(HealthClient::new(channel).check(request));
+    match response {
+        Ok(response) => {
+            let status = response.into_inner().status();
+            if status != ServingStatus::Serving {
+                debug!(
+                    "gRPC health check to {endpoint} returned {}",
+                    status.as_str_name()
+                );
+            }
+            status == ServingStatus::Serving
+        }
+        Err(status) => {
+            debug!("gRPC health check to {endpoint} failed: {status}");
+            false
+        }
+    }
+}
+
+#[cfg(all(test, feature = "grpc-healthcheck-test-server"))]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddrV4, TcpListener};
+    use std::time::Duration;
+
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic_health::ServingStatus;
+
+    use crate::horust::healthcheck::grpc::{check, prepare};
+
+    #[test]
+    fn test_grpc_check() {
+        let server_runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        listener.set_nonblocking(true).unwrap();
+        server_runtime.block_on(async {
+            let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+            let (reporter, service) = tonic_health::server::health_reporter();
+            reporter
+                .set_service_status("ok.Service", ServingStatus::Serving)
+                .await;
+            reporter
+                .set_service_status("down.Service", ServingStatus::NotServing)
+                .await;
+            tokio::spawn(
+                tonic::transport::Server::builder()
+                    .add_service(service)
+                    .serve_with_incoming(TcpListenerStream::new(listener)),
+            );
+        });
+
+        let timeout = Duration::from_secs(5);
+        prepare(&endpoint, timeout).unwrap();
+        assert!(check(&endpoint, None, timeout));
+        assert!(check(&endpoint, Some("ok.Service"), timeout));
+        assert!(!check(&endpoint, Some("down.Service"), timeout));
+        assert!(!check(&endpoint, Some("unknown.Service"), timeout));
+        // Not prepared: connects on the first check.
+        let timeout = Duration::from_millis(500);
+        assert!(check(&endpoint, None, timeout));
+
+        assert!(prepare("not an endpoint", timeout).is_err());
+        drop(server_runtime);
+        assert!(!check(&endpoint, None, timeout));
+    }
+}
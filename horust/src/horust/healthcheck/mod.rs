@@ -1,157 +1,645 @@
-//! If a service has defined an healthchecker, this module will spawn a worker to making sure that
-//! the service is working as supposed to.
+//! If a service has defined an healthchecker, this module will periodically run its checks on a
+//! shared pool of threads, making sure that the service is working as supposed to.
 
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
-use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::Instant;
 
 use crossbeam::channel::{Receiver, RecvTimeoutError, Sender, unbounded};
 
 use checks::*;
+pub(crate) use custom::CustomChecks;
+pub use custom::{CustomCheck, CustomCheckParams};
 
 use crate::horust::bus::BusConnector;
 use crate::horust::formats::{
-    Event, Healthiness, HealthinessStatus, Service, ServiceName, ServiceStatus,
+    CommandCheckResult, Event, Healthiness, HealthinessStatus, Service, ServiceName, ServiceStatus,
 };
 
 mod checks;
+mod custom;
+#[cfg(feature = "grpc-healthcheck")]
+mod grpc;
+pub(crate) mod ready_pattern;
+mod resources;
 
-struct Worker {
-    service: Service,
-    bus: BusConnector<Event>,
-    work_done_notifier: Receiver<()>,
+/// The role of a set of checks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Probe {
+    /// The service is killed if these checks keep failing (`[healthiness]` or `[liveness]`).
+    Liveness,
+    /// The dependents of the service are not started until these checks pass (`[readiness]`).
+    Readiness,
 }
 
-impl Worker {
-    fn new(service: Service, bus: BusConnector<Event>, work_done_notifier: Receiver<()>) -> Self {
-        Worker {
-            service,
-            bus,
-            work_done_notifier,
+impl Probe {
+    fn healthiness(self, service: &Service) -> &Healthiness {
+        match self {
+            Probe::Liveness => &service.healthiness,
+            Probe::Readiness => &service.readiness,
         }
     }
-    pub fn spawn_thread(self) -> JoinHandle<()> {
-        thread::spawn(move || self.run())
-    }
-    fn run(self) {
-        loop {
-            let status = check_health(&self.service.healthiness);
-            self.bus.send_event(Event::HealthCheck(
-                self.service.name.clone(),
-                status.clone(),
-            ));
-            match self
-                .work_done_notifier
-                .recv_timeout(Duration::from_millis(1000))
-            {
-                Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
-                _ => (),
-            };
+
+    fn event(self, service_name: ServiceName, status: HealthinessStatus) -> Event {
+        match self {
+            Probe::Liveness => Event::HealthCheck(service_name, status),
+            Probe::Readiness => Event::ReadinessCheck(service_name, status),
         }
     }
 }
 
-// TODO:
-// * Tunable healthchecks timing in horust's config
-// * If there are no checks to run, just exit the thread. or go sleep until an "service created" event is received.
-pub fn spawn(bus: BusConnector<Event>, services: Vec<Service>) {
-    thread::spawn(move || {
-        run(bus, services);
-    });
+/// Default maximum amount of checks running at the same time.
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// What a job runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Task {
+    /// The checks of the probe.
+    Checks,
+    /// The `on-failure-command` of the probe, for at most the termination `wait` of the service.
+    OnFailureCommand,
 }
 
-/// Returns true if the service is healthy and all checks are passed.
-fn check_health(healthiness: &Healthiness) -> HealthinessStatus {
-    get_checks()
-        .into_iter()
-        .all(|check| check.run(healthiness))
-        .into()
-}
-
-fn run(bus: BusConnector<Event>, services: Vec<Service>) {
-    let mut workers = hashmap! {};
-    let get_service = |s_name: &ServiceName| {
-        services
-            .iter()
-            .filter(|sh| sh.name == *s_name)
-            .take(1)
-            .cloned()
-            .collect::<Vec<Service>>()
-            .remove(0)
-    };
+/// Runs the checks of a probe once, or its `on-failure-command`.
+struct Job {
+    task: Task,
+    key: ProbeKey,
+    generation: u64,
+    service: Arc<Service>,
+    state: ProbeState,
+    /// Set by the first of the worker finishing the checks and the scheduler abandoning them, so
+    /// that exactly one of them knows that the worker has been replaced.
+    finished: Arc<AtomicBool>,
+}
 
-    for ev in bus.iter() {
-        match ev {
-            Event::StatusChanged(s_name, ServiceStatus::Started) => {
-                let service = get_service(&s_name);
-                if !service.healthiness.has_any_check_defined() {
-                    bus.send_event(Event::HealthCheck(s_name, HealthinessStatus::Healthy));
+/// The inputs of the scheduler: the events of the bus, and the results of the checks.
+enum Input {
+    Event(Event),
+    /// A worker has started running the checks: they have to be done within the timeout.
+    Started {
+        key: ProbeKey,
+        generation: u64,
+    },
+    Checked {
+        key: ProbeKey,
+        generation: u64,
+        status: HealthinessStatus,
+    },
+    /// A line of the output of the service has matched the `ready-pattern` of the probe.
+    PatternMatched(ProbeKey),
+    /// The `on-failure-command` of the service is done, if it had one.
+    FailureCommandDone(ServiceName, Option<CommandCheckResult>),
+}
+
+type ProbeKey = (ServiceName, Probe);
+
+/// A probe of a started service.
+struct ScheduledProbe {
+    /// Identifies the run of the service, results of the previous runs are discarded.
+    generation: u64,
+    reporter: Reporter,
+    /// Given to the checks at each run.
+    state: ProbeState,
+    /// None while the checks are running, unless they have timed out.
+    next_run: Option<Instant>,
+    /// The checks sent to the pool, until their result is received.
+    running: Option<RunningChecks>,
+}
+
+/// Checks of a probe, sent to the pool.
+struct RunningChecks {
+    /// Shared with the job.
+    finished: Arc<AtomicBool>,
+    /// Set once a worker has started running them.
+    deadline: Option<Instant>,
+    /// They were still running at the deadline, and have been reported as failed.
+    timed_out: bool,
+}
+
+/// The threads running the checks. A thread stuck running checks which have been abandoned is
+/// replaced by a new one, and exits once they are done.
+struct Pool {
+    jobs: Sender<Job>,
+    pending_jobs: Receiver<Job>,
+    results: Sender<Input>,
+    custom_checks: CustomChecks,
+}
+
+impl Pool {
+    fn new(concurrency: usize, results: Sender<Input>, custom_checks: CustomChecks) -> Self {
+        let (jobs, pending_jobs) = unbounded();
+        let pool = Self {
+            jobs,
+            pending_jobs,
+            results,
+            custom_checks,
+        };
+        for _ in 0..concurrency {
+            pool.spawn_worker();
+        }
+        pool
+    }
+
+    fn spawn_worker(&self) {
+        let pending_jobs = self.pending_jobs.clone();
+        let results = self.results.clone();
+        let custom_checks = self.custom_checks.clone();
+        thread::spawn(move || {
+            for job in pending_jobs {
+                // Abandoned before being started: another worker has already replaced this one.
+                if job.finished.load(Ordering::SeqCst) {
+                    break;
+                }
+                if job.task == Task::OnFailureCommand {
+                    let result = job
+                        .state
+                        .run_on_failure_command(job.service.termination.wait);
+                    if results
+                        .send(Input::FailureCommandDone(job.key.0, result))
+                        .is_err()
+                    {
+                        break;
+                    }
                     continue;
                 }
-                if let Some((sender, handler)) = workers.remove(&s_name) {
-                    stop_worker(sender, handler)
+                let started = Input::Started {
+                    key: job.key.clone(),
+                    generation: job.generation,
+                };
+                if results.send(started).is_err() {
+                    break;
+                }
+                let healthiness = job.key.1.healthiness(&job.service);
+                let status = check_health(&job.service, healthiness, &job.state, &custom_checks);
+                let replaced = job.finished.swap(true, Ordering::SeqCst);
+                let result = Input::Checked {
+                    key: job.key,
+                    generation: job.generation,
+                    status,
+                };
+                if results.send(result).is_err() || replaced {
+                    break;
                 }
-                let (worker_notifier, work_done_rcv) = unbounded();
-                let handle = Worker::new(service, bus.join_bus(), work_done_rcv).spawn_thread();
-                workers.insert(s_name, (worker_notifier, handle));
             }
-            Event::ServiceExited(s_name, _exit_code) => {
-                if let Some((sender, handler)) = workers.remove(&s_name) {
-                    stop_worker(sender, handler)
-                } else {
-                    warn!("Worker thread for {} not found.", s_name);
+        });
+    }
+
+    /// Stops waiting for the checks: if they are still running, a new worker takes the place of
+    /// the one running them. Returns false if they were already done.
+    fn abandon(&self, running: &RunningChecks) -> bool {
+        let abandoned = !running.finished.swap(true, Ordering::SeqCst);
+        if abandoned {
+            self.spawn_worker();
+        }
+        abandoned
+    }
+}
+
+/// Runs the checks of every started service at their interval, using a fixed pool of threads.
+/// At most one run of the checks of a probe is in flight, so a slow probe delays neither itself
+/// nor the other services' ones beyond the pool being busy. Checks still running after `timeout`
+/// are reported as failed, and keep failing at each interval until they are done, without taking
+/// the place of the other services' checks in the pool.
+struct Scheduler<'a> {
+    bus: &'a BusConnector<Event>,
+    services: HashMap<ServiceName, Arc<Service>>,
+    probes: HashMap<ProbeKey, ScheduledProbe>,
+    /// The `ready-pattern`s matched by the current run of the services. They can match before the
+    /// probe is scheduled.
+    matched_patterns: HashSet<ProbeKey>,
+    /// The services whose `on-failure-command` is running: they are killed once it is done.
+    failure_commands: HashSet<ServiceName>,
+    next_generation: u64,
+    pool: Pool,
+}
+
+impl<'a> Scheduler<'a> {
+    fn new(bus: &'a BusConnector<Event>, services: Vec<Service>, pool: Pool) -> Self {
+        Self {
+            bus,
+            services: services
+                .into_iter()
+                .map(|service| (service.name.clone(), Arc::new(service)))
+                .collect(),
+            probes: HashMap::new(),
+            matched_patterns: HashSet::new(),
+            failure_commands: HashSet::new(),
+            next_generation: 0,
+            pool,
+        }
+    }
+
+    fn run(mut self, inputs: Receiver<Input>) {
+        loop {
+            let input = match self.run_due_probes(Instant::now()) {
+                Some(next_run) => {
+                    match inputs.recv_timeout(next_run.saturating_duration_since(Instant::now())) {
+                        Ok(input) => input,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                None => match inputs.recv() {
+                    Ok(input) => input,
+                    Err(_) => break,
+                },
+            };
+            match input {
+                Input::Event(Event::ShuttingDownInitiated(_)) => {
+                    // Nobody would kill them once their command is done.
+                    for service_name in self.failure_commands.drain() {
+                        self.bus.send_event(Event::Kill(service_name));
+                    }
+                    break;
+                }
+                Input::Event(event) => self.handle_event(event),
+                Input::Started { key, generation } => self.handle_started(key, generation),
+                Input::Checked {
+                    key,
+                    generation,
+                    status,
+                } => self.handle_result(key, generation, status),
+                Input::PatternMatched(key) => self.handle_pattern_matched(key),
+                Input::FailureCommandDone(service_name, result) => {
+                    self.handle_failure_command_done(service_name, result)
                 }
             }
-            Event::ShuttingDownInitiated(_) => {
-                // Stop all the workers:
-                for (ws, _wh) in workers.values() {
-                    // TODO: handle these
-                    ws.send(()).unwrap();
+        }
+    }
+
+    /// Sends the due probes to the pool, times out the running ones, and returns when the next
+    /// one of them is due.
+    fn run_due_probes(&mut self, now: Instant) -> Option<Instant> {
+        let mut next = None;
+        let mut update_next = |at: Instant| {
+            next = Some(next.map_or(at, |next: Instant| next.min(at)));
+        };
+        for (key, probe) in &mut self.probes {
+            let healthiness = key.1.healthiness(&self.services[&key.0]);
+            if let Some(running) = &mut probe.running {
+                match running.deadline {
+                    Some(deadline) if deadline <= now => {
+                        running.deadline = None;
+                        // Otherwise, the checks have just finished, and their result is on its way.
+                        if self.pool.abandon(running) {
+                            warn!(
+                                "{}: the checks are still running after {:?}, they are considered failed.",
+                                key.0,
+                                healthiness.timeout()
+                            );
+                            running.timed_out = true;
+                            probe.next_run = Some(now);
+                        }
+                    }
+                    Some(deadline) => update_next(deadline),
+                    None => (),
                 }
-                // Actually wait for them
-                for (_s_name, (_ws, wh)) in workers {
-                    wh.join().unwrap();
+            }
+            let Some(next_run) = probe.next_run else {
+                continue;
+            };
+            if next_run > now {
+                update_next(next_run);
+                continue;
+            }
+            if probe.running.is_some() {
+                // The checks which timed out are still running: they fail again.
+                if let Some(status) = probe.reporter.report(HealthinessStatus::Unhealthy, now) {
+                    self.bus.send_event(key.1.event(key.0.clone(), status));
+                }
+                probe.next_run = Some(now + healthiness.interval());
+                update_next(now + healthiness.interval());
+                continue;
+            }
+            probe.next_run = None;
+            probe.state.pattern_matched = self.matched_patterns.contains(key);
+            let finished = Arc::new(AtomicBool::new(false));
+            probe.running = Some(RunningChecks {
+                finished: finished.clone(),
+                deadline: None,
+                timed_out: false,
+            });
+            let job = Job {
+                task: Task::Checks,
+                key: key.clone(),
+                generation: probe.generation,
+                service: self.services[&key.0].clone(),
+                state: probe.state.clone(),
+                finished,
+            };
+            if let Err(error) = self.pool.jobs.send(job) {
+                error!(
+                    "Cannot send the health check of {} to the pool: {error}",
+                    key.0
+                );
+            }
+        }
+        next
+    }
+
+    /// The running checks of the probe are abandoned, if any.
+    fn remove_probe(&mut self, key: &ProbeKey) {
+        if let Some(running) = self.probes.remove(key).and_then(|probe| probe.running) {
+            self.pool.abandon(&running);
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::StatusChanged(s_name, ServiceStatus::Started) => {
+                let Some(service) = self.services.get(&s_name).cloned() else {
+                    warn!("Service {} not found.", s_name);
+                    return;
+                };
+                for probe in [Probe::Liveness, Probe::Readiness] {
+                    let key = (s_name.clone(), probe);
+                    self.remove_probe(&key);
+                    let healthiness = probe.healthiness(&service);
+                    if !healthiness.has_any_check_defined() {
+                        // Without liveness checks, a service is healthy as soon as it is running.
+                        if probe == Probe::Liveness {
+                            self.bus.send_event(
+                                probe.event(s_name.clone(), HealthinessStatus::Healthy),
+                            );
+                        }
+                        continue;
+                    }
+                    let now = Instant::now();
+                    self.next_generation += 1;
+                    self.probes.insert(
+                        key,
+                        ScheduledProbe {
+                            generation: self.next_generation,
+                            reporter: Reporter::new(healthiness, now),
+                            state: ProbeState::new(&service, healthiness),
+                            next_run: Some(now),
+                            running: None,
+                        },
+                    );
+                }
+            }
+            Event::RunOnFailureCommand(s_name) => self.run_failure_command(s_name),
+            Event::PidChanged(s_name, pid, _) => resources::set_pid(&s_name, pid),
+            Event::ServiceExited(s_name, ..) => {
+                // Removing the probes drops their state.
+                resources::forget(&s_name);
+                self.failure_commands.remove(&s_name);
+                for probe in [Probe::Liveness, Probe::Readiness] {
+                    self.remove_probe(&(s_name.clone(), probe));
+                    self.matched_patterns.remove(&(s_name.clone(), probe));
                 }
-                break;
             }
             _ => {}
         }
     }
+
+    fn handle_started(&mut self, key: ProbeKey, generation: u64) {
+        let timeout = key.1.healthiness(&self.services[&key.0]).timeout();
+        if let Some(running) = self
+            .probes
+            .get_mut(&key)
+            .filter(|probe| probe.generation == generation)
+            .and_then(|probe| probe.running.as_mut())
+        {
+            running.deadline = Some(Instant::now() + timeout);
+        }
+    }
+
+    fn handle_result(&mut self, key: ProbeKey, generation: u64, status: HealthinessStatus) {
+        let Some(probe) = self
+            .probes
+            .get_mut(&key)
+            .filter(|probe| probe.generation == generation)
+        else {
+            // The service exited, or was restarted, while the checks were running.
+            return;
+        };
+        if let Some(result) = probe.state.command_result.lock().unwrap().take() {
+            self.bus
+                .send_event(Event::CommandChecked(key.0.clone(), result));
+        }
+        if probe
+            .running
+            .take()
+            .is_some_and(|running| running.timed_out)
+        {
+            // Their failure has already been reported.
+            return;
+        }
+        let now = Instant::now();
+        if let Some(status) = probe.reporter.report(status, now) {
+            self.bus.send_event(key.1.event(key.0.clone(), status));
+        }
+        let service = &self.services[&key.0];
+        // The pattern has matched while the checks were running.
+        probe.next_run = if !probe.state.pattern_matched && self.matched_patterns.contains(&key) {
+            Some(now)
+        } else {
+            Some(now + key.1.healthiness(service).interval())
+        };
+    }
+
+    /// Sends the `on-failure-command` of the liveness probe to the pool. The service is killed
+    /// right away if it has already exited.
+    fn run_failure_command(&mut self, service_name: ServiceName) {
+        let key = (service_name.clone(), Probe::Liveness);
+        let Some(probe) = self.probes.get(&key) else {
+            self.bus.send_event(Event::Kill(service_name));
+            return;
+        };
+        let job = Job {
+            task: Task::OnFailureCommand,
+            key,
+            generation: probe.generation,
+            service: self.services[&service_name].clone(),
+            state: probe.state.clone(),
+            finished: Arc::new(AtomicBool::new(false)),
+        };
+        match self.pool.jobs.send(job) {
+            Ok(()) => {
+                self.failure_commands.insert(service_name);
+            }
+            Err(error) => {
+                error!("Cannot send the on-failure-command of {service_name} to the pool: {error}");
+                self.bus.send_event(Event::Kill(service_name));
+            }
+        }
+    }
+
+    fn handle_failure_command_done(
+        &mut self,
+        service_name: ServiceName,
+        result: Option<CommandCheckResult>,
+    ) {
+        if let Some(result) = result {
+            info!(
+                "{service_name}: `{}` exited with code {:?}: {}",
+                result.command, result.exit_code, result.output
+            );
+        }
+        // Otherwise, the service has exited in the meantime.
+        if self.failure_commands.remove(&service_name) {
+            self.bus.send_event(Event::Kill(service_name));
+        }
+    }
+
+    /// Runs the probe right away, instead of waiting for its interval.
+    fn handle_pattern_matched(&mut self, key: ProbeKey) {
+        if let Some(probe) = self.probes.get_mut(&key)
+            && probe.running.is_none()
+        {
+            probe.next_run = Some(Instant::now());
+        }
+        self.matched_patterns.insert(key);
+    }
 }
 
-fn stop_worker(sender: Sender<()>, handler: JoinHandle<()>) {
-    if let Err(error) = sender.send(()) {
-        error!(
-            "Cannot send msg to sender - channel might be closed. Error: {:?}",
-            error
-        );
+/// Decides which check results are sent to the supervisor, based on `start-period` and
+/// `success-threshold`.
+struct Reporter {
+    start_period_end: Option<Instant>,
+    success_threshold: u32,
+    successes_in_a_row: u32,
+}
+
+impl Reporter {
+    fn new(healthiness: &Healthiness, started_at: Instant) -> Self {
+        Self {
+            start_period_end: Some(started_at + healthiness.start_period()),
+            success_threshold: healthiness.success_threshold(),
+            successes_in_a_row: 0,
+        }
     }
-    if let Err(error) = handler.join() {
-        error!("Error joining thread: {:?}", error);
+
+    fn report(&mut self, status: HealthinessStatus, now: Instant) -> Option<HealthinessStatus> {
+        match status {
+            HealthinessStatus::Healthy => {
+                self.successes_in_a_row += 1;
+                if self.successes_in_a_row < self.success_threshold {
+                    return None;
+                }
+                // As in Docker, the start period ends with the first success.
+                self.start_period_end = None;
+            }
+            HealthinessStatus::Unhealthy => {
+                self.successes_in_a_row = 0;
+                if self.start_period_end.is_some_and(|end| now < end) {
+                    return None;
+                }
+                self.start_period_end = None;
+            }
+        }
+        Some(status)
     }
 }
 
-/// Setup require for the service, before running the healthchecks and starting the service
-pub fn prepare_service(healthiness: &Healthiness) -> Result<Vec<()>, std::io::Error> {
+pub fn spawn(
+    bus: BusConnector<Event>,
+    services: Vec<Service>,
+    concurrency: usize,
+    custom_checks: CustomChecks,
+) {
+    thread::spawn(move || {
+        run(bus, services, concurrency, &custom_checks);
+    });
+}
+
+/// Returns true if the service is healthy and all checks are passed.
+fn check_health(
+    service: &Service,
+    healthiness: &Healthiness,
+    state: &ProbeState,
+    custom_checks: &CustomChecks,
+) -> HealthinessStatus {
+    (get_checks()
+        .into_iter()
+        .all(|check| check.run(service, healthiness, state))
+        && custom_checks.run(service, healthiness))
+    .into()
+}
+
+fn run(
+    bus: BusConnector<Event>,
+    services: Vec<Service>,
+    concurrency: usize,
+    custom_checks: &CustomChecks,
+) {
+    let (inputs_sender, inputs) = unbounded();
+    ready_pattern::set_scheduler(Some(inputs_sender.clone()));
+    let pool = Pool::new(concurrency, inputs_sender.clone(), custom_checks.clone());
+    thread::scope(|scope| {
+        let bus = &bus;
+        scope.spawn(move || {
+            for event in bus.iter() {
+                let shutting_down = matches!(event, Event::ShuttingDownInitiated(_));
+                if inputs_sender.send(Input::Event(event)).is_err() || shutting_down {
+                    break;
+                }
+            }
+        });
+        // Returning drops the pool: its threads stop once their running checks are done.
+        Scheduler::new(bus, services, pool).run(inputs);
+    });
+    ready_pattern::set_scheduler(None);
+}
+
+/// Setup required for the service, before running the liveness and readiness checks and starting
+/// the service.
+pub fn prepare_service(
+    service: &Service,
+    custom_checks: &CustomChecks,
+) -> Result<(), std::io::Error> {
+    prepare_checks(service, &service.healthiness, custom_checks)?;
+    prepare_checks(service, &service.readiness, custom_checks)
+}
+
+fn prepare_checks(
+    service: &Service,
+    healthiness: &Healthiness,
+    custom_checks: &CustomChecks,
+) -> Result<(), std::io::Error> {
     get_checks()
         .iter()
-        .map(|check| check.prepare(healthiness))
-        .collect()
+        .try_for_each(|check| check.prepare(service, healthiness))?;
+    custom_checks.prepare(service, healthiness)
 }
 
 #[cfg(test)]
 mod test {
+    use std::collections::{BTreeMap, HashMap};
     use std::io::{Read, Write};
     use std::net::{Ipv4Addr, SocketAddrV4, TcpListener};
-    use std::sync::mpsc;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::net::UnixListener;
+    use std::sync::{Arc, mpsc};
     use std::thread;
-    use std::time::Duration;
+    use std::time::{Duration, Instant, SystemTime};
 
     use anyhow::Result;
+    use nix::sys::socket::{self, AddressFamily, Backlog, SockFlag, SockType, UnixAddr};
     use tempfile::TempDir;
 
-    use crate::horust::formats::{Healthiness, HealthinessStatus};
-    use crate::horust::healthcheck::{check_health, prepare_service};
+    use crate::horust::bus::Bus;
+    use crate::horust::formats::{
+        Event, Healthiness, HealthinessStatus, HttpHealthiness, Service, ServiceStatus,
+        ShuttingDown, StatusRange,
+    };
+    use crate::horust::healthcheck::{
+        CustomCheck, CustomCheckParams, CustomChecks, Pool, Probe, ProbeState, Reporter, Scheduler,
+        check_health, prepare_checks, run,
+    };
+
+    fn service() -> Service {
+        Service::from_name("svc")
+    }
+
+    /// Runs the checks once, as the pool does.
+    fn check(service: &Service, healthiness: &Healthiness) -> HealthinessStatus {
+        let state = ProbeState::new(service, healthiness);
+        check_health(service, healthiness, &state, &CustomChecks::default())
+    }
 
     #[test]
     fn test_healthiness_check_file() -> Result<()> {
@@ -162,11 +650,30 @@ mod test {
             http_endpoint: None,
             ..Default::default()
         };
-        assert_ne!(check_health(&healthiness), HealthinessStatus::Healthy);
+        assert_ne!(check(&service(), &healthiness), HealthinessStatus::Healthy);
         std::fs::write(file_path, "Hello world!")?;
-        assert_eq!(check_health(&healthiness), HealthinessStatus::Healthy);
+        assert_eq!(check(&service(), &healthiness), HealthinessStatus::Healthy);
         let healthiness: Healthiness = Default::default();
-        assert_eq!(check_health(&healthiness), HealthinessStatus::Healthy);
+        assert_eq!(check(&service(), &healthiness), HealthinessStatus::Healthy);
+        Ok(())
+    }
+
+    #[test]
+    fn test_healthiness_heartbeat_file() -> Result<()> {
+        let tempdir = TempDir::with_prefix("health")?;
+        let heartbeat_file = tempdir.path().join("heartbeat");
+        let healthiness = Healthiness {
+            heartbeat_file: Some(heartbeat_file.clone()),
+            max_age: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        assert_ne!(check(&service(), &healthiness), HealthinessStatus::Healthy);
+        let file = std::fs::File::create(&heartbeat_file)?;
+        assert_eq!(check(&service(), &healthiness), HealthinessStatus::Healthy);
+        file.set_modified(SystemTime::now() - Duration::from_secs(120))?;
+        assert_ne!(check(&service(), &healthiness), HealthinessStatus::Healthy);
+        file.set_modified(SystemTime::now())?;
+        assert_eq!(check(&service(), &healthiness), HealthinessStatus::Healthy);
         Ok(())
     }
 
@@ -175,21 +682,29 @@ mod test {
             info!("Received request");
             let mut buffer = [0; 512];
             let mut stream = stream?;
-            stream.read(&mut buffer).unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
             let response = b"HTTP/1.1 200 OK\r\n\r\n";
-            stream.write(response).expect("Stream write");
+            stream.write_all(response).expect("Stream write");
         }
         Ok(())
     }
 
     #[test]
+    #[cfg(feature = "http-healthcheck")]
     fn test_healthiness_http() -> Result<()> {
         let healthiness = Healthiness {
             file_path: None,
             http_endpoint: Some("http://localhost:123/".into()),
             ..Default::default()
         };
-        assert_ne!(check_health(&healthiness), HealthinessStatus::Healthy);
+        assert_ne!(check(&service(), &healthiness), HealthinessStatus::Healthy);
         let loopback = Ipv4Addr::new(127, 0, 0, 1);
         let socket = SocketAddrV4::new(loopback, 0);
         let listener = TcpListener::bind(socket)?;
@@ -205,11 +720,85 @@ mod test {
             handle_request(listener).unwrap();
             sender.send(()).expect("Chan closed");
         });
-        assert_eq!(check_health(&healthiness), HealthinessStatus::Healthy);
+        assert_eq!(check(&service(), &healthiness), HealthinessStatus::Healthy);
         receiver
             .recv_timeout(Duration::from_millis(2000))
             .expect("Failed to received response from handle_request");
-        assert_ne!(check_health(&healthiness), HealthinessStatus::Healthy);
+        assert_ne!(check(&service(), &healthiness), HealthinessStatus::Healthy);
+        Ok(())
+    }
+
+    /// Answers every request with `response`, and returns the received requests.
+    fn serve_http(listener: TcpListener, response: &'static str) -> mpsc::Receiver<String> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buffer = [0; 1024];
+                let read = stream.read(&mut buffer).unwrap();
+                stream.write_all(response.as_bytes()).expect("Stream write");
+                let request = String::from_utf8_lossy(&buffer[..read]).to_string();
+                if sender.send(request).is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+
+    #[test]
+    #[cfg(feature = "http-healthcheck")]
+    fn test_healthiness_http_options() -> Result<()> {
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))?;
+        let endpoint = format!("http://localhost:{}/health", listener.local_addr()?.port());
+        let requests = serve_http(
+            listener,
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 21\r\n\r\n{\"status\":\"degraded\"}",
+        );
+        let mut healthiness = Healthiness {
+            http_endpoint: Some(endpoint),
+            http: HttpHealthiness {
+                headers: BTreeMap::from([("X-Token".to_string(), "secret".to_string())]),
+                body_contains: Some("degraded".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        prepare_checks(&service(), &healthiness, &CustomChecks::default())?;
+        // 503 is not accepted by default.
+        assert_eq!(
+            check(&service(), &healthiness),
+            HealthinessStatus::Unhealthy
+        );
+        let request = requests.recv_timeout(Duration::from_secs(2))?;
+        assert!(request.starts_with("GET /health "), "{request}");
+        assert!(
+            request.to_lowercase().contains("x-token: secret"),
+            "{request}"
+        );
+
+        healthiness.http.status = vec![
+            StatusRange {
+                start: 200,
+                end: 299,
+            },
+            StatusRange {
+                start: 503,
+                end: 503,
+            },
+        ];
+        prepare_checks(&service(), &healthiness, &CustomChecks::default())?;
+        assert_eq!(check(&service(), &healthiness), HealthinessStatus::Healthy);
+
+        healthiness.http.body_regex = Some(r#""status":\s*"ok""#.into());
+        prepare_checks(&service(), &healthiness, &CustomChecks::default())?;
+        assert_eq!(
+            check(&service(), &healthiness),
+            HealthinessStatus::Unhealthy
+        );
+
+        healthiness.http.body_regex = Some("not a [regex".into());
+        assert!(prepare_checks(&service(), &healthiness, &CustomChecks::default()).is_err());
         Ok(())
     }
 
@@ -223,12 +812,361 @@ mod test {
             command: Some(format!("cat {}", file_path.to_str().unwrap())),
             ..Default::default()
         };
-        prepare_service(&healthiness)?;
-        assert_ne!(check_health(&healthiness), HealthinessStatus::Healthy);
+        prepare_checks(&service(), &healthiness, &CustomChecks::default())?;
+        assert_ne!(check(&service(), &healthiness), HealthinessStatus::Healthy);
         std::fs::write(&file_path, "Hello world!")?;
-        assert_eq!(check_health(&healthiness), HealthinessStatus::Healthy);
+        assert_eq!(check(&service(), &healthiness), HealthinessStatus::Healthy);
         let healthiness: Healthiness = Default::default();
-        assert_eq!(check_health(&healthiness), HealthinessStatus::Healthy);
+        assert_eq!(check(&service(), &healthiness), HealthinessStatus::Healthy);
         Ok(())
     }
+
+    #[test]
+    fn test_healthiness_command_context() -> Result<()> {
+        let tempdir = TempDir::with_prefix("health")?;
+        let mut service = Service::from_name("command_context");
+        service.working_directory = tempdir.path().to_path_buf();
+        service.environment.additional = HashMap::from([("FOO".into(), "foo".into())]);
+        let healthiness = Healthiness {
+            command: Some(r#"sh -c 'echo "$FOO $BAR"; pwd; echo failed >&2; exit 3'"#.into()),
+            command_env: BTreeMap::from([("BAR".into(), "bar".into())]),
+            ..Default::default()
+        };
+        prepare_checks(&service, &healthiness, &CustomChecks::default())?;
+        let state = ProbeState::new(&service, &healthiness);
+        let status = check_health(&service, &healthiness, &state, &CustomChecks::default());
+        assert_eq!(status, HealthinessStatus::Unhealthy);
+        let result = state.command_result.lock().unwrap().take().unwrap();
+        assert_eq!(result.exit_code, Some(3));
+        assert_eq!(
+            result.output,
+            format!("foo bar\n{}\nfailed", tempdir.path().display())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_healthiness_tcp() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let endpoint = listener.local_addr()?.to_string();
+        let healthiness = Healthiness {
+            tcp_endpoint: Some(endpoint.clone()),
+            ..Default::default()
+        };
+        assert_eq!(check(&service(), &healthiness), HealthinessStatus::Healthy);
+        drop(listener);
+        assert_ne!(check(&service(), &healthiness), HealthinessStatus::Healthy);
+        let healthiness = Healthiness {
+            tcp_endpoint: Some("not-a-valid-endpoint".into()),
+            ..Default::default()
+        };
+        assert_ne!(check(&service(), &healthiness), HealthinessStatus::Healthy);
+        Ok(())
+    }
+
+    #[test]
+    fn test_healthiness_unix_socket() -> Result<()> {
+        let tempdir = TempDir::with_prefix("health")?;
+        let socket_path = tempdir.path().join("service.sock");
+        let healthiness = Healthiness {
+            unix_socket: Some(socket_path.clone()),
+            ..Default::default()
+        };
+        assert_ne!(check(&service(), &healthiness), HealthinessStatus::Healthy);
+        let listener = UnixListener::bind(&socket_path)?;
+        assert_eq!(check(&service(), &healthiness), HealthinessStatus::Healthy);
+        // The socket file is left behind, but nobody is listening anymore.
+        drop(listener);
+        assert_ne!(check(&service(), &healthiness), HealthinessStatus::Healthy);
+        Ok(())
+    }
+
+    #[test]
+    fn test_healthiness_unix_socket_not_accepting() -> Result<()> {
+        let tempdir = TempDir::with_prefix("health")?;
+        let socket_path = tempdir.path().join("service.sock");
+        let healthiness = Healthiness {
+            unix_socket: Some(socket_path.clone()),
+            timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+        // The connections are never accepted: with a backlog of 0, the second one has to wait.
+        let listener = socket::socket(
+            AddressFamily::Unix,
+            SockType::Stream,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        socket::bind(listener.as_raw_fd(), &UnixAddr::new(&socket_path)?)?;
+        socket::listen(&listener, Backlog::new(0)?)?;
+        assert_eq!(check(&service(), &healthiness), HealthinessStatus::Healthy);
+        let started = Instant::now();
+        assert_eq!(
+            check(&service(), &healthiness),
+            HealthinessStatus::Unhealthy
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_healthiness_command_timeout() -> Result<()> {
+        let healthiness = Healthiness {
+            command: Some("sleep 10".into()),
+            timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        prepare_checks(&service(), &healthiness, &CustomChecks::default())?;
+        let started = Instant::now();
+        assert_ne!(check(&service(), &healthiness), HealthinessStatus::Healthy);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // The process left in the background keeps the output open: only the command is waited.
+        let healthiness = Healthiness {
+            command: Some("sh -c 'sleep 10 & echo started'".into()),
+            timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        assert_eq!(check(&service(), &healthiness), HealthinessStatus::Healthy);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reporter_start_period() {
+        let healthiness = Healthiness {
+            start_period: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let mut reporter = Reporter::new(&healthiness, start);
+        // Failures during the start period are not reported.
+        assert_eq!(reporter.report(HealthinessStatus::Unhealthy, start), None);
+        let after_start_period = start + Duration::from_secs(11);
+        assert_eq!(
+            reporter.report(HealthinessStatus::Unhealthy, after_start_period),
+            Some(HealthinessStatus::Unhealthy)
+        );
+
+        // The start period ends with the first success.
+        let mut reporter = Reporter::new(&healthiness, start);
+        assert_eq!(
+            reporter.report(HealthinessStatus::Healthy, start),
+            Some(HealthinessStatus::Healthy)
+        );
+        assert_eq!(
+            reporter.report(HealthinessStatus::Unhealthy, start),
+            Some(HealthinessStatus::Unhealthy)
+        );
+    }
+
+    #[test]
+    fn test_reporter_success_threshold() {
+        let healthiness = Healthiness {
+            success_threshold: Some(2),
+            ..Default::default()
+        };
+        let now = Instant::now();
+        let mut reporter = Reporter::new(&healthiness, now);
+        assert_eq!(reporter.report(HealthinessStatus::Healthy, now), None);
+        assert_eq!(
+            reporter.report(HealthinessStatus::Unhealthy, now),
+            Some(HealthinessStatus::Unhealthy)
+        );
+        // Successes need to be in a row.
+        assert_eq!(reporter.report(HealthinessStatus::Healthy, now), None);
+        assert_eq!(
+            reporter.report(HealthinessStatus::Healthy, now),
+            Some(HealthinessStatus::Healthy)
+        );
+    }
+
+    #[test]
+    fn test_scheduler() -> Result<()> {
+        let tempdir = TempDir::with_prefix("health")?;
+        let file_path = tempdir.path().join("file.txt");
+        std::fs::write(&file_path, "Hello world!")?;
+        let interval = Some(Duration::from_millis(10));
+        let mut healthy = Service::from_name("healthy");
+        healthy.healthiness = Healthiness {
+            file_path: Some(file_path),
+            interval,
+            ..Default::default()
+        };
+        let mut unhealthy = Service::from_name("unhealthy");
+        unhealthy.healthiness = Healthiness {
+            file_path: Some(tempdir.path().join("missing.txt")),
+            interval,
+            ..Default::default()
+        };
+        let no_checks = Service::from_name("no_checks");
+
+        let bus = Bus::new();
+        let test_bus = bus.join_bus();
+        let healthcheck_bus = bus.join_bus();
+        thread::spawn(move || bus.run());
+        let (stopped_sender, stopped) = mpsc::channel();
+        let services = vec![healthy, unhealthy, no_checks];
+        thread::spawn(move || {
+            // A single thread runs the checks of all the services.
+            run(healthcheck_bus, services, 1, &CustomChecks::default());
+            stopped_sender.send(()).unwrap();
+        });
+
+        for name in ["healthy", "unhealthy", "no_checks"] {
+            test_bus.send_event(Event::new_status_changed(name, ServiceStatus::Started));
+        }
+        let mut statuses = HashMap::new();
+        for event in test_bus.iter() {
+            if let Event::HealthCheck(name, status) = event {
+                statuses.insert(name, status);
+            }
+            if statuses.len() == 3 {
+                break;
+            }
+        }
+        assert_eq!(statuses["healthy"], HealthinessStatus::Healthy);
+        assert_eq!(statuses["unhealthy"], HealthinessStatus::Unhealthy);
+        assert_eq!(statuses["no_checks"], HealthinessStatus::Healthy);
+
+        // The checks of an exited service are not run anymore.
+        test_bus.send_event(Event::new_service_exited("unhealthy".into(), 1, None));
+        thread::sleep(Duration::from_millis(100));
+        test_bus.try_get_events();
+        thread::sleep(Duration::from_millis(100));
+        let events = test_bus.try_get_events();
+        assert!(events.contains(&Event::HealthCheck(
+            "healthy".into(),
+            HealthinessStatus::Healthy
+        )));
+        assert!(!events.iter().any(|event| matches!(
+            event,
+            Event::HealthCheck(name, _) if name == "unhealthy"
+        )));
+
+        test_bus.send_event(Event::ShuttingDownInitiated(ShuttingDown::Gracefully));
+        stopped.recv_timeout(Duration::from_secs(5))?;
+        Ok(())
+    }
+
+    /// Never returns, until the sender of `release` is dropped.
+    struct StuckCheck {
+        release: crossbeam::channel::Receiver<()>,
+    }
+
+    impl CustomCheck for StuckCheck {
+        fn run(&self, _service: &Service, _params: &CustomCheckParams) -> bool {
+            let _ = self.release.recv();
+            true
+        }
+    }
+
+    #[test]
+    fn test_scheduler_timeout() -> Result<()> {
+        let tempdir = TempDir::with_prefix("health")?;
+        let file_path = tempdir.path().join("file.txt");
+        std::fs::write(&file_path, "Hello world!")?;
+        let mut healthy = Service::from_name("healthy");
+        healthy.healthiness = Healthiness {
+            file_path: Some(file_path),
+            interval: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        let mut stuck = Service::from_name("stuck");
+        stuck.healthiness = Healthiness {
+            custom: BTreeMap::from([("stuck".to_string(), Default::default())]),
+            interval: Some(Duration::from_millis(10)),
+            timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let (release, released) = crossbeam::channel::unbounded();
+        let mut custom_checks = CustomChecks::default();
+        custom_checks.register("stuck".into(), Arc::new(StuckCheck { release: released }));
+
+        let bus = Bus::new();
+        let test_bus = bus.join_bus();
+        let healthcheck_bus = bus.join_bus();
+        thread::spawn(move || bus.run());
+        let (stopped_sender, stopped) = mpsc::channel();
+        let services = vec![stuck, healthy];
+        thread::spawn(move || {
+            // The only thread of the pool gets stuck.
+            run(healthcheck_bus, services, 1, &custom_checks);
+            stopped_sender.send(()).unwrap();
+        });
+        test_bus.send_event(Event::new_status_changed("stuck", ServiceStatus::Started));
+        test_bus.send_event(Event::new_status_changed("healthy", ServiceStatus::Started));
+
+        // The stuck checks keep failing at each interval, and the other services' ones still run.
+        let started = Instant::now();
+        let (mut stuck_failures, mut healthy_after_timeout) = (0, 0);
+        for event in test_bus.iter() {
+            match event {
+                Event::HealthCheck(name, HealthinessStatus::Unhealthy) if name == "stuck" => {
+                    stuck_failures += 1;
+                }
+                Event::HealthCheck(name, HealthinessStatus::Healthy) if name == "healthy" => {
+                    healthy_after_timeout += usize::from(stuck_failures > 0);
+                }
+                Event::HealthCheck(name, status) => panic!("{name}: {status:?}"),
+                _ => (),
+            }
+            if stuck_failures >= 3 && healthy_after_timeout >= 3 {
+                break;
+            }
+        }
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        drop(release);
+        test_bus.send_event(Event::ShuttingDownInitiated(ShuttingDown::Gracefully));
+        stopped.recv_timeout(Duration::from_secs(5))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_scheduler_ready_pattern() {
+        let mut service = Service::from_name("pattern");
+        service.readiness = Healthiness {
+            ready_pattern: Some("ready".into()),
+            interval: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        let bus = Bus::new();
+        let scheduler_bus = bus.join_bus();
+        let (results, _inputs) = crossbeam::channel::unbounded();
+        let pool = Pool::new(0, results, CustomChecks::default());
+        let jobs = pool.pending_jobs.clone();
+        let mut scheduler = Scheduler::new(&scheduler_bus, vec![service], pool);
+        let key = ("pattern".to_string(), Probe::Readiness);
+
+        // The pattern can match before the probe is scheduled.
+        scheduler.handle_pattern_matched(key.clone());
+        scheduler.handle_event(Event::new_status_changed("pattern", ServiceStatus::Started));
+        scheduler.run_due_probes(Instant::now());
+        let job = jobs.try_recv().unwrap();
+        assert!(job.state.pattern_matched);
+        assert_eq!(
+            check_health(
+                &job.service,
+                &job.service.readiness,
+                &job.state,
+                &CustomChecks::default()
+            ),
+            HealthinessStatus::Healthy
+        );
+
+        // A new run has to match again, and is checked right away when it does.
+        scheduler.handle_event(Event::new_service_exited("pattern".into(), 0, None));
+        scheduler.handle_event(Event::new_status_changed("pattern", ServiceStatus::Started));
+        scheduler.run_due_probes(Instant::now());
+        let job = jobs.try_recv().unwrap();
+        assert!(!job.state.pattern_matched);
+        scheduler.handle_result(key.clone(), job.generation, HealthinessStatus::Unhealthy);
+        assert!(scheduler.run_due_probes(Instant::now()).is_some());
+        assert!(jobs.try_recv().is_err());
+        scheduler.handle_pattern_matched(key);
+        scheduler.run_due_probes(Instant::now());
+        assert!(jobs.try_recv().unwrap().state.pattern_matched);
+    }
 }
@@ -0,0 +1,163 @@
+//! `ready-pattern` checks: the output of the service is scanned line by line while it is written
+//! to its destination, and the check passes as soon as a line matches the pattern.
+
+use std::io::{self, Read};
+use std::sync::Mutex;
+
+use crossbeam::channel::Sender;
+use regex::Regex;
+
+use crate::horust::formats::{Service, ServiceName};
+use crate::horust::healthcheck::{Input, Probe};
+
+/// Longer lines are matched in pieces.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// The inputs of the running scheduler, which is told about the matches.
+static SCHEDULER: Mutex<Option<Sender<Input>>> = Mutex::new(None);
+
+pub(super) fn set_scheduler(inputs: Option<Sender<Input>>) {
+    *SCHEDULER.lock().unwrap() = inputs;
+}
+
+/// Whether the output of the service must be scanned.
+pub(crate) fn watches_output(service: &Service) -> bool {
+    patterns(service).next().is_some()
+}
+
+fn patterns(service: &Service) -> impl Iterator<Item = (Probe, &String)> {
+    [Probe::Liveness, Probe::Readiness]
+        .into_iter()
+        .filter_map(|probe| Some((probe, probe.healthiness(service).ready_pattern.as_ref()?)))
+}
+
+/// A reader looking for the `ready-pattern`s of a service in everything it reads. The scheduler
+/// is told about the first match of each pattern, and runs the probe right away.
+pub(crate) struct ReadyPatternReader<R> {
+    inner: R,
+    service_name: ServiceName,
+    /// The patterns which haven't matched yet.
+    patterns: Vec<(Regex, Probe)>,
+    scheduler: Option<Sender<Input>>,
+    line: Vec<u8>,
+}
+
+impl<R: Read> ReadyPatternReader<R> {
+    pub(crate) fn new(inner: R, service: &Service) -> Self {
+        let scheduler = SCHEDULER.lock().unwrap().clone();
+        Self::with_scheduler(inner, service, scheduler)
+    }
+
+    fn with_scheduler(inner: R, service: &Service, scheduler: Option<Sender<Input>>) -> Self {
+        let patterns = patterns(service)
+            .filter_map(|(probe, pattern)| match Regex::new(pattern) {
+                Ok(regex) => Some((regex, probe)),
+                Err(error) => {
+                    error!("{}: invalid ready-pattern: {error}", service.name);
+                    None
+                }
+            })
+            .collect();
+        Self {
+            inner,
+            service_name: service.name.clone(),
+            patterns,
+            scheduler,
+            line: vec![],
+        }
+    }
+
+    fn scan(&mut self, bytes: &[u8]) {
+        if self.patterns.is_empty() {
+            return;
+        }
+        for chunk in bytes.split_inclusive(|byte| *byte == b'\n') {
+            self.line.extend_from_slice(chunk);
+            if chunk.ends_with(b"\n") || self.line.len() >= MAX_LINE_LENGTH {
+                self.match_line();
+            }
+        }
+    }
+
+    fn match_line(&mut self) {
+        let line = String::from_utf8_lossy(&self.line);
+        let line = line.trim_end_matches(['\r', '\n']);
+        let scheduler = &self.scheduler;
+        let service_name = &self.service_name;
+        self.patterns.retain(|(regex, probe)| {
+            if !regex.is_match(line) {
+                return true;
+            }
+            if let Some(scheduler) = scheduler {
+                // The scheduler is gone once horust is shutting down.
+                let _ = scheduler.send(Input::PatternMatched((service_name.clone(), *probe)));
+            }
+            false
+        });
+        self.line.clear();
+    }
+}
+
+impl<R: Read> Read for ReadyPatternReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if read == 0 && !self.line.is_empty() {
+            self.match_line();
+        }
+        self.scan(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use crossbeam::channel::unbounded;
+
+    use crate::horust::formats::Service;
+    use crate::horust::healthcheck::ready_pattern::ReadyPatternReader;
+    use crate::horust::healthcheck::{Input, Probe};
+
+    #[test]
+    fn test_ready_pattern_reader() {
+        let mut service = Service::from_name("ready_pattern");
+        service.healthiness.ready_pattern = Some("ready to accept connections$".into());
+        service.readiness.ready_pattern = Some("^listening on port \\d+".into());
+        let (scheduler, inputs) = unbounded();
+        let matched = || {
+            inputs
+                .try_iter()
+                .map(|input| match input {
+                    Input::PatternMatched((service_name, probe)) => {
+                        assert_eq!(service_name, "ready_pattern");
+                        probe
+                    }
+                    _ => panic!("Unexpected input"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let output = "starting\nready to accept connections (not yet)\nlistening on port 80";
+        let mut reader = ReadyPatternReader::with_scheduler(
+            output.as_bytes(),
+            &service,
+            Some(scheduler.clone()),
+        );
+        let mut read = String::new();
+        reader.read_to_string(&mut read).unwrap();
+        // The output is not changed.
+        assert_eq!(read, output);
+        // The last line is matched even without a trailing newline.
+        assert_eq!(matched(), vec![Probe::Readiness]);
+
+        // Only the first match is sent.
+        let mut reader = ReadyPatternReader::with_scheduler(
+            "ready to accept connections\r\nready to accept connections\n".as_bytes(),
+            &service,
+            Some(scheduler),
+        );
+        reader.read_to_string(&mut String::new()).unwrap();
+        assert_eq!(matched(), vec![Probe::Liveness]);
+    }
+}
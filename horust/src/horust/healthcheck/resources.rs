@@ -0,0 +1,235 @@
+//! Resource usage checks: the usage of the service's main process is read from `/proc`, and the
+//! check fails once a soft limit has been exceeded for `sustained-for`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use nix::unistd::Pid;
+
+use crate::horust::formats::{ResourceHealthiness, ServiceName};
+
+/// Resources used by a process.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Usage {
+    /// In bytes.
+    rss: u64,
+    fds: u64,
+    threads: u64,
+    /// User and system time, in clock ticks.
+    cpu_ticks: u64,
+}
+
+/// The previous check of a service.
+struct Sample {
+    pid: Pid,
+    at: Instant,
+    cpu_ticks: u64,
+    exceeded_since: Option<Instant>,
+}
+
+type Pids = Mutex<HashMap<ServiceName, Pid>>;
+static PIDS: OnceLock<Pids> = OnceLock::new();
+
+type Samples = Mutex<HashMap<(ServiceName, ResourceHealthiness), Sample>>;
+static SAMPLES: OnceLock<Samples> = OnceLock::new();
+
+fn get_pids() -> &'static Pids {
+    PIDS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn get_samples() -> &'static Samples {
+    SAMPLES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Records the pid of the current run of the service.
+pub(super) fn set_pid(service_name: &str, pid: Pid) {
+    get_pids()
+        .lock()
+        .unwrap()
+        .insert(service_name.to_string(), pid);
+}
+
+/// Forgets the pid and the samples of a service which exited.
+pub(super) fn forget(service_name: &str) {
+    get_pids().lock().unwrap().remove(service_name);
+    get_samples()
+        .lock()
+        .unwrap()
+        .retain(|(name, _), _| name != service_name);
+}
+
+/// Returns false if a limit has been exceeded for longer than `sustained-for`. A service whose
+/// usage can't be read (e.g. it just exited) is considered healthy.
+pub(super) fn check(service_name: &str, resources: &ResourceHealthiness) -> bool {
+    let Some(pid) = get_pids().lock().unwrap().get(service_name).copied() else {
+        return true;
+    };
+    let usage = match read_usage(pid) {
+        Ok(usage) => usage,
+        Err(error) => {
+            debug!("{service_name}: cannot read the resource usage of {pid}: {error}");
+            return true;
+        }
+    };
+    let now = Instant::now();
+    let mut samples = get_samples().lock().unwrap();
+    let key = (service_name.to_string(), resources.clone());
+    let previous = samples.get(&key).filter(|sample| sample.pid == pid);
+    let cpu = previous.and_then(|previous| cpu_percent(previous, &usage, now));
+    let exceeded = exceeded_limits(resources, &usage, cpu);
+    let exceeded_since = if exceeded.is_empty() {
+        None
+    } else {
+        previous
+            .and_then(|previous| previous.exceeded_since)
+            .or_else(|| {
+                warn!("{service_name}: {}.", exceeded.join(", "));
+                Some(now)
+            })
+    };
+    samples.insert(
+        key,
+        Sample {
+            pid,
+            at: now,
+            cpu_ticks: usage.cpu_ticks,
+            exceeded_since,
+        },
+    );
+    exceeded_since.is_none_or(|since| now.duration_since(since) < resources.sustained_for())
+}
+
+/// CPU usage since the previous sample, in percent of one core.
+fn cpu_percent(previous: &Sample, usage: &Usage, now: Instant) -> Option<u64> {
+    let elapsed = now.duration_since(previous.at).as_secs_f64();
+    // Safety: sysconf has no preconditions.
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if elapsed <= 0.0 || ticks_per_second <= 0 {
+        return None;
+    }
+    let ticks = usage.cpu_ticks.saturating_sub(previous.cpu_ticks) as f64;
+    Some((ticks / ticks_per_second as f64 / elapsed * 100.0) as u64)
+}
+
+/// Describes the limits exceeded by `usage`.
+fn exceeded_limits(
+    resources: &ResourceHealthiness,
+    usage: &Usage,
+    cpu_percent: Option<u64>,
+) -> Vec<String> {
+    let limits = [
+        ("rss", Some(usage.rss), resources.max_rss),
+        ("fds", Some(usage.fds), resources.max_fds),
+        ("threads", Some(usage.threads), resources.max_threads),
+        ("cpu", cpu_percent, resources.max_cpu.map(u64::from)),
+    ];
+    limits
+        .into_iter()
+        .filter_map(|(name, used, max)| match (used, max) {
+            (Some(used), Some(max)) if used > max => {
+                Some(format!("{name} {used} above max-{name} {max}"))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn read_usage(pid: Pid) -> io::Result<Usage> {
+    let proc_dir = PathBuf::from(format!("/proc/{pid}"));
+    let status = fs::read_to_string(proc_dir.join("status"))?;
+    let stat = fs::read_to_string(proc_dir.join("stat"))?;
+    let fds = fs::read_dir(proc_dir.join("fd"))?.count() as u64;
+    Ok(Usage {
+        fds,
+        ..parse_status_and_stat(&status, &stat)
+    })
+}
+
+/// Parses `/proc/<pid>/status` and `/proc/<pid>/stat`.
+fn parse_status_and_stat(status: &str, stat: &str) -> Usage {
+    let status_field = |name: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .and_then(|value| value.split_whitespace().next())
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(0)
+    };
+    // The command name can contain spaces and parentheses, the fields start after the last ')'.
+    // utime and stime are the fields 14 and 15.
+    let stat_fields: Vec<&str> = stat
+        .rsplit_once(')')
+        .map(|(_, fields)| fields.split_whitespace().collect())
+        .unwrap_or_default();
+    let stat_field = |index: usize| {
+        stat_fields
+            .get(index)
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(0)
+    };
+    Usage {
+        rss: status_field("VmRSS:") * 1024,
+        fds: 0,
+        threads: status_field("Threads:"),
+        cpu_ticks: stat_field(11) + stat_field(12),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use nix::unistd::getpid;
+
+    use crate::horust::formats::ResourceHealthiness;
+    use crate::horust::healthcheck::resources::{
+        Usage, check, forget, parse_status_and_stat, set_pid,
+    };
+
+    #[test]
+    fn test_parse_status_and_stat() {
+        let status = "Name:\tcat\nVmRSS:\t    1024 kB\nThreads:\t3\n";
+        let stat = "42 (my (weird) cmd) S 1 42 42 0 -1 4194304 100 0 0 0 7 5 0 0 20 0 3 0";
+        assert_eq!(
+            parse_status_and_stat(status, stat),
+            Usage {
+                rss: 1024 * 1024,
+                fds: 0,
+                threads: 3,
+                cpu_ticks: 12,
+            }
+        );
+    }
+
+    #[test]
+    fn test_check_resources() {
+        let name = "resources";
+        let within_limits = ResourceHealthiness {
+            max_rss: Some(u64::MAX),
+            max_threads: Some(100_000),
+            ..Default::default()
+        };
+        let above_limits = ResourceHealthiness {
+            max_fds: Some(0),
+            ..Default::default()
+        };
+        let sustained = ResourceHealthiness {
+            max_fds: Some(0),
+            sustained_for: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        // Without a pid, there is nothing to check.
+        assert!(check(name, &above_limits));
+        set_pid(name, getpid());
+        assert!(check(name, &within_limits));
+        assert!(!check(name, &above_limits));
+        assert!(check(name, &sustained));
+        assert!(check(name, &sustained));
+        forget(name);
+        assert!(check(name, &above_limits));
+    }
+}
@@ -0,0 +1,166 @@
+//! In-memory ring buffers holding the most recent output of each service.
+//! They are fed by the threads reading the services' output pipes, and read by the commands handler
+//! to serve `horustctl logs`. A buffer outlives the process that filled it, so the output of a
+//! crashed service is still there after it has been restarted.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::horust::formats::ServiceName;
+
+pub(crate) type SharedLogBuffer = Arc<Mutex<LogBuffer>>;
+
+type LogBuffers = Mutex<HashMap<ServiceName, SharedLogBuffer>>;
+static LOG_BUFFERS: OnceLock<LogBuffers> = OnceLock::new();
+
+fn get_log_buffers() -> &'static LogBuffers {
+    LOG_BUFFERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Returns the log buffer of the service, creating it if this is the first run.
+pub(crate) fn register(service_name: &str, capacity: usize) -> SharedLogBuffer {
+    get_log_buffers()
+        .lock()
+        .unwrap()
+        .entry(service_name.to_string())
+        .or_insert_with(|| Arc::new(Mutex::new(LogBuffer::new(capacity))))
+        .clone()
+}
+
+/// Returns the log buffer of the service, if it has one.
+pub(crate) fn get(service_name: &str) -> Option<SharedLogBuffer> {
+    get_log_buffers().lock().unwrap().get(service_name).cloned()
+}
+
+/// Keeps the last `capacity` bytes written into it.
+#[derive(Debug)]
+pub(crate) struct LogBuffer {
+    capacity: usize,
+    content: VecDeque<u8>,
+    /// Total amount of bytes ever pushed. Readers use it as offset to only fetch new content.
+    written: u64,
+}
+
+impl LogBuffer {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            content: VecDeque::with_capacity(capacity),
+            written: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, bytes: &[u8]) {
+        self.written += bytes.len() as u64;
+        let bytes = &bytes[bytes.len().saturating_sub(self.capacity)..];
+        let overflow = (self.content.len() + bytes.len()).saturating_sub(self.capacity);
+        self.content.drain(..overflow);
+        self.content.extend(bytes);
+    }
+
+    /// Offset of the oldest byte still available.
+    fn first_offset(&self) -> u64 {
+        self.written - self.content.len() as u64
+    }
+
+    /// Returns everything written after `offset`, and the offset to use for the next read.
+    /// If part of that content was already overwritten, only what's left is returned.
+    pub(crate) fn read_from(&self, offset: u64) -> (Vec<u8>, u64) {
+        let skip = offset.saturating_sub(self.first_offset()) as usize;
+        let content = self.content.iter().skip(skip).copied().collect();
+        (content, self.written)
+    }
+
+    /// Returns the last `lines` lines (or the whole buffer if `lines` is 0), and the offset to use
+    /// for the next read.
+    pub(crate) fn tail(&self, lines: usize) -> (Vec<u8>, u64) {
+        let mut start = 0;
+        if lines > 0 {
+            // The newline terminating the last line doesn't start a new one.
+            let last = self.content.len().saturating_sub(1);
+            let mut newlines = 0;
+            for (i, byte) in self.content.iter().enumerate().rev() {
+                if *byte == b'\n' && i != last {
+                    newlines += 1;
+                    if newlines == lines {
+                        start = i + 1;
+                        break;
+                    }
+                }
+            }
+        }
+        let content = self.content.range(start..).copied().collect();
+        (content, self.written)
+    }
+}
+
+/// A reader copying everything it reads into a log buffer, if there is one.
+pub(crate) struct LogBufferReader<R> {
+    inner: R,
+    log_buffer: Option<SharedLogBuffer>,
+}
+
+impl<R: Read> LogBufferReader<R> {
+    pub(crate) fn new(inner: R, log_buffer: Option<SharedLogBuffer>) -> Self {
+        Self { inner, log_buffer }
+    }
+}
+
+impl<R: Read> Read for LogBufferReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(log_buffer) = &self.log_buffer {
+            log_buffer.lock().unwrap().push(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+
+    use crate::horust::log_buffer::{LogBuffer, LogBufferReader};
+
+    #[test]
+    fn test_log_buffer_keeps_last_bytes() {
+        let mut buffer = LogBuffer::new(8);
+        buffer.push(b"hello");
+        assert_eq!(buffer.read_from(0), (b"hello".to_vec(), 5));
+        buffer.push(b" world");
+        assert_eq!(buffer.read_from(0), (b"lo world".to_vec(), 11));
+        // Only the content written after the offset is returned.
+        assert_eq!(buffer.read_from(9), (b"ld".to_vec(), 11));
+        assert_eq!(buffer.read_from(11), (vec![], 11));
+        // A single write bigger than the buffer.
+        buffer.push(b"0123456789");
+        assert_eq!(buffer.read_from(0), (b"23456789".to_vec(), 21));
+    }
+
+    #[test]
+    fn test_log_buffer_tail() {
+        let mut buffer = LogBuffer::new(64);
+        buffer.push(b"first\nsecond\nthird\n");
+        assert_eq!(buffer.tail(1).0, b"third\n".to_vec());
+        assert_eq!(buffer.tail(2).0, b"second\nthird\n".to_vec());
+        assert_eq!(buffer.tail(3).0, b"first\nsecond\nthird\n".to_vec());
+        assert_eq!(buffer.tail(10).0, b"first\nsecond\nthird\n".to_vec());
+        assert_eq!(buffer.tail(0).0, b"first\nsecond\nthird\n".to_vec());
+        // A line that is still being written counts as a line.
+        buffer.push(b"fourth");
+        assert_eq!(buffer.tail(1).0, b"fourth".to_vec());
+        assert_eq!(LogBuffer::new(8).tail(1), (vec![], 0));
+    }
+
+    #[test]
+    fn test_log_buffer_reader() {
+        let buffer = Arc::new(Mutex::new(LogBuffer::new(64)));
+        let mut reader = LogBufferReader::new("some output".as_bytes(), Some(buffer.clone()));
+        let mut read = String::new();
+        reader.read_to_string(&mut read).unwrap();
+        assert_eq!(read, "some output");
+        assert_eq!(buffer.lock().unwrap().tail(0).0, b"some output".to_vec());
+    }
+}
@@ -0,0 +1,429 @@
+//! Forwards the output of services to log collectors, as one record per line.
+//! Records are sent as RFC5424 syslog messages or as JSON lines, over unix datagram, UDP or TCP
+//! sockets. Lines written while the collector is unreachable are dropped: it's retried with an
+//! exponential backoff, so that a collector which doesn't answer can't block the service.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, anyhow};
+use nix::unistd::{self, Pid};
+use serde::Serialize;
+
+use crate::horust::formats::{LogEndpoint, LogOutput};
+
+/// syslog facility used for all the records: "system daemons".
+const SYSLOG_FACILITY_DAEMON: u8 = 3;
+const SYSLOG_SEVERITY_ERROR: u8 = 3;
+const SYSLOG_SEVERITY_INFO: u8 = 6;
+/// RFC5424 limits on the header fields.
+const SYSLOG_MAX_HOSTNAME: usize = 255;
+const SYSLOG_MAX_APP_NAME: usize = 48;
+/// Longer lines are split into several records, of at most this many bytes.
+const MAX_RECORD: usize = 8 * 1024;
+/// The timeout of connections and writes to a TCP collector.
+const TCP_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long to wait before reconnecting to an unreachable collector, doubled on every failure.
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// A line of output of a service.
+#[derive(Serialize, Debug)]
+struct Record<'a> {
+    timestamp: String,
+    service: &'a str,
+    stream: &'a str,
+    pid: i32,
+    message: &'a str,
+}
+
+impl Record<'_> {
+    fn to_json_line(&self) -> Vec<u8> {
+        let mut line = serde_json::to_vec(self).expect("Record serialization");
+        line.push(b'\n');
+        line
+    }
+
+    fn to_syslog(&self, hostname: &str) -> Vec<u8> {
+        let severity = if self.stream == "stderr" {
+            SYSLOG_SEVERITY_ERROR
+        } else {
+            SYSLOG_SEVERITY_INFO
+        };
+        let priority = SYSLOG_FACILITY_DAEMON * 8 + severity;
+        format!(
+            "<{priority}>1 {} {} {} {} {} - {}",
+            self.timestamp,
+            syslog_header_field(hostname, SYSLOG_MAX_HOSTNAME),
+            syslog_header_field(self.service, SYSLOG_MAX_APP_NAME),
+            self.pid,
+            self.stream,
+            self.message
+        )
+        .into_bytes()
+    }
+}
+
+/// Header fields can only contain printable ASCII characters, and can't be empty.
+fn syslog_header_field(value: &str, max_len: usize) -> String {
+    let field: String = value
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(max_len)
+        .collect();
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
+
+/// Formats `time` as an RFC3339 UTC timestamp, with millisecond precision.
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+    // Converts days since the epoch to a date in the proleptic Gregorian calendar.
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+enum Socket {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+impl Socket {
+    fn connect(endpoint: &LogEndpoint) -> io::Result<Self> {
+        Ok(match endpoint {
+            LogEndpoint::Unix(path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Socket::Unix(socket)
+            }
+            LogEndpoint::Udp(address) => {
+                let address = address.to_socket_addrs()?.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("{address} not found"))
+                })?;
+                let local = if address.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                let socket = UdpSocket::bind(local)?;
+                socket.connect(address)?;
+                Socket::Udp(socket)
+            }
+            LogEndpoint::Tcp(address) => Socket::Tcp(tcp_connect(address)?),
+        })
+    }
+
+    fn send(&mut self, payload: &[u8]) -> io::Result<()> {
+        match self {
+            Socket::Unix(socket) => socket.send(payload).map(drop),
+            Socket::Udp(socket) => socket.send(payload).map(drop),
+            Socket::Tcp(stream) => write_all_timeout(stream, payload),
+        }
+    }
+}
+
+/// Tries every address of `address`, like `TcpStream::connect`, but with a timeout.
+fn tcp_connect(address: &str) -> io::Result<TcpStream> {
+    let mut last_err = None;
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, TCP_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{address} not found"))))
+}
+
+/// Like `write_all`, but fails if the whole payload isn't written within `TCP_TIMEOUT`. The
+/// timeout of the stream only applies to each write, which can send a few bytes at a time.
+fn write_all_timeout(stream: &mut TcpStream, mut payload: &[u8]) -> io::Result<()> {
+    let deadline = Instant::now() + TCP_TIMEOUT;
+    while !payload.is_empty() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        stream.set_write_timeout(Some(remaining))?;
+        match stream.write(payload) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(written) => payload = &payload[written..],
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// A socket which is (re)connected on demand. After a failure, the records are dropped until the
+/// next retry.
+struct Connection {
+    endpoint: LogEndpoint,
+    socket: Option<Socket>,
+    retry_delay: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl Connection {
+    fn new(endpoint: LogEndpoint) -> Self {
+        Self {
+            endpoint,
+            socket: None,
+            retry_delay: MIN_RETRY_DELAY,
+            retry_at: None,
+        }
+    }
+
+    fn send(&mut self, payload: &[u8]) -> io::Result<()> {
+        if self
+            .retry_at
+            .is_some_and(|retry_at| Instant::now() < retry_at)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "waiting to reconnect",
+            ));
+        }
+        let res = match &mut self.socket {
+            Some(socket) => socket.send(payload),
+            None => Socket::connect(&self.endpoint)
+                .and_then(|socket| self.socket.insert(socket).send(payload)),
+        };
+        match res {
+            Ok(()) => {
+                self.retry_delay = MIN_RETRY_DELAY;
+                self.retry_at = None;
+            }
+            Err(_) => {
+                self.socket = None;
+                self.retry_at = Some(Instant::now() + self.retry_delay);
+                self.retry_delay = (self.retry_delay * 2).min(MAX_RETRY_DELAY);
+            }
+        }
+        res
+    }
+}
+
+/// Reads `source` line by line, and sends every line to `destination` until EOF is reached. Lines
+/// longer than `MAX_RECORD` are sent in several records.
+pub(crate) fn forward_records(
+    source: impl Read,
+    destination: &LogOutput,
+    service_name: &str,
+    stream: &str,
+    pid: Pid,
+) -> Result<()> {
+    let (endpoint, is_syslog) = match destination {
+        LogOutput::Syslog(endpoint) => (endpoint, true),
+        LogOutput::JsonLines(endpoint) => (endpoint, false),
+        other => return Err(anyhow!("{other:?} is not a forwarded log output")),
+    };
+    let hostname = unistd::gethostname()
+        .map(|hostname| hostname.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut connection = Connection::new(endpoint.clone());
+    let mut reachable = true;
+    let mut reader = BufReader::new(source);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if (&mut reader)
+            .take(MAX_RECORD as u64)
+            .read_until(b'\n', &mut line)?
+            == 0
+        {
+            break;
+        }
+        let message = String::from_utf8_lossy(line.strip_suffix(b"\n").unwrap_or(&line));
+        let record = Record {
+            timestamp: rfc3339(SystemTime::now()),
+            service: service_name,
+            stream,
+            pid: pid.as_raw(),
+            message: &message,
+        };
+        let payload = match (is_syslog, endpoint) {
+            (true, LogEndpoint::Tcp(_)) => {
+                // Octet counting framing, RFC6587.
+                let message = record.to_syslog(&hostname);
+                [format!("{} ", message.len()).into_bytes(), message].concat()
+            }
+            (true, _) => record.to_syslog(&hostname),
+            (false, _) => record.to_json_line(),
+        };
+        match connection.send(&payload) {
+            Ok(()) if !reachable => {
+                info!("{service_name}: {stream} is being forwarded to {endpoint} again");
+                reachable = true;
+            }
+            Ok(()) => (),
+            // Only warn once, not for every line.
+            Err(err) if reachable => {
+                warn!("{service_name}: failed to forward {stream} to {endpoint}: {err}");
+                reachable = false;
+            }
+            Err(_) => (),
+        }
+    }
+    debug!("EOF reached");
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::net::{TcpListener, UdpSocket};
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    use nix::unistd::Pid;
+
+    use crate::horust::formats::{LogEndpoint, LogOutput};
+    use crate::horust::log_forwarder::{MAX_RECORD, Record, forward_records, rfc3339};
+
+    fn record<'a>(stream: &'a str, message: &'a str) -> Record<'a> {
+        Record {
+            timestamp: "2024-02-29T13:14:15.016Z".to_string(),
+            service: "my service.toml",
+            stream,
+            pid: 42,
+            message,
+        }
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let leap_day = UNIX_EPOCH + Duration::from_millis(1_709_212_455_016);
+        assert_eq!(rfc3339(leap_day), "2024-02-29T13:14:15.016Z");
+        let end_of_year = UNIX_EPOCH + Duration::from_secs(1_735_689_599);
+        assert_eq!(rfc3339(end_of_year), "2024-12-31T23:59:59.000Z");
+    }
+
+    #[test]
+    fn test_record_formats() {
+        assert_eq!(
+            String::from_utf8(record("stdout", "hello \"world\"").to_json_line()).unwrap(),
+            "{\"timestamp\":\"2024-02-29T13:14:15.016Z\",\"service\":\"my service.toml\",\
+             \"stream\":\"stdout\",\"pid\":42,\"message\":\"hello \\\"world\\\"\"}\n"
+        );
+        assert_eq!(
+            String::from_utf8(record("stdout", "hello").to_syslog("host")).unwrap(),
+            "<30>1 2024-02-29T13:14:15.016Z host my_service.toml 42 stdout - hello"
+        );
+        assert_eq!(
+            String::from_utf8(record("stderr", "oops").to_syslog("")).unwrap(),
+            "<27>1 2024-02-29T13:14:15.016Z - my_service.toml 42 stderr - oops"
+        );
+    }
+
+    #[test]
+    fn test_forward_json_lines_over_udp() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let destination =
+            LogOutput::JsonLines(LogEndpoint::Udp(listener.local_addr().unwrap().to_string()));
+        let output = "first\nsecond".as_bytes();
+        forward_records(output, &destination, "svc", "stderr", Pid::from_raw(7)).unwrap();
+        let mut buf = [0; 512];
+        for expected in ["first", "second"] {
+            let read = listener.recv(&mut buf).unwrap();
+            let record: serde_json::Value = serde_json::from_slice(&buf[..read]).unwrap();
+            assert_eq!(record["message"], expected);
+            assert_eq!(record["service"], "svc");
+            assert_eq!(record["stream"], "stderr");
+            assert_eq!(record["pid"], 7);
+        }
+    }
+
+    #[test]
+    fn test_forward_long_line() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let destination =
+            LogOutput::JsonLines(LogEndpoint::Udp(listener.local_addr().unwrap().to_string()));
+        let output = format!("{}\n", "x".repeat(MAX_RECORD + 10));
+        forward_records(
+            output.as_bytes(),
+            &destination,
+            "svc",
+            "stdout",
+            Pid::from_raw(7),
+        )
+        .unwrap();
+        let mut buf = [0; 2 * MAX_RECORD];
+        for expected in [MAX_RECORD, 10] {
+            let read = listener.recv(&mut buf).unwrap();
+            let record: serde_json::Value = serde_json::from_slice(&buf[..read]).unwrap();
+            assert_eq!(record["message"].as_str().unwrap().len(), expected);
+        }
+    }
+
+    #[test]
+    fn test_forward_syslog_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let destination =
+            LogOutput::Syslog(LogEndpoint::Tcp(listener.local_addr().unwrap().to_string()));
+        let output = "hello\n".as_bytes();
+        forward_records(output, &destination, "svc", "stdout", Pid::from_raw(7)).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+        let (len, message) = received.split_once(' ').unwrap();
+        assert_eq!(len.parse::<usize>().unwrap(), message.len());
+        assert!(message.starts_with("<30>1 "), "{message}");
+        assert!(message.ends_with(" svc 7 stdout - hello"), "{message}");
+    }
+
+    #[test]
+    fn test_forward_to_stalled_tcp_collector() {
+        // The connections are queued, but never accepted nor read: the writes end up blocking.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let destination =
+            LogOutput::JsonLines(LogEndpoint::Tcp(listener.local_addr().unwrap().to_string()));
+        let line = format!("{}\n", "x".repeat(4095));
+        let output = line.repeat(4 * 1024);
+        let start = Instant::now();
+        forward_records(
+            output.as_bytes(),
+            &destination,
+            "svc",
+            "stdout",
+            Pid::from_raw(7),
+        )
+        .unwrap();
+        // The records are dropped after the first write timeout, instead of blocking on each.
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "{:?}",
+            start.elapsed()
+        );
+    }
+}
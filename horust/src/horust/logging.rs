@@ -0,0 +1,175 @@
+//! Setup of horust's own logs.
+//! Messages can carry structured fields (e.g. `info!(service = name; "...")`): they are part of
+//! the JSON object in the `json` format, while the `text` format only prints the message.
+
+use std::io::Write;
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+use env_logger::fmt::hidden_kv_format;
+use log::kv::{self, Key, Value, VisitSource, VisitValue};
+use log::{Log, Metadata, Record};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::horust::formats::LogFormat;
+use crate::horust::log_forwarder::rfc3339;
+
+/// Whether the logs are written in the `json` format, rather than `text`.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Writes the logs in the current format.
+struct Logger {
+    text: env_logger::Logger,
+    json: env_logger::Logger,
+}
+
+impl Logger {
+    fn current(&self) -> &env_logger::Logger {
+        if JSON.load(Ordering::Relaxed) {
+            &self.json
+        } else {
+            &self.text
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.current().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.current().log(record)
+    }
+
+    fn flush(&self) {
+        self.current().flush()
+    }
+}
+
+/// Initializes the logger, or changes its format if it's already initialized: it's set from the
+/// command line before loading the configuration, then from the configuration. The level filter
+/// is read from `HORUST_LOG`.
+pub fn init_logging(format: LogFormat) {
+    JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let builder = || {
+            let env = env_logger::Env::new()
+                .filter("HORUST_LOG")
+                .write_style("HORUST_LOG_STYLE");
+            env_logger::Builder::from_env(env)
+        };
+        let text = builder().format_key_values(hidden_kv_format).build();
+        let json = builder()
+            .format(|buf, record| writeln!(buf, "{}", json_line(record, SystemTime::now())))
+            .build();
+        let max_level = text.filter();
+        if log::set_boxed_logger(Box::new(Logger { text, json })).is_ok() {
+            log::set_max_level(max_level);
+        }
+    });
+}
+
+fn json_line(record: &Record, time: SystemTime) -> String {
+    let mut fields = Map::new();
+    fields.insert("timestamp".into(), rfc3339(time).into());
+    fields.insert("level".into(), record.level().as_str().into());
+    fields.insert("target".into(), record.target().into());
+    fields.insert("message".into(), record.args().to_string().into());
+    let _ = record.key_values().visit(&mut FieldsVisitor(&mut fields));
+    JsonValue::Object(fields).to_string()
+}
+
+struct FieldsVisitor<'a>(&'a mut Map<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for FieldsVisitor<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let mut json = JsonValue::Null;
+        value.visit(JsonVisitor(&mut json))?;
+        self.0.insert(key.to_string(), json);
+        Ok(())
+    }
+}
+
+/// Keeps numbers and booleans as such, everything else becomes a string.
+struct JsonVisitor<'a>(&'a mut JsonValue);
+
+impl<'v> VisitValue<'v> for JsonVisitor<'_> {
+    fn visit_any(&mut self, value: Value) -> Result<(), kv::Error> {
+        *self.0 = value.to_string().into();
+        Ok(())
+    }
+    fn visit_null(&mut self) -> Result<(), kv::Error> {
+        *self.0 = JsonValue::Null;
+        Ok(())
+    }
+    fn visit_u64(&mut self, value: u64) -> Result<(), kv::Error> {
+        *self.0 = value.into();
+        Ok(())
+    }
+    fn visit_i64(&mut self, value: i64) -> Result<(), kv::Error> {
+        *self.0 = value.into();
+        Ok(())
+    }
+    fn visit_f64(&mut self, value: f64) -> Result<(), kv::Error> {
+        *self.0 = value.into();
+        Ok(())
+    }
+    fn visit_bool(&mut self, value: bool) -> Result<(), kv::Error> {
+        *self.0 = value.into();
+        Ok(())
+    }
+    fn visit_str(&mut self, value: &str) -> Result<(), kv::Error> {
+        *self.0 = value.into();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::UNIX_EPOCH;
+
+    use log::kv::{ToValue, Value};
+    use log::{Level, Record};
+
+    use crate::horust::logging::json_line;
+
+    #[test]
+    fn test_json_line() {
+        let pid: Option<i32> = Some(42);
+        let exit_code: Option<i32> = None;
+        let key_values: [(&str, Value); 5] = [
+            ("service", "db.toml".to_value()),
+            ("event", "status_changed".to_value()),
+            ("old_status", Value::from_display(&"Running")),
+            ("pid", pid.to_value()),
+            ("exit_code", exit_code.to_value()),
+        ];
+        let line = json_line(
+            &Record::builder()
+                .args(format_args!("db.toml: Running ⇾ Failed"))
+                .level(Level::Info)
+                .target("horust::supervisor")
+                .key_values(&key_values)
+                .build(),
+            UNIX_EPOCH,
+        );
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "timestamp": "1970-01-01T00:00:00.000Z",
+                "level": "INFO",
+                "target": "horust::supervisor",
+                "message": "db.toml: Running ⇾ Failed",
+                "service": "db.toml",
+                "event": "status_changed",
+                "old_status": "Running",
+                "pid": 42,
+                "exit_code": null,
+            })
+        );
+    }
+}
@@ -2,6 +2,7 @@ use std::ffi::OsStr;
 use std::fmt::Debug;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use anyhow::Result;
 use bus::BusConnector;
@@ -13,13 +14,18 @@ pub use formats::Event;
 use crate::horust::bus::Bus;
 use crate::horust::formats::{Service, validate};
 
-pub use self::formats::{ExitStatus, HorustConfig, get_sample_service};
+pub use self::formats::{ExitStatus, HorustConfig, LogFormat, get_sample_service};
+pub use self::healthcheck::{CustomCheck, CustomCheckParams};
+pub use self::logging::init_logging;
 
 pub mod bus;
 mod commands_handler;
 mod error;
 pub mod formats;
 mod healthcheck;
+mod log_buffer;
+mod log_forwarder;
+mod logging;
 mod signal_safe;
 mod supervisor;
 
@@ -28,6 +34,9 @@ pub struct Horust {
     services: Vec<Service>,
     uds_path: PathBuf,
     bus: Option<Bus<Event>>,
+    healthcheck_concurrency: usize,
+    custom_checks: healthcheck::CustomChecks,
+    systemd_cgroup: bool,
 }
 
 impl Horust {
@@ -36,6 +45,9 @@ impl Horust {
             services,
             uds_path,
             bus: Some(Bus::new()),
+            healthcheck_concurrency: healthcheck::DEFAULT_CONCURRENCY,
+            custom_checks: Default::default(),
+            systemd_cgroup: false,
         }
     }
 
@@ -67,6 +79,27 @@ impl Horust {
         Horust::new(services, uds_path)
     }
 
+    /// Applies horust's configuration to the services, e.g. the default timing of the health checks.
+    pub fn apply_config(&mut self, config: &HorustConfig) {
+        for service in &mut self.services {
+            service.healthiness.apply_defaults(config);
+            service.readiness.apply_defaults(config);
+        }
+        if let Some(concurrency) = config.healthcheck_concurrency {
+            self.healthcheck_concurrency = concurrency.max(1);
+        }
+        self.systemd_cgroup = config.systemd_cgroup;
+    }
+
+    /// Registers a health check, run for the services having a `[healthiness.custom.<name>]` or
+    /// `[readiness.custom.<name>]` section. Registering a name again replaces its check.
+    pub fn register_check<C>(&mut self, name: impl Into<String>, check: C)
+    where
+        C: CustomCheck + 'static,
+    {
+        self.custom_checks.register(name.into(), Arc::new(check));
+    }
+
     /// Returns a BusConnector.
     /// Panics if called when the `Horust::run()` has already been called.
     pub fn join_bus(&self) -> BusConnector<Event> {
@@ -94,16 +127,26 @@ impl Horust {
             prctl(PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0);
         }
         supervisor::init();
+        supervisor::init_cgroups(self.systemd_cgroup);
 
         debug!("Services: {:?}", self.services);
         // Spawn helper threads:
-        healthcheck::spawn(self.join_bus(), self.services.clone());
+        healthcheck::spawn(
+            self.join_bus(),
+            self.services.clone(),
+            self.healthcheck_concurrency,
+            self.custom_checks.clone(),
+        );
         commands_handler::spawn(
             self.join_bus(),
             self.uds_path.clone(),
             self.services.iter().map(|s| s.name.clone()).collect(),
         );
-        let handle = supervisor::spawn(self.join_bus(), self.services.clone());
+        let handle = supervisor::spawn(
+            self.join_bus(),
+            self.services.clone(),
+            self.custom_checks.clone(),
+        );
         let bus = self
             .bus
             .take()
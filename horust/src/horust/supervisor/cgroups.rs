@@ -0,0 +1,517 @@
+//! The cgroups of the services. Every service runs in its own cgroup, named after it, in the cgroup
+//! of this horust instance: `horust-<pid>/services/<service>`. With cgroup v2 it's created in the
+//! cgroup horust was started in, so that it works with a delegated subtree, e.g. in a container.
+//! The cgroup of a service is removed when it exits, unless some of its processes are left in it.
+//! With `systemd-cgroup`, systemd creates them instead, as the transient scopes
+//! `horust-<pid>-<service>.scope`, and removes them once empty.
+
+#[cfg(target_os = "linux")]
+use std::collections::HashMap;
+#[cfg(target_os = "linux")]
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::Result;
+#[cfg(target_os = "linux")]
+use anyhow::{Context, anyhow};
+use nix::unistd::Pid;
+
+use crate::horust::formats::Service;
+#[cfg(target_os = "linux")]
+use crate::horust::formats::ServiceName;
+
+#[cfg(target_os = "linux")]
+static CGROUPS: OnceLock<Result<Cgroups>> = OnceLock::new();
+
+/// The leaf cgroup horust moves itself to with cgroup v2, see `Cgroups::delegate`.
+#[cfg(target_os = "linux")]
+const SUPERVISOR_CGROUP: &str = "supervisor";
+/// The cgroup of the instance holding the ones of the services, apart from `SUPERVISOR_CGROUP`.
+#[cfg(target_os = "linux")]
+const SERVICES_CGROUP: &str = "services";
+
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+enum Driver {
+    /// Horust creates the cgroups of the services in its own, `instance`. `origin` is the cgroup
+    /// horust was started in, if it moved itself to `<instance>/supervisor`.
+    Cgroupfs {
+        instance: PathBuf,
+        origin: Option<PathBuf>,
+    },
+    /// Systemd creates a transient scope for each service.
+    Systemd,
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+struct Cgroups {
+    /// Cgroup v2, otherwise cgroup v1, possibly along with an empty cgroup v2 hierarchy.
+    unified: bool,
+    driver: Driver,
+    /// The mount points of the hierarchies: the unified one with cgroup v2, one per controller
+    /// with cgroup v1.
+    hierarchies: Vec<PathBuf>,
+    /// The cgroup of each service, relative to the root of the hierarchies. A service is removed
+    /// once its cgroup is.
+    services: Mutex<HashMap<ServiceName, PathBuf>>,
+}
+
+/// Sets up the cgroup of this horust instance, before any service is started. Horust might not be
+/// allowed to manage the cgroups: the services then run in horust's cgroup, and only the services
+/// with a `[resource-limit]` section fail to start.
+#[cfg(target_os = "linux")]
+pub(crate) fn init(systemd: bool) {
+    let cgroups = Cgroups::new(systemd);
+    match &cgroups {
+        Ok(cgroups) => debug!("Cgroups: {:?}", cgroups),
+        Err(err) if systemd => warn!("Failed to use systemd to manage the cgroups: {:#}", err),
+        Err(err) => debug!("The services won't have their own cgroup: {:#}", err),
+    }
+    let _ = CGROUPS.set(cgroups);
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn init(_systemd: bool) {}
+
+/// Whether the services get their own cgroup, which they have to wait for before exec.
+#[cfg(target_os = "linux")]
+pub(crate) fn enabled() -> bool {
+    matches!(CGROUPS.get(), Some(Ok(_)))
+}
+
+/// Moves the process of the service to its cgroup, then applies its resource limits. Returns the
+/// OOM kills counter of the cgroup, read before the service runs: the cgroup can be reused.
+#[cfg(target_os = "linux")]
+pub(crate) fn add(service: &Service, pid: Pid) -> Result<Option<u64>> {
+    use libcgroups::common::{CgroupConfig, CgroupManager, ControllerOpt, create_cgroup_manager};
+
+    let cgroups = match CGROUPS.get() {
+        Some(Ok(cgroups)) => cgroups,
+        // A cgroup is only needed to apply the limits.
+        _ if service.resource_limit.has_no_limit() => return Ok(None),
+        Some(Err(err)) => return Err(anyhow!("horust has no cgroup: {:#}", err)),
+        None => return Err(anyhow!("horust has no cgroup")),
+    };
+    // libcgroups would ignore it.
+    if !cgroups.unified && service.resource_limit.memory_high.is_some() {
+        return Err(anyhow!("memory-high is only supported with cgroup v2"));
+    }
+    let cgroup_path = cgroups.create(&service.name)?;
+    let manager = create_cgroup_manager(CgroupConfig {
+        cgroup_path: cgroup_path.clone(),
+        systemd_cgroup: matches!(cgroups.driver, Driver::Systemd),
+        container_name: service.name.clone(),
+    })
+    .with_context(|| format!("Failed to create cgroup manager for {}", service.name))?;
+    manager
+        .add_task(pid)
+        .with_context(|| format!("Failed to add task to cgroup {}", service.name))?;
+    let path = match cgroups.driver {
+        Driver::Cgroupfs { .. } => cgroup_path,
+        // Systemd picks the path of the scope.
+        Driver::Systemd => fs::read_to_string(format!("/proc/{}/cgroup", pid))
+            .ok()
+            .and_then(|content| unified_cgroup(&content))
+            .with_context(|| format!("Failed to find the scope of {}", service.name))?,
+    };
+    cgroups
+        .services
+        .lock()
+        .unwrap()
+        .insert(service.name.clone(), path);
+    let oom_kills = oom_kills(&service.name);
+
+    if service.resource_limit.has_no_limit() {
+        return Ok(oom_kills);
+    }
+    manager
+        .apply(&ControllerOpt {
+            resources: &service.resource_limit.resources()?,
+            disable_oom_killer: false,
+            oom_score_adj: None,
+            freezer_state: None,
+        })
+        .with_context(|| format!("Failed to apply resource limits to cgroup {}", service.name))?;
+    Ok(oom_kills)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn add(service: &Service, _pid: Pid) -> Result<Option<u64>> {
+    if !service.resource_limit.has_no_limit() {
+        anyhow::bail!("not supported on macOS");
+    }
+    Ok(None)
+}
+
+/// How many processes of the cgroup of the service were killed by the OOM killer, if it has a
+/// cgroup. The counter is in `memory.events` with cgroup v2, and in `memory.oom_control` with
+/// cgroup v1.
+#[cfg(target_os = "linux")]
+pub(crate) fn oom_kills(service_name: &str) -> Option<u64> {
+    use libcgroups::v1;
+
+    let cgroups = CGROUPS.get()?.as_ref().ok()?;
+    let path = cgroups.services.lock().unwrap().get(service_name)?.clone();
+    let events = if cgroups.unified {
+        in_hierarchy(&cgroups.hierarchies[0], &path).join("memory.events")
+    } else {
+        in_hierarchy(
+            &v1::util::get_subsystem_mount_point(&v1::ControllerType::Memory).ok()?,
+            &path,
+        )
+        .join("memory.oom_control")
+    };
+    fs::read_to_string(events)
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill ")?.parse().ok())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn oom_kills(_service_name: &str) -> Option<u64> {
+    None
+}
+
+/// Removes the cgroup of the service once it has exited, if no process is left in it.
+#[cfg(target_os = "linux")]
+pub(crate) fn remove(service_name: &str) {
+    let Some(Ok(cgroups)) = CGROUPS.get() else {
+        return;
+    };
+    let mut services = cgroups.services.lock().unwrap();
+    if let Some(path) = services.get(service_name)
+        && cgroups.remove_cgroup(path)
+    {
+        services.remove(service_name);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn remove(_service_name: &str) {}
+
+/// Removes the cgroups left when horust exits, and the one of this instance.
+#[cfg(target_os = "linux")]
+pub(crate) fn cleanup() {
+    let Some(Ok(cgroups)) = CGROUPS.get() else {
+        return;
+    };
+    let mut services = cgroups.services.lock().unwrap();
+    services.retain(|_, path| !cgroups.remove_cgroup(path));
+    if let Driver::Cgroupfs { instance, origin } = &cgroups.driver {
+        if let Some(origin) = origin {
+            cgroups.restore(instance, origin);
+        }
+        cgroups.remove_cgroup(&instance.join(SERVICES_CGROUP));
+        cgroups.remove_cgroup(instance);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn cleanup() {}
+
+#[cfg(target_os = "linux")]
+impl Cgroups {
+    fn new(systemd: bool) -> Result<Self> {
+        use libcgroups::common::{CgroupSetup, DEFAULT_CGROUP_ROOT, get_cgroup_setup};
+        use libcgroups::v1;
+
+        let unified = matches!(get_cgroup_setup()?, CgroupSetup::Unified);
+        let hierarchies = if unified {
+            vec![PathBuf::from(DEFAULT_CGROUP_ROOT)]
+        } else {
+            let mut mount_points: Vec<PathBuf> = v1::util::list_supported_mount_points()?
+                .into_values()
+                .collect();
+            // Some controllers share a hierarchy, like cpu and cpuacct.
+            mount_points.sort();
+            mount_points.dedup();
+            mount_points
+        };
+        if systemd {
+            if !unified {
+                anyhow::bail!("systemd-cgroup requires cgroup v2");
+            }
+            if !libcgroups::systemd::booted() {
+                anyhow::bail!("systemd-cgroup requires systemd to be running");
+            }
+            return Ok(Self {
+                unified,
+                driver: Driver::Systemd,
+                hierarchies,
+                services: Default::default(),
+            });
+        }
+
+        // With cgroup v1, each hierarchy has its own path, which a container usually mounts as
+        // the root of the hierarchy.
+        let parent = if unified {
+            fs::read_to_string("/proc/self/cgroup")
+                .ok()
+                .and_then(|content| unified_cgroup(&content))
+                .context("Failed to find horust's cgroup")?
+        } else {
+            PathBuf::from("/")
+        };
+        let first_hierarchy = hierarchies.first().context("No cgroup hierarchy mounted")?;
+        let instance = create_instance(first_hierarchy, &parent)?;
+        let mut cgroups = Self {
+            unified,
+            driver: Driver::Cgroupfs {
+                instance,
+                origin: None,
+            },
+            hierarchies,
+            services: Default::default(),
+        };
+        if unified {
+            cgroups.delegate(&parent);
+        }
+        Ok(cgroups)
+    }
+
+    /// With cgroup v2, the controllers have to be enabled in every parent of the cgroups of the
+    /// services, which can't have processes of their own, except for the root cgroup. If horust
+    /// is alone in the cgroup it was started in, it moves to the leaf `<instance>/supervisor`.
+    fn delegate(&mut self, parent: &Path) {
+        let Driver::Cgroupfs { instance, origin } = &mut self.driver else {
+            return;
+        };
+        let hierarchy = &self.hierarchies[0];
+        let parent_dir = in_hierarchy(hierarchy, parent);
+        let is_root = !parent_dir.join("cgroup.type").exists();
+        let pid = nix::unistd::getpid().to_string();
+        let is_alone = fs::read_to_string(parent_dir.join("cgroup.procs"))
+            .is_ok_and(|procs| procs.lines().all(|proc| proc == pid));
+        if !is_root && is_alone {
+            let supervisor = in_hierarchy(hierarchy, &instance.join(SUPERVISOR_CGROUP));
+            match fs::create_dir(&supervisor)
+                .and_then(|_| fs::write(supervisor.join("cgroup.procs"), &pid))
+            {
+                Ok(()) => *origin = Some(parent.to_path_buf()),
+                Err(err) => {
+                    debug!("Failed to move horust to {}: {}", supervisor.display(), err);
+                    let _ = fs::remove_dir(&supervisor);
+                }
+            }
+        }
+        let services = in_hierarchy(hierarchy, &instance.join(SERVICES_CGROUP));
+        if let Err(err) = fs::create_dir(&services) {
+            debug!("Failed to create cgroup {}: {}", services.display(), err);
+        }
+        let controllers =
+            fs::read_to_string(parent_dir.join("cgroup.controllers")).unwrap_or_default();
+        for dir in [parent_dir, in_hierarchy(hierarchy, instance), services] {
+            for controller in controllers.split_whitespace() {
+                let subtree_control = dir.join("cgroup.subtree_control");
+                if let Err(err) = fs::write(&subtree_control, format!("+{}", controller)) {
+                    debug!(
+                        "Failed to enable {} in {}: {}",
+                        controller,
+                        subtree_control.display(),
+                        err
+                    );
+                }
+            }
+        }
+    }
+
+    /// Undoes `delegate` before horust exits, so that the instance can be removed: disables the
+    /// controllers, as a cgroup enabling them for its children can't have processes, then moves
+    /// horust back to `origin` and removes `<instance>/supervisor`.
+    fn restore(&self, instance: &Path, origin: &Path) {
+        let hierarchy = &self.hierarchies[0];
+        let origin_dir = in_hierarchy(hierarchy, origin);
+        for dir in [in_hierarchy(hierarchy, instance), origin_dir.clone()] {
+            let subtree_control = dir.join("cgroup.subtree_control");
+            let controllers = fs::read_to_string(&subtree_control).unwrap_or_default();
+            for controller in controllers.split_whitespace() {
+                if let Err(err) = fs::write(&subtree_control, format!("-{}", controller)) {
+                    debug!(
+                        "Failed to disable {} in {}: {}",
+                        controller,
+                        subtree_control.display(),
+                        err
+                    );
+                }
+            }
+        }
+        let pid = nix::unistd::getpid().to_string();
+        let supervisor = in_hierarchy(hierarchy, &instance.join(SUPERVISOR_CGROUP));
+        if let Err(err) = fs::write(origin_dir.join("cgroup.procs"), pid)
+            .and_then(|_| fs::remove_dir(&supervisor))
+        {
+            debug!("Failed to remove {}: {}", supervisor.display(), err);
+        }
+    }
+
+    /// Returns the cgroup path to pass to the cgroup manager. With cgroup v2, the cgroup is
+    /// created beforehand: the manager would enable the controllers from the root cgroup down.
+    fn create(&self, service_name: &str) -> Result<PathBuf> {
+        match &self.driver {
+            Driver::Cgroupfs { instance, .. } => {
+                let path = instance
+                    .join(SERVICES_CGROUP)
+                    .join(cgroup_name_escape(service_name));
+                if self.unified {
+                    let dir = in_hierarchy(&self.hierarchies[0], &path);
+                    if let Err(err) = fs::create_dir(&dir)
+                        && err.kind() != std::io::ErrorKind::AlreadyExists
+                    {
+                        return Err(err)
+                            .with_context(|| format!("Failed to create cgroup {}", dir.display()));
+                    }
+                }
+                Ok(path)
+            }
+            Driver::Systemd => Ok(PathBuf::from(format!(
+                ":horust-{}:{}",
+                nix::unistd::getpid(),
+                unit_name_escape(service_name)
+            ))),
+        }
+    }
+
+    /// Removes the cgroup from every hierarchy, returns false if some processes are left in it.
+    /// Systemd removes the scopes by itself.
+    fn remove_cgroup(&self, path: &Path) -> bool {
+        if matches!(self.driver, Driver::Systemd) {
+            return true;
+        }
+        let mut removed = true;
+        for hierarchy in &self.hierarchies {
+            let dir = in_hierarchy(hierarchy, path);
+            match fs::remove_dir(&dir) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => {
+                    debug!("Failed to remove cgroup {}: {}", dir.display(), err);
+                    removed = false;
+                }
+            }
+        }
+        removed
+    }
+}
+
+/// Creates the cgroup of this instance in `parent`. The pid of horust is only unique in its PID
+/// namespace, so a suffix is added if the cgroup exists.
+#[cfg(target_os = "linux")]
+fn create_instance(hierarchy: &Path, parent: &Path) -> Result<PathBuf> {
+    let pid = nix::unistd::getpid();
+    for attempt in 0.. {
+        let instance = match attempt {
+            0 => parent.join(format!("horust-{}", pid)),
+            _ => parent.join(format!("horust-{}-{}", pid, attempt)),
+        };
+        let dir = in_hierarchy(hierarchy, &instance);
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(instance),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Failed to create cgroup {}", dir.display()));
+            }
+        }
+    }
+    unreachable!()
+}
+
+/// The directory of a cgroup in the hierarchy mounted at `hierarchy`.
+#[cfg(target_os = "linux")]
+fn in_hierarchy(hierarchy: &Path, cgroup: &Path) -> PathBuf {
+    hierarchy.join(cgroup.strip_prefix("/").unwrap_or(cgroup))
+}
+
+/// The cgroup v2 path in the content of `/proc/<pid>/cgroup`.
+#[cfg(target_os = "linux")]
+fn unified_cgroup(content: &str) -> Option<PathBuf> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(PathBuf::from)
+}
+
+/// Escapes `/`, `\\` and a leading `.` like `unit_name_escape`, so that the cgroup of a service is a
+/// child of `<instance>/services`.
+#[cfg(target_os = "linux")]
+fn cgroup_name_escape(name: &str) -> String {
+    name.chars()
+        .enumerate()
+        .map(|(i, c)| match c {
+            '.' if i == 0 => format!("\\x{:02x}", c as u32),
+            '/' | '\\' => format!("\\x{:02x}", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// Escapes the characters not allowed in a systemd unit name, like `systemd-escape`.
+#[cfg(target_os = "linux")]
+fn unit_name_escape(name: &str) -> String {
+    name.bytes()
+        .enumerate()
+        .map(|(i, byte)| match byte {
+            b'.' if i == 0 => format!("\\x{:02x}", byte),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'.' => (byte as char).to_string(),
+            _ => format!("\\x{:02x}", byte),
+        })
+        .collect()
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use crate::horust::supervisor::cgroups::{
+        cgroup_name_escape, create_instance, in_hierarchy, unified_cgroup, unit_name_escape,
+    };
+
+    #[test]
+    fn test_unified_cgroup() {
+        let content = "12:memory:/docker/abc\n0::/system.slice/horust.service\n";
+        assert_eq!(
+            unified_cgroup(content),
+            Some(PathBuf::from("/system.slice/horust.service"))
+        );
+        assert_eq!(unified_cgroup("4:memory:/docker/abc\n"), None);
+    }
+
+    #[test]
+    fn test_in_hierarchy() {
+        let hierarchy = Path::new("/sys/fs/cgroup/memory");
+        assert_eq!(
+            in_hierarchy(hierarchy, Path::new("/horust-1/db.toml")),
+            PathBuf::from("/sys/fs/cgroup/memory/horust-1/db.toml")
+        );
+        assert_eq!(in_hierarchy(hierarchy, Path::new("/")), hierarchy);
+    }
+
+    #[test]
+    fn test_create_instance() {
+        let hierarchy = tempfile::TempDir::with_prefix("cgroups").unwrap();
+        let pid = nix::unistd::getpid();
+        let first = create_instance(hierarchy.path(), Path::new("/")).unwrap();
+        assert_eq!(first, PathBuf::from(format!("/horust-{}", pid)));
+        let second = create_instance(hierarchy.path(), Path::new("/")).unwrap();
+        assert_eq!(second, PathBuf::from(format!("/horust-{}-1", pid)));
+        assert!(hierarchy.path().join(format!("horust-{}-1", pid)).is_dir());
+    }
+
+    #[test]
+    fn test_cgroup_name_escape() {
+        assert_eq!(cgroup_name_escape("my db.toml"), "my db.toml");
+        assert_eq!(cgroup_name_escape(".."), "\\x2e.");
+        assert_eq!(cgroup_name_escape("../db"), "\\x2e.\\x2fdb");
+    }
+
+    #[test]
+    fn test_unit_name_escape() {
+        assert_eq!(unit_name_escape("db.toml"), "db.toml");
+        assert_eq!(unit_name_escape("my app:1"), "my\\x20app\\x3a1");
+        assert_eq!(unit_name_escape(".hidden"), "\\x2ehidden");
+    }
+}
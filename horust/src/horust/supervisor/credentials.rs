@@ -0,0 +1,338 @@
+//! The identity a service runs with. It is resolved before forking, because reading /etc/passwd
+//! and /etc/group isn't async-signal-safe, and applied in the child right before exec.
+
+#[cfg(target_os = "linux")]
+use std::fs;
+
+use anyhow::Result;
+#[cfg(target_os = "linux")]
+use anyhow::bail;
+use nix::errno::Errno;
+use nix::unistd::{self, Gid, Uid};
+
+use crate::horust::formats::Service;
+#[cfg(target_os = "linux")]
+use crate::horust::formats::{Capabilities, Capability};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Credentials {
+    uid: Uid,
+    /// None to keep horust's group.
+    gid: Option<Gid>,
+    /// None to keep horust's supplementary groups.
+    groups: Option<Vec<Gid>>,
+    /// None to keep horust's capabilities.
+    #[cfg(target_os = "linux")]
+    capabilities: Option<CapabilitySets>,
+    no_new_privileges: bool,
+}
+
+#[cfg(target_os = "linux")]
+const CAP_SETPCAP: u8 = 8;
+
+/// Capability sets, as bitmasks indexed by capability number.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct CapabilitySets {
+    /// The bounding, permitted, effective and inheritable sets.
+    allowed: u64,
+    ambient: u64,
+    /// The highest capability known by the kernel.
+    last_cap: u8,
+}
+
+#[cfg(target_os = "linux")]
+impl CapabilitySets {
+    /// Only the capabilities horust has can be given to the service: the others are ignored,
+    /// unless the service has its own user namespace, where it has every capability. Dropping
+    /// capabilities from the bounding set requires `CAP_SETPCAP`, with the same exception.
+    fn new(capabilities: &Capabilities, user_namespace: bool) -> Result<Option<Self>> {
+        if capabilities.is_empty() {
+            return Ok(None);
+        }
+        let last_cap = fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+            .ok()
+            .and_then(|last_cap| last_cap.trim().parse::<u8>().ok())
+            .unwrap_or(40)
+            .min(63);
+        let all = u64::MAX >> (63 - last_cap);
+        let status = fs::read_to_string("/proc/self/status").unwrap_or_default();
+        let status_mask = |field: &str| {
+            status.lines().find_map(|line| {
+                let mask = line.strip_prefix(field)?;
+                u64::from_str_radix(mask.trim(), 16).ok()
+            })
+        };
+        let permitted = if user_namespace {
+            all
+        } else {
+            status_mask("CapPrm:").unwrap_or(all)
+        };
+        let mask = |capabilities: &[Capability]| {
+            capabilities
+                .iter()
+                .fold(0, |mask, capability| mask | 1 << capability.index())
+        };
+        let ambient = mask(&capabilities.ambient);
+        let kept = if capabilities.keep.is_empty() {
+            all
+        } else {
+            mask(&capabilities.keep) | ambient
+        };
+        let allowed = kept & !mask(&capabilities.drop) & permitted;
+        if ambient & !allowed != 0 {
+            warn!(
+                "Ambient capabilities {:#x} are not available to horust, they are ignored.",
+                ambient & !allowed
+            );
+        }
+        let bounding = status_mask("CapBnd:").unwrap_or(all);
+        let effective = status_mask("CapEff:").unwrap_or(all);
+        if !user_namespace && bounding & !allowed != 0 && !Self::has(effective, CAP_SETPCAP) {
+            bail!(
+                "Dropping the capabilities {:#x} from the bounding set requires CAP_SETPCAP, \
+                 which horust doesn't have: run it as root, or give the service its own user \
+                 namespace.",
+                bounding & !allowed
+            );
+        }
+        Ok(Some(Self {
+            allowed,
+            ambient: ambient & allowed,
+            last_cap,
+        }))
+    }
+
+    fn has(mask: u64, capability: u8) -> bool {
+        mask & (1 << capability) != 0
+    }
+
+    /// Has to be done before giving up root, as it requires `CAP_SETPCAP`.
+    fn drop_bounding(&self) -> Result<(), Errno> {
+        for capability in 0..=self.last_cap {
+            if Self::has(self.allowed, capability) {
+                continue;
+            }
+            // Safety: prctl has no memory safety preconditions.
+            let in_bounding =
+                unsafe { libc::prctl(libc::PR_CAPBSET_READ, capability as libc::c_ulong, 0, 0, 0) };
+            if Errno::result(in_bounding)? == 1 {
+                // Safety: as above.
+                let res = unsafe {
+                    libc::prctl(libc::PR_CAPBSET_DROP, capability as libc::c_ulong, 0, 0, 0)
+                };
+                Errno::result(res)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the permitted, effective and inheritable sets, then raises the ambient capabilities,
+    /// which requires them to be permitted and inheritable.
+    fn set(&self) -> Result<(), Errno> {
+        #[repr(C)]
+        struct CapUserHeader {
+            version: u32,
+            pid: libc::c_int,
+        }
+        #[repr(C)]
+        struct CapUserData {
+            effective: u32,
+            permitted: u32,
+            inheritable: u32,
+        }
+        const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+        let header = CapUserHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let data = [self.allowed as u32, (self.allowed >> 32) as u32].map(|mask| CapUserData {
+            effective: mask,
+            permitted: mask,
+            inheritable: mask,
+        });
+        // Safety: the header and the two data structs of the version 3 are valid for the call.
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_capset,
+                &header as *const CapUserHeader,
+                data.as_ptr(),
+            )
+        };
+        Errno::result(res)?;
+        for capability in 0..=self.last_cap {
+            if Self::has(self.ambient, capability) {
+                // Safety: prctl has no memory safety preconditions.
+                let res = unsafe {
+                    libc::prctl(
+                        libc::PR_CAP_AMBIENT,
+                        libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong,
+                        capability as libc::c_ulong,
+                        0,
+                        0,
+                    )
+                };
+                Errno::result(res)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Credentials {
+    /// Changing the groups requires privileges. When horust isn't running as root, only the
+    /// `group` and `supplementary-groups` set explicitly are applied, failing if it's not allowed.
+    pub(crate) fn new(service: &Service) -> Result<Self> {
+        let privileged = unistd::geteuid().is_root();
+        let uid = service.user.get_uid()?;
+        let gid = service.get_gid()?;
+        let groups = if privileged || !service.supplementary_groups.is_empty() {
+            Some(service.get_groups(gid)?)
+        } else {
+            None
+        };
+        let gid = (privileged || service.group.is_some()).then_some(gid);
+        #[cfg(not(target_os = "linux"))]
+        if !service.capabilities.is_empty() || service.no_new_privileges {
+            anyhow::bail!("capabilities and no-new-privileges are only supported on Linux");
+        }
+        Ok(Self {
+            uid,
+            gid,
+            groups,
+            #[cfg(target_os = "linux")]
+            capabilities: CapabilitySets::new(&service.capabilities, service.namespaces.user)?,
+            no_new_privileges: service.no_new_privileges,
+        })
+    }
+
+    /// In its own user namespace, the service runs as root, which is mapped to its `user` and
+    /// `group` outside. The supplementary groups can't be set there.
+    pub(crate) fn in_user_namespace(self) -> Self {
+        Self {
+            uid: Uid::from_raw(0),
+            gid: Some(Gid::from_raw(0)),
+            groups: None,
+            ..self
+        }
+    }
+
+    /// Switches to these credentials: the groups and the bounding set have to be set before
+    /// giving up root with `setuid`, which has to keep the capabilities to set them afterward.
+    pub(crate) fn apply(&self) -> Result<(), Errno> {
+        #[cfg(target_os = "linux")]
+        if let Some(capabilities) = &self.capabilities {
+            capabilities.drop_bounding()?;
+            nix::sys::prctl::set_keepcaps(true)?;
+        }
+        if let Some(groups) = &self.groups {
+            unistd::setgroups(groups)?;
+        }
+        if let Some(gid) = self.gid {
+            unistd::setgid(gid)?;
+        }
+        unistd::setuid(self.uid)?;
+        #[cfg(target_os = "linux")]
+        if let Some(capabilities) = &self.capabilities {
+            capabilities.set()?;
+        }
+        #[cfg(target_os = "linux")]
+        if self.no_new_privileges {
+            nix::sys::prctl::set_no_new_privs()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nix::unistd::{self, Gid, Uid};
+
+    #[cfg(target_os = "linux")]
+    use crate::horust::formats::{Capabilities, Capability};
+    use crate::horust::formats::{Group, Service};
+    #[cfg(target_os = "linux")]
+    use crate::horust::supervisor::credentials::CapabilitySets;
+    use crate::horust::supervisor::credentials::Credentials;
+
+    #[test]
+    fn test_credentials() {
+        let service = Service::from_name("credentials");
+        let credentials = Credentials::new(&service).unwrap();
+        assert_eq!(credentials.uid, unistd::getuid());
+        if unistd::geteuid().is_root() {
+            let groups = credentials.groups.as_ref().unwrap();
+            assert!(groups.contains(&credentials.gid.unwrap()));
+        } else {
+            assert_eq!(credentials.gid, None);
+            assert_eq!(credentials.groups, None);
+        }
+
+        let service = Service {
+            group: Some(Group::Gid(4242)),
+            supplementary_groups: vec![Group::Gid(4243), Group::Gid(4243)],
+            ..Service::from_name("credentials")
+        };
+        let credentials = Credentials::new(&service).unwrap();
+        assert_eq!(credentials.gid, Some(Gid::from_raw(4242)));
+        let groups = credentials.groups.unwrap();
+        assert!(groups.contains(&Gid::from_raw(4242)));
+        assert_eq!(groups.iter().filter(|gid| gid.as_raw() == 4243).count(), 1);
+
+        let service = Service {
+            group: Some(Group::Name("surely-not-a-group".into())),
+            ..Service::from_name("credentials")
+        };
+        assert!(Credentials::new(&service).is_err());
+
+        let credentials = Credentials::new(&Service::from_name("credentials"))
+            .unwrap()
+            .in_user_namespace();
+        assert_eq!(credentials.uid, Uid::from_raw(0));
+        assert_eq!(credentials.gid, Some(Gid::from_raw(0)));
+        assert_eq!(credentials.groups, None);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_capability_sets() {
+        let capability = |name: &str| Capability::try_from(name.to_string()).unwrap();
+        assert_eq!(
+            CapabilitySets::new(&Capabilities::default(), false).unwrap(),
+            None
+        );
+
+        let capabilities = Capabilities {
+            keep: vec![capability("CHOWN")],
+            drop: vec![capability("CHOWN")],
+            ambient: vec![capability("NET_BIND_SERVICE")],
+        };
+        // Without CAP_SETPCAP, the bounding set can only be changed in a user namespace.
+        assert_eq!(
+            CapabilitySets::new(&capabilities, false).is_ok(),
+            unistd::geteuid().is_root()
+        );
+        if unistd::geteuid().is_root() {
+            let sets = CapabilitySets::new(&capabilities, false).unwrap().unwrap();
+            assert_eq!(sets.allowed, 1 << 10);
+            assert_eq!(sets.ambient, 1 << 10);
+        }
+        // In its own user namespace, the service has every capability, whatever horust has.
+        let sets = CapabilitySets::new(&capabilities, true).unwrap().unwrap();
+        assert_eq!(sets.allowed, 1 << 10);
+        assert_eq!(sets.ambient, 1 << 10);
+
+        let sets = CapabilitySets::new(
+            &Capabilities {
+                drop: vec![capability("SYS_ADMIN")],
+                ..Default::default()
+            },
+            true,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(sets.allowed & (1 << 21), 0);
+        assert_eq!(sets.allowed >> (sets.last_cap + 1), 0);
+        assert_eq!(sets.ambient, 0);
+    }
+}
@@ -10,18 +10,31 @@ use std::time::{Duration, Instant};
 use nix::sys::signal;
 use nix::unistd;
 
-pub(crate) use process_spawner::find_program;
+pub(crate) use cgroups::init as init_cgroups;
+pub(crate) use credentials::Credentials;
+pub(crate) use process_spawner::{find_program, find_program_in};
+pub(crate) use reaper::{forget_waited, spawn_waited, try_wait_waited};
 use repo::Repo;
 use service_handler::ServiceHandler;
 pub(crate) use signal_handling::init;
 
 use crate::horust::bus::BusConnector;
-use crate::horust::formats::{Event, ExitStatus, Service, ServiceStatus, ShuttingDown};
-use crate::horust::healthcheck;
-
+use crate::horust::formats::{
+    Event, ExitReason, ExitStatus, HealthinessFailureAction, HealthinessStatus, Service,
+    ServiceStatus, ShuttingDown,
+};
+use crate::horust::healthcheck::{self, CustomChecks};
+
+mod cgroups;
+mod credentials;
+mod mounts;
+mod namespaces;
 mod process_spawner;
 mod reaper;
 mod repo;
+mod rlimits;
+mod scheduling;
+mod seccomp;
 mod service_handler;
 mod signal_handling;
 
@@ -89,8 +102,12 @@ const MAX_PROCESS_REAPS_ITERS: u32 = 20;
 const INIT_PID: unistd::Pid = unistd::Pid::from_raw(1);
 
 // Spawns and runs this component in a new thread.
-pub fn spawn(bus: BusConnector<Event>, services: Vec<Service>) -> thread::JoinHandle<ExitStatus> {
-    thread::spawn(move || Supervisor::new(bus, services).run())
+pub fn spawn(
+    bus: BusConnector<Event>,
+    services: Vec<Service>,
+    custom_checks: CustomChecks,
+) -> thread::JoinHandle<ExitStatus> {
+    thread::spawn(move || Supervisor::new(bus, services, custom_checks).run())
 }
 
 #[derive(Clone, Debug, Copy, PartialEq, Eq)]
@@ -104,32 +121,77 @@ pub struct Supervisor {
     /// The system is shutting down, no more services will be spawned.
     status: LifecycleStatus,
     repo: Repo,
+    /// Prepared before starting each service.
+    custom_checks: CustomChecks,
 }
 
 impl Supervisor {
-    fn new(bus: BusConnector<Event>, services: Vec<Service>) -> Self {
+    fn new(bus: BusConnector<Event>, services: Vec<Service>, custom_checks: CustomChecks) -> Self {
         let repo = Repo::new(bus, services);
         Self {
             repo,
             status: LifecycleStatus::Running,
+            custom_checks,
         }
     }
 
     /// Handle the events, returns Events (state changes) to be dispatched.
+    /// Every status transition caused by the event is logged, with structured fields.
     fn handle_event(&mut self, ev: Event) -> Vec<Event> {
+        let previous = ev
+            .service_name()
+            .and_then(|name| self.repo.services.get(name))
+            .map(|sh| (sh.name().clone(), sh.status.clone(), sh.pid));
+        let exit_code = match ev {
+            Event::ServiceExited(_, exit_code, _) => Some(exit_code),
+            _ => None,
+        };
+        let produced_events = self.apply_event(ev);
+        if let Some((service_name, old_status, old_pid)) = previous {
+            let sh = self.repo.get_sh(&service_name);
+            if sh.status != old_status {
+                let new_status = &sh.status;
+                let pid = sh.pid.or(old_pid).map(unistd::Pid::as_raw);
+                info!(
+                    service = service_name.as_str(),
+                    event = "status_changed",
+                    old_status:% = old_status,
+                    new_status:% = new_status,
+                    pid,
+                    exit_code;
+                    "{service_name}: {old_status} ⇾ {new_status}"
+                );
+            }
+        }
+        produced_events
+    }
+
+    fn apply_event(&mut self, ev: Event) -> Vec<Event> {
         match ev {
-            Event::ServiceExited(service_name, exit_code) => {
+            Event::ServiceExited(service_name, exit_code, reason) => {
                 let pid = self.repo.get_sh(&service_name).pid.unwrap();
                 self.repo.remove_pid(pid);
+                cgroups::remove(&service_name);
                 let service_handler = self.repo.get_mut_sh(&service_name);
                 service_handler.shutting_down_start = None;
                 service_handler.pid = None;
+                service_handler.oom_killed = reason == Some(ExitReason::OomKilled);
+                if service_handler.oom_killed {
+                    warn!(
+                        service = service_name.as_str(),
+                        event = "oom_killed",
+                        pid = pid.as_raw();
+                        "Service: {} was OOM-killed, it ran out of memory in its cgroup.",
+                        service_handler.name()
+                    );
+                }
 
-                let has_failed = !service_handler
-                    .service()
-                    .failure
-                    .successful_exit_code
-                    .contains(&exit_code);
+                let has_failed = service_handler.oom_killed
+                    || !service_handler
+                        .service()
+                        .failure
+                        .successful_exit_code
+                        .contains(&exit_code);
 
                 // If it has failed too quickly, increase service_handler's restart attempts
                 // and check if it has more attempts left.
@@ -143,6 +205,10 @@ impl Supervisor {
                         && service_handler.has_some_failed_healthchecks())
                 {
                     warn!(
+                        service = service_name.as_str(),
+                        event = "service_failed",
+                        pid = pid.as_raw(),
+                        exit_code;
                         "Service: {} has failed, exit code: {}, healthchecks: {} ({:?})",
                         service_handler.name(),
                         exit_code,
@@ -152,6 +218,10 @@ impl Supervisor {
                     ServiceStatus::Failed
                 } else {
                     info!(
+                        service = service_name.as_str(),
+                        event = "service_exited",
+                        pid = pid.as_raw(),
+                        exit_code;
                         "Service: {} successfully exited with: {}.",
                         service_handler.name(),
                         exit_code
@@ -171,11 +241,12 @@ impl Supervisor {
                 service_handler.status = ServiceStatus::Starting;
                 let evs = vec![Event::StatusChanged(service_name, ServiceStatus::Starting)];
 
-                let res = healthcheck::prepare_service(&service_handler.service().healthiness);
-                if res.is_err() {
+                let res =
+                    healthcheck::prepare_service(service_handler.service(), &self.custom_checks);
+                if let Err(error) = res {
                     //TODO: maybe this is a bit too aggressive.
                     error!(
-                        "Prepare healthiness checks failed for service: {}, shutting down...",
+                        "Prepare healthiness checks failed for service: {}, shutting down... ({error})",
                         service_handler.name()
                     );
                     service_handler.status = ServiceStatus::FinishedFailed;
@@ -229,17 +300,20 @@ impl Supervisor {
                     ServiceStatus::Failed,
                 )]
             }
-            Event::PidChanged(service_name, pid) => {
+            Event::PidChanged(service_name, pid, oom_kills) => {
                 self.repo.add_pid(pid, service_name.clone());
 
                 let service_handler = self.repo.get_mut_sh(&service_name);
                 service_handler.pid = Some(pid);
+                service_handler.oom_kills = oom_kills;
+                service_handler.sigkilled = false;
                 if service_handler.is_in_killing() {
                     // Ah! Gotcha!
                     service_handler.shutting_down_start = Some(Instant::now());
                     kill(service_handler, None)
                 } else {
                     service_handler.status = ServiceStatus::Started;
+                    service_handler.reset_healthchecks();
                     return vec![Event::StatusChanged(service_name, ServiceStatus::Started)];
                 }
 
@@ -249,7 +323,22 @@ impl Supervisor {
                 let sh = self.repo.get_mut_sh(&s_name);
                 // Count the failed healthiness checks. The state change producer will handle states
                 // changes (if they're needed)
-                sh.add_healthcheck_event(health);
+                sh.add_healthcheck_event(health.clone());
+                if sh.degraded && health == HealthinessStatus::Healthy {
+                    info!(service = s_name.as_str(), event = "recovered"; "{s_name} is healthy again");
+                    sh.degraded = false;
+                    sh.healthiness_checks_failed = Some(0);
+                    return vec![Event::Degraded(s_name, false)];
+                }
+                if !sh.is_running() || !sh.has_exceeded_failed_healthchecks() {
+                    return vec![];
+                }
+                let sh = self.repo.services.get_mut(&s_name).unwrap();
+                handle_healthiness_failure(sh)
+            }
+            Event::ReadinessCheck(s_name, readiness) => {
+                let sh = self.repo.get_mut_sh(&s_name);
+                sh.add_readiness_event(readiness);
                 vec![]
             }
             Event::ShuttingDownInitiated(shutting_down) => {
@@ -318,8 +407,8 @@ impl Supervisor {
             let next_evs: Vec<Event> = self
                 .repo
                 .services
-                .iter()
-                .flat_map(|(_s_name, sh)| sh.next(&self.repo, self.status))
+                .values()
+                .flat_map(|sh| sh.next(&self.repo, self.status))
                 .chain(reaper::run(&self.repo, MAX_PROCESS_REAPS_ITERS))
                 .collect();
             debug!("Next evs: {:?}", next_evs);
@@ -343,6 +432,7 @@ impl Supervisor {
             thread::sleep(Duration::from_secs(3));
             let _res = signal::kill(all_processes, signal::SIGKILL);
         }
+        cgroups::cleanup();
 
         self.repo
             .send_ev(Event::ShuttingDownInitiated(ShuttingDown::Gracefully));
@@ -354,12 +444,50 @@ impl Supervisor {
     }
 }
 
+/// Applies the `on-failure` action of a service whose healthchecks have failed too many times.
+/// `restart` and `stop` are handled by the service handler's FSM.
+fn handle_healthiness_failure(sh: &mut ServiceHandler) -> Vec<Event> {
+    let name = sh.name().clone();
+    match sh.service().healthiness.on_failure {
+        HealthinessFailureAction::Restart | HealthinessFailureAction::Stop => vec![],
+        HealthinessFailureAction::MarkUnhealthy if sh.degraded => vec![],
+        HealthinessFailureAction::MarkUnhealthy => {
+            warn!(service = name.as_str(), event = "degraded"; "{name} is unhealthy, marking it as degraded");
+            sh.degraded = true;
+            vec![Event::Degraded(name, true)]
+        }
+        HealthinessFailureAction::Signal(signal) => {
+            let signal = signal.into();
+            warn!(service = name.as_str(), event = "signaled"; "{name} is unhealthy, sending {signal}");
+            kill(sh, Some(signal));
+            // Signal it again only if the checks keep failing.
+            sh.healthiness_checks_failed = Some(0);
+            vec![]
+        }
+        HealthinessFailureAction::RunCommand => {
+            warn!(
+                service = name.as_str(),
+                event = "running_on_failure_command";
+                "{name} is unhealthy, running its on-failure-command before killing it"
+            );
+            // The health checker kills it once the command is done.
+            let (new_sh, new_status) = sh.change_status(ServiceStatus::InKilling);
+            *sh = new_sh;
+            vec![
+                Event::new_status_changed(&name, new_status),
+                Event::RunOnFailureCommand(name),
+            ]
+        }
+    }
+}
+
 /// A Kill wrapper which will send a signal to sh.
 /// It will send the signal set out in the termination section of the service
-fn kill(sh: &ServiceHandler, signal: Option<signal::Signal>) {
+fn kill(sh: &mut ServiceHandler, signal: Option<signal::Signal>) {
     let signal = signal.unwrap_or_else(|| sh.service().termination.signal.into());
     debug!("Going to send {} signal to pid {:?}", signal, sh.pid());
     if let Some(pid) = sh.pid() {
+        sh.sigkilled |= signal == signal::SIGKILL;
         if let Err(error) = signal::kill(pid, signal) {
             match error {
                 // No process or process group can be found corresponding to that specified by pid
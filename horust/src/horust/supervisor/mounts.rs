@@ -0,0 +1,376 @@
+//! The `[sandbox]` of a service. The mounts are resolved before forking, and the child sets them
+//! up in its own mount namespace, before giving up the privileges they require.
+
+#[cfg(target_os = "linux")]
+use std::ffi::{CStr, CString};
+#[cfg(target_os = "linux")]
+use std::fs::OpenOptions;
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsRawFd, OwnedFd};
+#[cfg(target_os = "linux")]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::OpenOptionsExt;
+#[cfg(target_os = "linux")]
+use std::path::{Path, PathBuf};
+
+#[cfg(target_os = "linux")]
+use anyhow::Context;
+use anyhow::Result;
+use nix::errno::Errno;
+#[cfg(target_os = "linux")]
+use nix::fcntl::OFlag;
+#[cfg(target_os = "linux")]
+use nix::mount::MsFlags;
+#[cfg(target_os = "linux")]
+use nix::sys::stat::Mode;
+
+use crate::horust::formats::Service;
+
+#[cfg(target_os = "linux")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Mount {
+    Bind {
+        source: CString,
+        target: CString,
+        read_only: bool,
+    },
+    /// The procfs of the PID namespace of the service.
+    Proc { target: CString },
+    /// An empty tmpfs, with `options` like `mode=1777`.
+    Tmpfs {
+        target: CString,
+        flags: MsFlags,
+        options: CString,
+    },
+}
+
+#[cfg(target_os = "linux")]
+impl Mount {
+    fn apply(&self) -> Result<(), Errno> {
+        match self {
+            Mount::Bind {
+                source,
+                target,
+                read_only,
+            } => {
+                nix::mount::mount(
+                    Some(source.as_c_str()),
+                    target.as_c_str(),
+                    None::<&CStr>,
+                    MsFlags::MS_BIND | MsFlags::MS_REC,
+                    None::<&CStr>,
+                )?;
+                if *read_only {
+                    make_read_only(target)?;
+                }
+            }
+            Mount::Proc { target } => nix::mount::mount(
+                Some(c"proc"),
+                target.as_c_str(),
+                Some(c"proc"),
+                MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+                None::<&CStr>,
+            )?,
+            Mount::Tmpfs {
+                target,
+                flags,
+                options,
+            } => nix::mount::mount(
+                Some(c"tmpfs"),
+                target.as_c_str(),
+                Some(c"tmpfs"),
+                *flags,
+                Some(options.as_c_str()),
+            )?,
+        }
+        Ok(())
+    }
+}
+
+/// Makes a bind mount read-only, along with its submounts. Its other flags are kept: in a user
+/// namespace, flags like `nosuid` are locked, and the mount can't be changed without them.
+#[cfg(target_os = "linux")]
+fn make_read_only(target: &CStr) -> Result<(), Errno> {
+    use nix::sys::statvfs::{FsFlags, statvfs};
+
+    let attr = libc::mount_attr {
+        attr_set: libc::MOUNT_ATTR_RDONLY,
+        attr_clr: 0,
+        propagation: 0,
+        userns_fd: 0,
+    };
+    // Safety: attr is a valid mount_attr, and its size is passed along.
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr,
+            libc::AT_FDCWD,
+            target.as_ptr(),
+            libc::AT_RECURSIVE as libc::c_uint,
+            &attr as *const libc::mount_attr,
+            size_of::<libc::mount_attr>(),
+        )
+    };
+    match Errno::result(res) {
+        Ok(_) => return Ok(()),
+        // mount_setattr requires Linux 5.12: only the bind mount itself can be remounted before.
+        Err(Errno::ENOSYS) => {}
+        Err(errno) => return Err(errno),
+    }
+    let current = statvfs(target)?.flags();
+    let mut flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY;
+    for (fs_flag, flag) in [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ] {
+        flags.set(flag, current.contains(fs_flag));
+    }
+    nix::mount::mount(None::<&CStr>, target, None::<&CStr>, flags, None::<&CStr>)
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Mounts {
+    /// None if the service doesn't need its own mount namespace.
+    #[cfg(target_os = "linux")]
+    mounts: Option<Vec<Mount>>,
+    /// The sources of the bind mounts, which are mounted from `/proc/self/fd`, as `private-tmp`
+    /// could hide them. They are opened before forking, to reserve their descriptor, then
+    /// reopened in the mount namespace of the service.
+    #[cfg(target_os = "linux")]
+    sources: Vec<(CString, OwnedFd)>,
+    #[cfg(target_os = "linux")]
+    root_directory: Option<CString>,
+}
+
+#[cfg(target_os = "linux")]
+fn to_cstring(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .with_context(|| format!("Invalid path {}", path.display()))
+}
+
+impl Mounts {
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn new(service: &Service) -> Result<Self> {
+        if !service.sandbox.is_empty() {
+            anyhow::bail!("sandbox is only supported on Linux");
+        }
+        Ok(Self::default())
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn new(service: &Service) -> Result<Self> {
+        let sandbox = &service.sandbox;
+        if sandbox.is_empty() && !service.namespaces.pid {
+            return Ok(Self::default());
+        }
+        // The paths in the sandbox, as seen from outside of `root-directory`.
+        let inside = |path: &Path| -> PathBuf {
+            match &sandbox.root_directory {
+                Some(root) => root.join(path.strip_prefix("/").unwrap_or(path)),
+                None => path.to_path_buf(),
+            }
+        };
+        let mut mounts = vec![];
+        // Only shows the processes of the PID namespace.
+        let proc = inside(Path::new("/proc"));
+        if service.namespaces.pid && proc.is_dir() {
+            mounts.push(Mount::Proc {
+                target: to_cstring(&proc)?,
+            });
+        }
+        // Before the bind mounts, which could target /tmp.
+        if sandbox.private_tmp {
+            for tmp in ["/tmp", "/var/tmp"].map(|tmp| inside(Path::new(tmp))) {
+                if tmp.is_dir() {
+                    mounts.push(Mount::Tmpfs {
+                        target: to_cstring(&tmp)?,
+                        flags: MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                        options: c"mode=1777".into(),
+                    });
+                }
+            }
+        }
+        let mut sources = vec![];
+        for bind_mount in &sandbox.bind_mounts {
+            let source: OwnedFd = OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_PATH)
+                .open(&bind_mount.source)
+                .with_context(|| format!("Bind mount source {}", bind_mount.source.display()))?
+                .into();
+            mounts.push(Mount::Bind {
+                source: CString::new(format!("/proc/self/fd/{}", source.as_raw_fd()))?,
+                target: to_cstring(&inside(&bind_mount.target))?,
+                read_only: bind_mount.read_only,
+            });
+            sources.push((to_cstring(&bind_mount.source)?, source));
+        }
+        for path in &sandbox.read_only_paths {
+            let path = to_cstring(&inside(path))?;
+            mounts.push(Mount::Bind {
+                source: path.clone(),
+                target: path,
+                read_only: true,
+            });
+        }
+        for path in &sandbox.inaccessible_paths {
+            let path = inside(path);
+            let metadata = path
+                .metadata()
+                .with_context(|| format!("Inaccessible path {}", path.display()))?;
+            let target = to_cstring(&path)?;
+            mounts.push(if metadata.is_dir() {
+                Mount::Tmpfs {
+                    target,
+                    flags: MsFlags::MS_RDONLY
+                        | MsFlags::MS_NOSUID
+                        | MsFlags::MS_NODEV
+                        | MsFlags::MS_NOEXEC,
+                    options: c"mode=000,size=4k,nr_inodes=1".into(),
+                }
+            } else {
+                Mount::Bind {
+                    source: c"/dev/null".into(),
+                    target,
+                    read_only: true,
+                }
+            });
+        }
+        Ok(Self {
+            mounts: Some(mounts),
+            sources,
+            root_directory: sandbox
+                .root_directory
+                .as_deref()
+                .map(to_cstring)
+                .transpose()?,
+        })
+    }
+
+    /// Moves to a new mount namespace, where the mounts don't propagate back to the host, then
+    /// sets up the mounts and the root directory. Requires `CAP_SYS_ADMIN`.
+    pub(crate) fn apply(&self) -> Result<(), Errno> {
+        #[cfg(target_os = "linux")]
+        if let Some(mounts) = &self.mounts {
+            nix::sched::unshare(nix::sched::CloneFlags::CLONE_NEWNS)?;
+            nix::mount::mount(
+                None::<&CStr>,
+                c"/",
+                None::<&CStr>,
+                MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+                None::<&CStr>,
+            )?;
+            for (path, source) in &self.sources {
+                // The descriptor opened by horust refers to a mount of its namespace, which can't
+                // be bound in this one.
+                let reopened = nix::fcntl::open(
+                    path.as_c_str(),
+                    OFlag::O_PATH | OFlag::O_CLOEXEC,
+                    Mode::empty(),
+                )?;
+                let res = nix::unistd::dup3(reopened, source.as_raw_fd(), OFlag::O_CLOEXEC);
+                let _ = nix::unistd::close(reopened);
+                res?;
+            }
+            mounts.iter().try_for_each(Mount::apply)?;
+            if let Some(root_directory) = &self.root_directory {
+                nix::unistd::chroot(root_directory.as_c_str())?;
+                nix::unistd::chdir(c"/")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use std::ffi::CString;
+    use std::os::fd::AsRawFd;
+
+    use nix::mount::MsFlags;
+
+    use crate::horust::formats::Service;
+    use crate::horust::supervisor::mounts::{Mount, Mounts};
+
+    #[test]
+    fn test_mounts() {
+        assert!(
+            Mounts::new(&Service::from_name("mounts"))
+                .unwrap()
+                .mounts
+                .is_none()
+        );
+
+        let root = tempfile::TempDir::with_prefix("horust").unwrap();
+        let data = tempfile::TempDir::with_prefix("horust").unwrap();
+        std::fs::create_dir_all(root.path().join("tmp")).unwrap();
+        std::fs::create_dir_all(root.path().join("etc/secrets")).unwrap();
+        std::fs::write(root.path().join("etc/passwd"), "").unwrap();
+        let service = format!(
+            r#"command = "/bin/true"
+[sandbox]
+private-tmp = true
+read-only-paths = ["/etc"]
+inaccessible-paths = ["/etc/secrets", "/etc/passwd"]
+bind-mounts = [{{ source = "{}", target = "/data", read-only = true }}]
+root-directory = "{}""#,
+            data.path().display(),
+            root.path().display()
+        );
+        let service: Service = service.parse().unwrap();
+        let mounts = Mounts::new(&service).unwrap();
+        let source = format!("/proc/self/fd/{}", mounts.sources[0].1.as_raw_fd());
+        assert_eq!(
+            std::fs::read_link(&source).unwrap(),
+            data.path().to_path_buf()
+        );
+        let inside = |path: &str| CString::new(format!("{}{path}", root.path().display())).unwrap();
+        assert_eq!(
+            mounts.mounts.unwrap(),
+            vec![
+                // /var/tmp doesn't exist in the root directory.
+                Mount::Tmpfs {
+                    target: inside("/tmp"),
+                    flags: MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                    options: c"mode=1777".into(),
+                },
+                Mount::Bind {
+                    source: CString::new(source).unwrap(),
+                    target: inside("/data"),
+                    read_only: true,
+                },
+                Mount::Bind {
+                    source: inside("/etc"),
+                    target: inside("/etc"),
+                    read_only: true,
+                },
+                Mount::Tmpfs {
+                    target: inside("/etc/secrets"),
+                    flags: MsFlags::MS_RDONLY
+                        | MsFlags::MS_NOSUID
+                        | MsFlags::MS_NODEV
+                        | MsFlags::MS_NOEXEC,
+                    options: c"mode=000,size=4k,nr_inodes=1".into(),
+                },
+                Mount::Bind {
+                    source: c"/dev/null".into(),
+                    target: inside("/etc/passwd"),
+                    read_only: true,
+                },
+            ]
+        );
+        assert_eq!(
+            mounts.root_directory,
+            Some(CString::new(root.path().display().to_string()).unwrap())
+        );
+
+        let mut service = service;
+        service.sandbox.inaccessible_paths = vec!["/surely/not/a/path".into()];
+        service.sandbox.root_directory = None;
+        Mounts::new(&service).unwrap_err();
+    }
+}
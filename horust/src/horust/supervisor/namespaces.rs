@@ -0,0 +1,289 @@
+//! The `[namespaces]` of a service. They are created by forking the service with `clone3`, so that
+//! it's the first process of its PID namespace. The uid and gid maps of a user namespace can only
+//! be written from outside of it: the child waits for horust to write them, and to move it to its
+//! cgroup.
+
+#[cfg(target_os = "linux")]
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsRawFd, OwnedFd};
+
+use anyhow::Result;
+use nix::errno::Errno;
+#[cfg(target_os = "linux")]
+use nix::sched::CloneFlags;
+use nix::unistd::{self, ForkResult, Pid};
+
+use crate::horust::formats::Service;
+#[cfg(target_os = "linux")]
+use crate::horust::supervisor::cgroups;
+
+/// The uid and gid maps of a user namespace.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+struct IdMaps {
+    uid_map: String,
+    gid_map: String,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Namespaces {
+    #[cfg(target_os = "linux")]
+    flags: Option<CloneFlags>,
+    #[cfg(target_os = "linux")]
+    loopback: bool,
+    #[cfg(target_os = "linux")]
+    hostname: Option<String>,
+    #[cfg(target_os = "linux")]
+    id_maps: Option<IdMaps>,
+    /// The pipe the child waits on: horust writes a byte into it once the child is set up.
+    #[cfg(target_os = "linux")]
+    sync_read: Option<OwnedFd>,
+    #[cfg(target_os = "linux")]
+    sync_write: Option<OwnedFd>,
+}
+
+impl Namespaces {
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn new(service: &Service) -> Result<Self> {
+        if !service.namespaces.is_empty() {
+            anyhow::bail!("namespaces are only supported on Linux");
+        }
+        Ok(Self::default())
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn new(service: &Service) -> Result<Self> {
+        let namespaces = &service.namespaces;
+        let mut flags = CloneFlags::empty();
+        for (enabled, flag) in [
+            (namespaces.network, CloneFlags::CLONE_NEWNET),
+            (namespaces.pid, CloneFlags::CLONE_NEWPID),
+            (namespaces.ipc, CloneFlags::CLONE_NEWIPC),
+            (namespaces.uts, CloneFlags::CLONE_NEWUTS),
+            (namespaces.user, CloneFlags::CLONE_NEWUSER),
+        ] {
+            flags.set(flag, enabled);
+        }
+        let id_maps = if namespaces.user {
+            Some(IdMaps {
+                uid_map: format!("0 {} 1", service.user.get_uid()?),
+                gid_map: format!("0 {} 1", service.get_gid()?),
+            })
+        } else {
+            None
+        };
+        // Nothing to wait for without a user namespace nor a cgroup.
+        let (sync_read, sync_write) = if id_maps.is_some() || cgroups::enabled() {
+            // Atomically, so the checks forking from other threads can't inherit the write end.
+            let (read, write) = unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC)?;
+            (Some(read), Some(write))
+        } else {
+            (None, None)
+        };
+        Ok(Self {
+            flags: (!flags.is_empty()).then_some(flags),
+            loopback: namespaces.network,
+            hostname: namespaces.hostname.clone(),
+            id_maps,
+            sync_read,
+            sync_write,
+        })
+    }
+
+    /// Forks the service into its new namespaces.
+    ///
+    /// # Safety
+    ///
+    /// The same as [`unistd::fork`]: the child can only make async-signal-safe calls.
+    pub(crate) unsafe fn fork(&self) -> Result<ForkResult, Errno> {
+        #[cfg(target_os = "linux")]
+        if let Some(flags) = self.flags {
+            #[repr(C)]
+            #[derive(Default)]
+            struct CloneArgs {
+                flags: u64,
+                pidfd: u64,
+                child_tid: u64,
+                parent_tid: u64,
+                exit_signal: u64,
+                stack: u64,
+                stack_size: u64,
+                tls: u64,
+            }
+            let args = CloneArgs {
+                flags: flags.bits() as u64,
+                exit_signal: libc::SIGCHLD as u64,
+                ..Default::default()
+            };
+            // Safety: without a stack, clone3 returns twice like fork.
+            let res = unsafe {
+                libc::syscall(
+                    libc::SYS_clone3,
+                    &args as *const CloneArgs,
+                    size_of::<CloneArgs>(),
+                )
+            };
+            return Ok(match Errno::result(res)? {
+                0 => ForkResult::Child,
+                child => ForkResult::Parent {
+                    child: Pid::from_raw(child as libc::pid_t),
+                },
+            });
+        }
+        // Safety: forwarded to the caller.
+        unsafe { unistd::fork() }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn setup_child(&self, _child: Pid) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called by horust once the child is forked: writes its uid and gid maps.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn setup_child(&self, child: Pid) -> Result<()> {
+        use anyhow::Context;
+        if let Some(id_maps) = &self.id_maps {
+            let proc = format!("/proc/{child}");
+            fs::write(format!("{proc}/uid_map"), &id_maps.uid_map)
+                .with_context(|| format!("Failed to write the uid map {}", id_maps.uid_map))?;
+            // Required to write the gid map without privileges.
+            fs::write(format!("{proc}/setgroups"), "deny")?;
+            fs::write(format!("{proc}/gid_map"), &id_maps.gid_map)
+                .with_context(|| format!("Failed to write the gid map {}", id_maps.gid_map))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn close_sync_writer(&mut self) {}
+
+    /// Called in the child right after the fork: closes its copy of the write end of the sync
+    /// pipe, so that it reads the end of the pipe if horust dies before releasing it.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn close_sync_writer(&mut self) {
+        self.sync_write = None;
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn release_child(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called by horust once the child is set up, e.g. moved to its cgroup: lets it continue.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn release_child(&self) -> Result<()> {
+        if let Some(write) = &self.sync_write {
+            unistd::write(write, &[0])?;
+        }
+        Ok(())
+    }
+
+    /// Waits for horust to set the child up, then brings the loopback interface up and sets the
+    /// hostname.
+    pub(crate) fn apply(&self) -> Result<(), Errno> {
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(read) = &self.sync_read {
+                let read = loop {
+                    match unistd::read(read.as_raw_fd(), &mut [0]) {
+                        Err(Errno::EINTR) => continue,
+                        res => break res?,
+                    }
+                };
+                if read == 0 {
+                    return Err(Errno::EPIPE);
+                }
+            }
+            if self.loopback {
+                loopback_up()?;
+            }
+            if let Some(hostname) = &self.hostname {
+                unistd::sethostname(hostname)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A new network namespace only has a loopback interface, which is down.
+#[cfg(target_os = "linux")]
+fn loopback_up() -> Result<(), Errno> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    // Safety: socket has no memory safety preconditions.
+    let socket = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    // Safety: the descriptor was just opened, and is only owned here.
+    let socket = unsafe { OwnedFd::from_raw_fd(Errno::result(socket)?) };
+    // Safety: ifreq is plain old data.
+    let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (name, byte) in request.ifr_name.iter_mut().zip(b"lo") {
+        *name = *byte as libc::c_char;
+    }
+    // Safety: the request is a valid ifreq, with the name of the interface.
+    let res = unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCGIFFLAGS as _, &mut request) };
+    Errno::result(res)?;
+    // Safety: SIOCGIFFLAGS sets the flags field of the union.
+    unsafe { request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short };
+    // Safety: as above.
+    let res = unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCSIFFLAGS as _, &request) };
+    Errno::result(res)?;
+    Ok(())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use nix::errno::Errno;
+    use nix::sched::CloneFlags;
+    use nix::unistd;
+
+    use crate::horust::formats::Service;
+    use crate::horust::supervisor::namespaces::Namespaces;
+
+    #[test]
+    fn test_namespaces() {
+        let namespaces = Namespaces::new(&Service::from_name("namespaces")).unwrap();
+        assert_eq!(namespaces.flags, None);
+        assert!(namespaces.id_maps.is_none());
+        assert!(namespaces.sync_read.is_none());
+
+        let service: Service = r#"command = "/bin/true"
+[namespaces]
+network = true
+pid = true
+uts = true
+hostname = "sandbox"
+user = true"#
+            .parse()
+            .unwrap();
+        let namespaces = Namespaces::new(&service).unwrap();
+        assert_eq!(
+            namespaces.flags,
+            Some(
+                CloneFlags::CLONE_NEWNET
+                    | CloneFlags::CLONE_NEWPID
+                    | CloneFlags::CLONE_NEWUTS
+                    | CloneFlags::CLONE_NEWUSER
+            )
+        );
+        assert!(namespaces.loopback);
+        assert_eq!(namespaces.hostname.as_deref(), Some("sandbox"));
+        let id_maps = namespaces.id_maps.unwrap();
+        assert_eq!(id_maps.uid_map, format!("0 {} 1", unistd::getuid()));
+        assert_eq!(id_maps.gid_map, format!("0 {} 1", unistd::getgid()));
+    }
+
+    #[test]
+    fn test_sync_pipe_closed() {
+        let service: Service = r#"command = "/bin/true"
+[namespaces]
+user = true"#
+            .parse()
+            .unwrap();
+        // Once the write end is closed, as if horust died, the child stops waiting.
+        let mut namespaces = Namespaces::new(&service).unwrap();
+        namespaces.close_sync_writer();
+        assert_eq!(namespaces.apply(), Err(Errno::EPIPE));
+    }
+}
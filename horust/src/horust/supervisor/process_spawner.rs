@@ -2,22 +2,34 @@ use anyhow::{Context, Result, anyhow};
 use crossbeam::channel::{after, tick};
 use nix::errno::Errno;
 use nix::fcntl;
+use nix::sys::signal::{self, Signal};
 use nix::unistd;
-use nix::unistd::{ForkResult, Pid, Uid, fork};
-use std::ffi::{CStr, CString};
+use nix::unistd::{ForkResult, Pid};
+use std::ffi::{CStr, CString, OsStr};
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{fs::File, io::BufReader};
 use std::{fs::OpenOptions, ops::Add};
 use std::{
-    io::{self, Read},
-    os::fd::OwnedFd,
+    io::{self, Read, Write},
+    os::fd::{OwnedFd, RawFd},
 };
 
 use crate::horust::bus::BusConnector;
 use crate::horust::formats::{Event, LogOutput, Service};
+use crate::horust::healthcheck::ready_pattern::{self, ReadyPatternReader};
+use crate::horust::log_buffer::{self, LogBufferReader, SharedLogBuffer};
+use crate::horust::log_forwarder::forward_records;
 use crate::horust::signal_safe::panic_ssafe;
+use crate::horust::supervisor::cgroups;
+use crate::horust::supervisor::credentials::Credentials;
+use crate::horust::supervisor::mounts::Mounts;
+use crate::horust::supervisor::namespaces::Namespaces;
+use crate::horust::supervisor::rlimits::Rlimits;
+use crate::horust::supervisor::scheduling::Scheduling;
+use crate::horust::supervisor::seccomp::SeccompFilter;
 
 /// Run another thread that will wait for the start delay and handle the fork / exec
 pub(crate) fn spawn_fork_exec_handler(
@@ -44,9 +56,9 @@ pub(crate) fn spawn_fork_exec_handler(
                         }
                     },
                     recv(timeout) -> _ => break match spawn_process(&service) {
-                            Ok(pid) => {
+                            Ok((pid, oom_kills)) => {
                                 debug!("Setting pid:{} for service: {}", pid, service.name);
-                                Event::new_pid_changed(service.name.clone(), pid)
+                                Event::new_pid_changed(service.name.clone(), pid, oom_kills)
                             }
                             Err(error) => {
                                 error!("Failed spawning the process: {}", error);
@@ -81,12 +93,39 @@ fn exec_args(service: &Service) -> Result<(CString, Vec<CString>, Vec<CString>)>
     Ok((CString::new(path)?, arg_cstrings, env_cstrings))
 }
 
+/// What the child sets up before exec. It's resolved before forking, because the child can only
+/// make async-signal-safe calls: the `apply` methods of its parts make no other calls.
+struct ChildSetup {
+    credentials: Credentials,
+    rlimits: Rlimits,
+    scheduling: Scheduling,
+    namespaces: Namespaces,
+    mounts: Mounts,
+    seccomp: SeccompFilter,
+}
+
+impl ChildSetup {
+    fn new(service: &Service) -> Result<Self> {
+        let mut credentials = Credentials::new(service)?;
+        if service.namespaces.user {
+            credentials = credentials.in_user_namespace();
+        }
+        Ok(Self {
+            credentials,
+            rlimits: Rlimits::new(service)?,
+            scheduling: Scheduling::new(service)?,
+            namespaces: Namespaces::new(service)?,
+            mounts: Mounts::new(service)?,
+            seccomp: SeccompFilter::new(service)?,
+        })
+    }
+}
+
 #[inline]
 fn child_process_main(
     service: &Service,
     path: CString,
-    cwd: PathBuf,
-    uid: Uid,
+    setup: &ChildSetup,
     arg_cptr: Vec<&CStr>,
     env_cptr: Vec<&CStr>,
 ) {
@@ -106,7 +145,48 @@ fn child_process_main(
             102,
         );
     }
-    if let Err(errno) = exec(path, arg_cptr, env_cptr, uid, cwd) {
+    // Before exec drops the privileges, which are needed to raise the hard limits.
+    if let Err(errno) = setup.rlimits.apply() {
+        panic_ssafe(
+            "child_process_main: Failed to set the rlimits.",
+            Some(&service.name),
+            errno,
+            104,
+        );
+    }
+    if let Err(errno) = setup.scheduling.apply() {
+        panic_ssafe(
+            "child_process_main: Failed to set the scheduling attributes.",
+            Some(&service.name),
+            errno,
+            105,
+        );
+    }
+    if let Err(errno) = setup.namespaces.apply() {
+        panic_ssafe(
+            "child_process_main: Failed to set up the namespaces.",
+            Some(&service.name),
+            errno,
+            107,
+        );
+    }
+    // After the scheduling attributes, which need /proc, as it might be left out of the sandbox.
+    if let Err(errno) = setup.mounts.apply() {
+        panic_ssafe(
+            "child_process_main: Failed to set up the sandbox.",
+            Some(&service.name),
+            errno,
+            106,
+        );
+    }
+    if let Err(errno) = exec(
+        path,
+        arg_cptr,
+        env_cptr,
+        &setup.credentials,
+        &setup.seccomp,
+        &service.working_directory,
+    ) {
         panic_ssafe(
             "child_process_main: Failed to exec the new process.",
             Some(&service.name),
@@ -116,55 +196,207 @@ fn child_process_main(
     }
 }
 
-/// Fork the process
-fn spawn_process(service: &Service) -> Result<Pid> {
+/// Fork the process, returns its pid and the OOM kills counter of its cgroup before it runs.
+fn spawn_process(service: &Service) -> Result<(Pid, Option<u64>)> {
     debug!("Spawning process for service: {}", service.name);
     let (path, arg_cstrings, env_cstrings) = exec_args(service)?;
-    let uid = service.user.get_uid()?;
-    let cwd = service.working_directory.clone();
+    let mut setup = ChildSetup::new(service)?;
     let arg_cptr: Vec<&CStr> = arg_cstrings.iter().map(|c| c.as_c_str()).collect();
     let env_cptr: Vec<&CStr> = env_cstrings.iter().map(|c| c.as_c_str()).collect();
-    let mut service_copy = service.clone();
-    let (pipe_read, pipe_write) = if service.stdout_rotate_size > 0 {
-        let (pipe_read, pipe_write) = unistd::pipe()?;
-        (Some(pipe_read), Some(pipe_write))
+    let log_buffer = (service.log_buffer_size > 0)
+        .then(|| log_buffer::register(&service.name, service.log_buffer_size as usize));
+    let watches_output = ready_pattern::watches_output(service);
+    let stdout_pipe = if service.stdout_rotate_size > 0
+        || service.stdout.is_forwarded()
+        || log_buffer.is_some()
+        || watches_output
+    {
+        Some(output_pipe()?)
+    } else {
+        None
+    };
+    let stderr_pipe = if service.stderr.is_forwarded() || log_buffer.is_some() || watches_output {
+        Some(output_pipe()?)
     } else {
-        (None, None)
+        None
     };
-    match unsafe { fork() } {
+    let (stdout, stderr) = captured_outputs(
+        service,
+        stdout_pipe.as_ref().map(|(_read, write)| write.as_raw_fd()),
+        stderr_pipe.as_ref().map(|(_read, write)| write.as_raw_fd()),
+    );
+    let mut service_copy = service.clone();
+    service_copy.stdout = stdout;
+    service_copy.stderr = stderr;
+    match unsafe { setup.namespaces.fork() } {
         Ok(ForkResult::Child) => {
-            if let Some(pipe_write) = &pipe_write {
-                drop(pipe_read.unwrap());
-                service_copy.stdout = LogOutput::Pipe(pipe_write.as_raw_fd());
-            }
-            child_process_main(&service_copy, path, cwd, uid, arg_cptr, env_cptr);
+            setup.namespaces.close_sync_writer();
+            let _stdout_write = stdout_pipe.map(|(read, write)| {
+                drop(read);
+                write
+            });
+            let _stderr_write = stderr_pipe.map(|(read, write)| {
+                drop(read);
+                write
+            });
+            child_process_main(&service_copy, path, &setup, arg_cptr, env_cptr);
             unreachable!();
-            // Here the "pipe_write" would go out of scope and its descriptor would be closed.
+            // Here the pipes' write ends would go out of scope and their descriptors would be closed.
             // But because child_process_main() does an exec() and never returns, the raw
-            // descriptor inside the LogOutput::Pipe stays open.
+            // descriptors inside the LogOutput::Pipe stay open.
         }
         Ok(ForkResult::Parent { child, .. }) => {
-            pipe_read.and_then(|pipe| {
-                drop(pipe_write.unwrap());
-                std::thread::spawn(move || {
-                    chunked_writer(pipe, service_copy).map_err(|e| error!("{e}"))
-                });
-                None::<()>
-            });
+            if let Err(err) = setup.namespaces.setup_child(child) {
+                let _ = signal::kill(child, Signal::SIGKILL);
+                return Err(err.context(format!(
+                    "Failed to set up the namespaces of {}",
+                    service.name
+                )));
+            }
             // only the root user and authorized users can manage the cgroup
-            if let Err(err) = service.resource_limit.apply(&service.name, child) {
-                warn!(
-                    "Failed to add the resource limit to {}: {}",
-                    &service.name, err
+            let oom_kills = match cgroups::add(service, child) {
+                Ok(oom_kills) => oom_kills,
+                // The service would run without its resource limits.
+                Err(err) if !service.resource_limit.has_no_limit() => {
+                    let _ = signal::kill(child, Signal::SIGKILL);
+                    return Err(err.context(format!(
+                        "Failed to apply the resource limits of {}",
+                        service.name
+                    )));
+                }
+                Err(err) => {
+                    warn!("Failed to add {} to its cgroup: {:#}", &service.name, err);
+                    None
+                }
+            };
+            if let Err(err) = setup.namespaces.release_child() {
+                let _ = signal::kill(child, Signal::SIGKILL);
+                return Err(err.context(format!("Failed to start {}", service.name)));
+            }
+            if let Some((read, write)) = stdout_pipe {
+                drop(write);
+                let rotate = service.stdout_rotate_size > 0;
+                let destination = service.stdout.clone();
+                spawn_output_writer(
+                    read,
+                    destination,
+                    service.clone(),
+                    OutputStream::Stdout { rotate },
+                    child,
+                    log_buffer.clone(),
+                );
+            }
+            if let Some((read, write)) = stderr_pipe {
+                drop(write);
+                let destination = service.stderr.clone();
+                spawn_output_writer(
+                    read,
+                    destination,
+                    service.clone(),
+                    OutputStream::Stderr,
+                    child,
+                    log_buffer,
                 );
             }
             debug!("Spawned child with PID {}.", child);
-            Ok(child)
+            Ok((child, oom_kills))
         }
         Err(err) => Err(Into::into(err)),
     }
 }
 
+/// Creates a pipe for capturing the output of a service.
+/// Both ends are closed on exec, so other services won't keep them open. The write end is
+/// dup'ed on the service's stdout / stderr, which are not affected.
+fn output_pipe() -> Result<(OwnedFd, OwnedFd)> {
+    // Setting FD_CLOEXEC after pipe() would race with the checks forking from other threads.
+    Ok(unistd::pipe2(fcntl::OFlag::O_CLOEXEC)?)
+}
+
+/// Where the service should write its stdout and stderr, if horust is capturing them.
+/// `stderr = "STDOUT"` (and the other way around) makes both streams share the same pipe.
+fn captured_outputs(
+    service: &Service,
+    stdout_pipe: Option<RawFd>,
+    stderr_pipe: Option<RawFd>,
+) -> (LogOutput, LogOutput) {
+    let stdout = match (&service.stdout, stdout_pipe, stderr_pipe) {
+        (LogOutput::Stderr, _, Some(pipe)) | (_, Some(pipe), _) => LogOutput::Pipe(pipe),
+        (stdout, None, _) => stdout.clone(),
+    };
+    let stderr = match (&service.stderr, stdout_pipe, stderr_pipe) {
+        (LogOutput::Stdout, Some(pipe), _) | (_, _, Some(pipe)) => LogOutput::Pipe(pipe),
+        (stderr, _, None) => stderr.clone(),
+    };
+    (stdout, stderr)
+}
+
+/// The service's stream read by an output writer.
+#[derive(Clone, Copy, Debug)]
+enum OutputStream {
+    Stdout { rotate: bool },
+    Stderr,
+}
+
+impl OutputStream {
+    fn name(&self) -> &'static str {
+        match self {
+            OutputStream::Stdout { .. } => "stdout",
+            OutputStream::Stderr => "stderr",
+        }
+    }
+}
+
+/// Runs a thread forwarding what the service writes on `source` to `destination`.
+fn spawn_output_writer(
+    source: OwnedFd,
+    destination: LogOutput,
+    service: Service,
+    stream: OutputStream,
+    pid: Pid,
+    log_buffer: Option<SharedLogBuffer>,
+) {
+    std::thread::spawn(move || {
+        let source = LogBufferReader::new(File::from(source), log_buffer);
+        let source = ReadyPatternReader::new(source, &service);
+        let res = match &destination {
+            LogOutput::Path(_) if matches!(stream, OutputStream::Stdout { rotate: true }) => {
+                chunked_writer(source, service)
+            }
+            LogOutput::Syslog(_) | LogOutput::JsonLines(_) => {
+                forward_records(source, &destination, &service.name, stream.name(), pid)
+            }
+            LogOutput::Path(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .mode(0o700)
+                .open(path)
+                .map_err(Into::into)
+                .and_then(|file| forward_output(source, file)),
+            LogOutput::Stderr => forward_output(source, io::stderr()),
+            _ => forward_output(source, io::stdout()),
+        };
+        res.map_err(|e| error!("{e}"))
+    });
+}
+
+/// Copies source into destination as soon as new content is available.
+fn forward_output(mut source: impl Read, mut destination: impl Write) -> Result<()> {
+    let mut buf = [0; 8192];
+    loop {
+        let read = match source.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        destination.write_all(&buf[..read])?;
+        destination.flush()?;
+    }
+    debug!("EOF reached");
+    Ok(())
+}
+
 /// Sets up the stdout / stderr descriptors.
 fn redirect_output(
     target_stream: &LogOutput,
@@ -232,14 +464,13 @@ fn open_next_chunk(
         .open(path)
 }
 
-fn chunked_writer(fd: OwnedFd, service: Service) -> Result<()> {
-    let source = File::from(fd);
+fn chunked_writer(source: impl Read, service: Service) -> Result<()> {
     let path = match &service.stdout {
         LogOutput::Path(path) => path,
         _ => return Err(anyhow!("Log output path is not set")),
     };
     let mut chunk = 0;
-    let mut reader = BufReader::new(&source);
+    let mut reader = BufReader::new(source);
     // Get the current Unix timestamp
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -265,13 +496,17 @@ fn chunked_writer(fd: OwnedFd, service: Service) -> Result<()> {
 
 /// Find program on PATH.
 ///
-pub(crate) fn find_program(program_name: &String) -> Result<String> {
+pub(crate) fn find_program(program_name: &str) -> Result<String> {
     let path_var = match std::env::var_os("PATH") {
         Some(val) => val,
         None => return Err(anyhow!("PATH environment variable is not set")),
     };
+    find_program_in(program_name, &path_var)
+}
 
-    let paths: Vec<PathBuf> = std::env::split_paths(&path_var).collect();
+/// Find program in the directories listed in `path_var`, using the format of the PATH variable.
+pub(crate) fn find_program_in(program_name: &str, path_var: &OsStr) -> Result<String> {
+    let paths: Vec<PathBuf> = std::env::split_paths(path_var).collect();
 
     for path in paths {
         let program_path = path.join(program_name);
@@ -298,15 +533,18 @@ fn exec(
     path: CString,
     arg_cptr: Vec<&CStr>,
     env_cptr: Vec<&CStr>,
-    uid: Uid,
-    cwd: PathBuf,
+    credentials: &Credentials,
+    seccomp: &SeccompFilter,
+    cwd: &Path,
 ) -> std::result::Result<(), Errno> {
     // Changes the current working directory to the specified path.
-    unistd::chdir(&cwd)?;
+    unistd::chdir(cwd)?;
     // Create new session and set process group id
     unistd::setsid()?;
-    // Set the user ID
-    unistd::setuid(uid)?;
+    // Set the groups and the user ID
+    credentials.apply()?;
+    // Last, so that the profile only has to allow the syscalls of the service, and execve.
+    seccomp.apply()?;
     unistd::execve(path.as_ref(), arg_cptr.as_ref(), env_cptr.as_ref())?;
     Ok(())
 }
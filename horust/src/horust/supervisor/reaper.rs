@@ -3,12 +3,55 @@
 //! collect its exit status. This module will make sure to reap also zombie processes and not only
 //! child services.
 
+use std::collections::BTreeMap;
+use std::io;
+use std::process::{Child, Command};
+use std::sync::Mutex;
+
+use nix::errno::Errno;
+use nix::sys::signal::Signal;
 use nix::sys::wait::{WaitPidFlag, WaitStatus, waitpid};
 use nix::unistd::Pid;
 
 use crate::horust::Event;
+use crate::horust::formats::ExitReason;
 use crate::horust::supervisor::repo::Repo;
 
+/// Processes spawned by another thread, which waits for them: their exit status is kept here by
+/// the reaper until that thread collects it.
+static WAITED_PIDS: Mutex<BTreeMap<Pid, Option<WaitStatus>>> = Mutex::new(BTreeMap::new());
+
+/// Spawns a process whose exit status is collected by the caller, with `try_wait_waited`.
+/// `forget_waited` has to be called if it gives up waiting for it.
+pub(crate) fn spawn_waited(command: &mut Command) -> io::Result<Child> {
+    // Locked until the pid is known, so the reaper can't collect the process before.
+    let mut waited_pids = WAITED_PIDS.lock().unwrap();
+    let child = command.spawn()?;
+    waited_pids.insert(Pid::from_raw(child.id() as i32), None);
+    Ok(child)
+}
+
+/// Returns the exit status of a process spawned with `spawn_waited` once it has exited, then the
+/// process is forgotten. It is collected here, unless the reaper was faster.
+pub(crate) fn try_wait_waited(pid: Pid) -> nix::Result<Option<WaitStatus>> {
+    let mut waited_pids = WAITED_PIDS.lock().unwrap();
+    let status = match waited_pids.get(&pid) {
+        Some(Some(status)) => *status,
+        Some(None) => match waitpid(pid, Some(WaitPidFlag::WNOHANG))? {
+            WaitStatus::StillAlive => return Ok(None),
+            status => status,
+        },
+        None => return Err(Errno::ECHILD),
+    };
+    waited_pids.remove(&pid);
+    Ok(Some(status))
+}
+
+/// The process is left to the reaper.
+pub(crate) fn forget_waited(pid: Pid) {
+    WAITED_PIDS.lock().unwrap().remove(&pid);
+}
+
 /// Reaps up to `max_iterations` dead processes
 ///
 /// # Safety
@@ -16,21 +59,36 @@ use crate::horust::supervisor::repo::Repo;
 /// This function must run in isolation with respect to the fork processes to
 /// prevent pid reusage.
 pub(crate) fn run(repo: &Repo, max_iterations: u32) -> Vec<Event> {
+    let mut waited_pids = WAITED_PIDS.lock().unwrap();
     (0..max_iterations)
         .filter_map(
             |_| match waitpid(Pid::from_raw(-1), Some(WaitPidFlag::WNOHANG)) {
+                Ok(wait_status)
+                    if wait_status
+                        .pid()
+                        .is_some_and(|pid| waited_pids.contains_key(&pid)) =>
+                {
+                    // Kept for the thread waiting for it.
+                    waited_pids.insert(wait_status.pid().unwrap(), Some(wait_status));
+                    None
+                }
                 Ok(wait_status) => match wait_status {
                     WaitStatus::Exited(pid, exit_code) => {
                         debug!("Pid '{}' has exited with status: {}", pid, exit_code);
                         repo.get_service_by_pid(pid)
-                            .map(|s_name| (s_name, exit_code))
+                            .map(|s_name| (s_name, exit_code, None))
                     }
                     WaitStatus::Signaled(pid, signal, core_dumped) => {
                         debug!(
                             "Pid '{:?}' has exited due to signal: {:?}. Core dumped: {}",
                             pid, signal, core_dumped
                         );
-                        repo.get_service_by_pid(pid).map(|s_name| (s_name, -137))
+                        repo.get_service_by_pid(pid).map(|s_name| {
+                            // The OOM killer sends SIGKILL, which is all we'd see otherwise.
+                            let oom_killed =
+                                signal == Signal::SIGKILL && repo.services[s_name].is_oom_killed();
+                            (s_name, -137, oom_killed.then_some(ExitReason::OomKilled))
+                        })
                     }
                     _ => None,
                 },
@@ -50,9 +108,9 @@ pub(crate) fn run(repo: &Repo, max_iterations: u32) -> Vec<Event> {
                 }
             },
         )
-        .map(|(sname, exit_code)| {
+        .map(|(sname, exit_code, reason)| {
             debug!("Service '{:?}' has exited.", sname);
-            Event::new_service_exited(sname.into(), exit_code)
+            Event::new_service_exited(sname.into(), exit_code, reason)
         })
         .collect()
 }
@@ -93,14 +93,14 @@ impl Repo {
     }
 
     /// Checks if the service is runnable. So the current status is Initial, and
-    /// all the start-after have started or finished.
+    /// all the start-after are ready (running and passing their readiness checks) or finished.
     pub(crate) fn is_service_runnable(&self, sh: &ServiceHandler) -> bool {
         if !sh.is_initial() {
             return false;
         }
         let is_started = |service_name: &ServiceName| {
             let sh = self.services.get(service_name).unwrap();
-            sh.is_running() || sh.is_finished()
+            sh.is_ready() || sh.is_finished()
         };
         sh.start_after().iter().all(is_started)
     }
@@ -126,7 +126,7 @@ impl Repo {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::horust::formats::ServiceStatus;
+    use crate::horust::formats::{HealthinessStatus, ServiceStatus};
 
     use crate::horust::supervisor::test_utils::{
         make_repo, make_repo_from_services, make_repo_with_start_after,
@@ -219,6 +219,26 @@ mod test {
         assert!(repo.is_service_runnable(sh));
     }
 
+    #[test]
+    fn test_is_runnable_dependency_ready() {
+        let mut dep = Service::from_name("dep");
+        dep.readiness.command = Some("true".into());
+        let mut svc = Service::from_name("svc");
+        svc.start_after = vec!["dep".into()];
+        let mut repo = make_repo_from_services(vec![dep, svc]);
+        repo.services.get_mut("dep").unwrap().status = ServiceStatus::Running;
+        let sh = repo.services.get("svc").unwrap().clone();
+        assert!(!repo.is_service_runnable(&sh));
+
+        let dep = repo.services.get_mut("dep").unwrap();
+        dep.add_readiness_event(HealthinessStatus::Healthy);
+        assert!(repo.is_service_runnable(&sh));
+
+        let dep = repo.services.get_mut("dep").unwrap();
+        dep.add_readiness_event(HealthinessStatus::Unhealthy);
+        assert!(!repo.is_service_runnable(&sh));
+    }
+
     #[test]
     fn test_is_runnable_dependency_finished() {
         let repo = make_repo_with_start_after(vec![
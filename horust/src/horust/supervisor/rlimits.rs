@@ -0,0 +1,114 @@
+//! The `[rlimits]` of a service. They are resolved before forking, and set in the child before it
+//! gives up its privileges, which are needed to raise a hard limit.
+
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::sys::resource::{Resource, getrlimit, setrlimit};
+
+use crate::horust::formats::{RlimitResource, Service};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Rlimits(Vec<(Resource, u64, u64)>);
+
+impl Rlimits {
+    pub(crate) fn new(service: &Service) -> Result<Self> {
+        service
+            .rlimits
+            .iter()
+            .map(|(resource, rlimit)| {
+                let name = format!("{resource:?}").to_lowercase();
+                let resource = to_resource(*resource)
+                    .with_context(|| format!("rlimit {name} is not supported on this platform"))?;
+                let (soft, hard) = rlimit.resolve(getrlimit(resource)?);
+                anyhow::ensure!(
+                    soft <= hard,
+                    "The soft {name} limit {soft} is above the hard limit {hard}"
+                );
+                Ok((resource, soft, hard))
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+
+    /// Sets the limits of the calling process.
+    pub(crate) fn apply(&self) -> Result<(), Errno> {
+        self.0
+            .iter()
+            .try_for_each(|(resource, soft, hard)| setrlimit(*resource, *soft, *hard))
+    }
+}
+
+fn to_resource(resource: RlimitResource) -> Option<Resource> {
+    Some(match resource {
+        RlimitResource::As => Resource::RLIMIT_AS,
+        RlimitResource::Core => Resource::RLIMIT_CORE,
+        RlimitResource::Cpu => Resource::RLIMIT_CPU,
+        RlimitResource::Data => Resource::RLIMIT_DATA,
+        RlimitResource::Fsize => Resource::RLIMIT_FSIZE,
+        RlimitResource::Nofile => Resource::RLIMIT_NOFILE,
+        RlimitResource::Stack => Resource::RLIMIT_STACK,
+        #[cfg(target_os = "linux")]
+        RlimitResource::Locks => Resource::RLIMIT_LOCKS,
+        #[cfg(target_os = "linux")]
+        RlimitResource::Memlock => Resource::RLIMIT_MEMLOCK,
+        #[cfg(target_os = "linux")]
+        RlimitResource::Msgqueue => Resource::RLIMIT_MSGQUEUE,
+        #[cfg(target_os = "linux")]
+        RlimitResource::Nice => Resource::RLIMIT_NICE,
+        #[cfg(target_os = "linux")]
+        RlimitResource::Nproc => Resource::RLIMIT_NPROC,
+        #[cfg(target_os = "linux")]
+        RlimitResource::Rss => Resource::RLIMIT_RSS,
+        #[cfg(target_os = "linux")]
+        RlimitResource::Rtprio => Resource::RLIMIT_RTPRIO,
+        #[cfg(target_os = "linux")]
+        RlimitResource::Rttime => Resource::RLIMIT_RTTIME,
+        #[cfg(target_os = "linux")]
+        RlimitResource::Sigpending => Resource::RLIMIT_SIGPENDING,
+        #[cfg(not(target_os = "linux"))]
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use nix::sys::resource::{Resource, getrlimit};
+
+    use crate::horust::formats::{Rlimit, RlimitResource, Service};
+    use crate::horust::supervisor::rlimits::Rlimits;
+
+    #[test]
+    fn test_rlimits() {
+        let (soft, hard) = getrlimit(Resource::RLIMIT_NOFILE).unwrap();
+        let service = |rlimit| Service {
+            rlimits: BTreeMap::from([(RlimitResource::Nofile, rlimit)]),
+            ..Service::from_name("rlimits")
+        };
+        let rlimits = Rlimits::new(&service(Rlimit {
+            soft: Some(16),
+            hard: None,
+        }))
+        .unwrap();
+        assert_eq!(rlimits, Rlimits(vec![(Resource::RLIMIT_NOFILE, 16, hard)]));
+
+        let rlimits = Rlimits::new(&service(Rlimit {
+            soft: None,
+            hard: Some(soft),
+        }))
+        .unwrap();
+        assert_eq!(
+            rlimits,
+            Rlimits(vec![(Resource::RLIMIT_NOFILE, soft, soft)])
+        );
+
+        if hard != Rlimit::UNLIMITED {
+            Rlimits::new(&service(Rlimit {
+                soft: Some(hard + 1),
+                hard: None,
+            }))
+            .unwrap_err();
+        }
+    }
+}
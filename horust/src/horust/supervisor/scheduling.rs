@@ -0,0 +1,150 @@
+//! The scheduling attributes and the umask of a service. Like the rlimits, they are set in the
+//! child before it gives up its privileges, which are needed to raise its priority.
+
+#[cfg(target_os = "linux")]
+use anyhow::Context;
+use anyhow::Result;
+use nix::errno::Errno;
+#[cfg(target_os = "linux")]
+use nix::sched::CpuSet;
+use nix::sys::stat::{self, Mode};
+
+#[cfg(target_os = "linux")]
+use crate::horust::formats::IoSchedulingClass;
+use crate::horust::formats::Service;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Scheduling {
+    nice: Option<libc::c_int>,
+    /// The value for `ioprio_set`: the class and the priority.
+    #[cfg(target_os = "linux")]
+    ioprio: Option<libc::c_int>,
+    /// Written to /proc/self/oom_score_adj.
+    #[cfg(target_os = "linux")]
+    oom_score_adj: Option<Vec<u8>>,
+    #[cfg(target_os = "linux")]
+    cpu_set: Option<CpuSet>,
+    umask: Option<Mode>,
+}
+
+impl Scheduling {
+    pub(crate) fn new(service: &Service) -> Result<Self> {
+        #[cfg(not(target_os = "linux"))]
+        if service.io_scheduling.is_some()
+            || service.oom_score_adjust.is_some()
+            || service.cpu_affinity.is_some()
+        {
+            anyhow::bail!(
+                "io-scheduling, oom-score-adjust and cpu-affinity are only supported on Linux"
+            );
+        }
+        Ok(Self {
+            nice: service.nice,
+            #[cfg(target_os = "linux")]
+            ioprio: service.io_scheduling.map(|io_scheduling| {
+                const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+                let class = match io_scheduling.class {
+                    IoSchedulingClass::Realtime => 1,
+                    IoSchedulingClass::BestEffort => 2,
+                    IoSchedulingClass::Idle => 3,
+                };
+                class << IOPRIO_CLASS_SHIFT | libc::c_int::from(io_scheduling.priority)
+            }),
+            #[cfg(target_os = "linux")]
+            oom_score_adj: service
+                .oom_score_adjust
+                .map(|adjust| adjust.to_string().into_bytes()),
+            #[cfg(target_os = "linux")]
+            cpu_set: service
+                .cpu_affinity
+                .as_ref()
+                .map(|cpus| {
+                    let mut cpu_set = CpuSet::new();
+                    cpus.cpus().iter().try_for_each(|cpu| {
+                        cpu_set
+                            .set(*cpu)
+                            .with_context(|| format!("CPU {cpu} is out of range"))
+                    })?;
+                    anyhow::Ok(cpu_set)
+                })
+                .transpose()?,
+            umask: service
+                .umask
+                .map(|umask| Mode::from_bits_truncate(umask.bits() as libc::mode_t)),
+        })
+    }
+
+    /// Applies the settings to the calling process.
+    pub(crate) fn apply(&self) -> Result<(), Errno> {
+        if let Some(nice) = self.nice {
+            // Safety: setpriority has no memory safety preconditions.
+            let res = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) };
+            Errno::result(res)?;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(ioprio) = self.ioprio {
+            const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+            // Safety: ioprio_set has no memory safety preconditions.
+            let res = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) };
+            Errno::result(res)?;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(oom_score_adj) = &self.oom_score_adj {
+            use std::os::fd::{FromRawFd, OwnedFd};
+
+            use nix::fcntl::{self, OFlag};
+            let fd = fcntl::open(
+                c"/proc/self/oom_score_adj",
+                OFlag::O_WRONLY | OFlag::O_CLOEXEC,
+                Mode::empty(),
+            )?;
+            // Safety: the descriptor was just opened, and is only owned here.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            nix::unistd::write(&fd, oom_score_adj)?;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(cpu_set) = &self.cpu_set {
+            nix::sched::sched_setaffinity(nix::unistd::Pid::from_raw(0), cpu_set)?;
+        }
+        if let Some(umask) = self.umask {
+            stat::umask(umask);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use crate::horust::formats::{CpuList, IoScheduling, IoSchedulingClass, Service};
+    use crate::horust::supervisor::scheduling::Scheduling;
+
+    #[test]
+    fn test_scheduling() {
+        assert_eq!(
+            Scheduling::new(&Service::from_name("scheduling")).unwrap(),
+            Scheduling::default()
+        );
+
+        let service = Service {
+            io_scheduling: Some(IoScheduling {
+                class: IoSchedulingClass::Idle,
+                priority: 7,
+            }),
+            oom_score_adjust: Some(-10),
+            cpu_affinity: Some(CpuList::try_from("0,2".to_string()).unwrap()),
+            ..Service::from_name("scheduling")
+        };
+        let scheduling = Scheduling::new(&service).unwrap();
+        assert_eq!(scheduling.ioprio, Some(3 << 13 | 7));
+        assert_eq!(scheduling.oom_score_adj.as_deref(), Some(&b"-10"[..]));
+        let cpu_set = scheduling.cpu_set.unwrap();
+        assert!(cpu_set.is_set(0).unwrap() && cpu_set.is_set(2).unwrap());
+        assert!(!cpu_set.is_set(1).unwrap());
+
+        let service = Service {
+            cpu_affinity: Some(CpuList::try_from("100000".to_string()).unwrap()),
+            ..Service::from_name("scheduling")
+        };
+        Scheduling::new(&service).unwrap_err();
+    }
+}
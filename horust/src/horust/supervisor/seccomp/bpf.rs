@@ -0,0 +1,444 @@
+//! Compiles a seccomp profile to a classic BPF program: the rules are checked in order, and the
+//! first one matching the syscall and its arguments decides what happens.
+
+use anyhow::{Context, Result, bail};
+use oci_spec::runtime::{LinuxSeccomp, LinuxSeccompAction, LinuxSeccompArg, LinuxSeccompOperator};
+
+use super::syscalls;
+use crate::horust::formats::SeccompAction;
+
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_ALU_AND_K: u16 = 0x54;
+const BPF_JEQ_K: u16 = 0x15;
+const BPF_JGT_K: u16 = 0x25;
+const BPF_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+/// The maximum length of a program accepted by the kernel.
+const BPF_MAXINSNS: usize = 4096;
+
+/// The offsets of the fields of `struct seccomp_data`.
+const NR: u32 = 0;
+const ARCH: u32 = 4;
+const ARGS: u32 = 16;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+/// Set in the numbers of the syscalls of the x32 ABI, which shares x86_64's `AUDIT_ARCH`.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+#[derive(Clone, Copy)]
+enum Jump {
+    Next,
+    Label(usize),
+}
+
+struct Instruction {
+    code: u16,
+    jt: Jump,
+    jf: Jump,
+    k: u32,
+}
+
+/// A program being built: the jumps go to labels, which are resolved once it's complete.
+#[derive(Default)]
+struct Program {
+    instructions: Vec<Instruction>,
+    labels: Vec<Option<usize>>,
+}
+
+impl Program {
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    /// The label points to the next instruction.
+    fn place(&mut self, label: usize) {
+        self.labels[label] = Some(self.instructions.len());
+    }
+
+    fn jump(&mut self, code: u16, k: u32, jt: Jump, jf: Jump) {
+        self.instructions.push(Instruction { code, jt, jf, k });
+    }
+
+    fn statement(&mut self, code: u16, k: u32) {
+        self.jump(code, k, Jump::Next, Jump::Next);
+    }
+
+    fn load(&mut self, offset: u32) {
+        self.statement(BPF_LD_W_ABS, offset);
+    }
+
+    fn ret(&mut self, action: u32) {
+        self.statement(BPF_RET_K, action);
+    }
+
+    fn assemble(self) -> Result<Vec<libc::sock_filter>> {
+        if self.instructions.len() > BPF_MAXINSNS {
+            bail!(
+                "The seccomp filter has {} instructions, the maximum is {BPF_MAXINSNS}",
+                self.instructions.len()
+            );
+        }
+        let offset = |from: usize, jump: Jump| -> Result<u8> {
+            match jump {
+                Jump::Next => Ok(0),
+                Jump::Label(label) => {
+                    let to = self.labels[label].expect("Jump to a label which wasn't placed");
+                    u8::try_from(to - from - 1).context(
+                        "The seccomp filter is too large: a jump spans more than 255 instructions",
+                    )
+                }
+            }
+        };
+        self.instructions
+            .iter()
+            .enumerate()
+            .map(|(i, instruction)| {
+                Ok(libc::sock_filter {
+                    code: instruction.code,
+                    jt: offset(i, instruction.jt)?,
+                    jf: offset(i, instruction.jf)?,
+                    k: instruction.k,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The offsets of the high and low halves of the argument `index`.
+fn arg_offsets(index: usize) -> Result<(u32, u32)> {
+    if index >= 6 {
+        bail!("Invalid seccomp argument index {index}, syscalls have 6 arguments");
+    }
+    let arg = ARGS + 8 * index as u32;
+    Ok(if cfg!(target_endian = "little") {
+        (arg + 4, arg)
+    } else {
+        (arg, arg + 4)
+    })
+}
+
+/// Jumps to `pass` if the argument matches, to `fail` otherwise. The 64 bits values are compared
+/// a half at a time.
+fn compare(program: &mut Program, arg: &LinuxSeccompArg, pass: usize, fail: usize) -> Result<()> {
+    use LinuxSeccompOperator::*;
+
+    let (high, low) = arg_offsets(arg.index())?;
+    let split = |value: u64| ((value >> 32) as u32, value as u32);
+    let (pass, fail, next) = (Jump::Label(pass), Jump::Label(fail), Jump::Next);
+    let (value_high, value_low) = split(arg.value());
+    program.load(high);
+    match arg.op() {
+        ScmpCmpEq => {
+            program.jump(BPF_JEQ_K, value_high, next, fail);
+            program.load(low);
+            program.jump(BPF_JEQ_K, value_low, pass, fail);
+        }
+        ScmpCmpNe => {
+            program.jump(BPF_JEQ_K, value_high, next, pass);
+            program.load(low);
+            program.jump(BPF_JEQ_K, value_low, fail, pass);
+        }
+        ScmpCmpMaskedEq => {
+            // `value` is the mask, `valueTwo` the expected value.
+            let (mask_high, mask_low) = (value_high, value_low);
+            let (value_high, value_low) = split(arg.value_two().unwrap_or_default());
+            program.statement(BPF_ALU_AND_K, mask_high);
+            program.jump(BPF_JEQ_K, value_high, next, fail);
+            program.load(low);
+            program.statement(BPF_ALU_AND_K, mask_low);
+            program.jump(BPF_JEQ_K, value_low, pass, fail);
+        }
+        ScmpCmpGt | ScmpCmpGe => {
+            program.jump(BPF_JGT_K, value_high, pass, next);
+            program.jump(BPF_JEQ_K, value_high, next, fail);
+            program.load(low);
+            let code = if arg.op() == ScmpCmpGt {
+                BPF_JGT_K
+            } else {
+                BPF_JGE_K
+            };
+            program.jump(code, value_low, pass, fail);
+        }
+        ScmpCmpLt | ScmpCmpLe => {
+            program.jump(BPF_JGE_K, value_high, next, pass);
+            program.jump(BPF_JEQ_K, value_high, next, fail);
+            program.load(low);
+            let code = if arg.op() == ScmpCmpLt {
+                BPF_JGE_K
+            } else {
+                BPF_JGT_K
+            };
+            program.jump(code, value_low, fail, pass);
+        }
+    }
+    Ok(())
+}
+
+/// The value returned by the filter, given the action of the profile and the one configured for
+/// the service.
+fn action(
+    action: LinuxSeccompAction,
+    errno_ret: Option<u32>,
+    configured: Option<SeccompAction>,
+) -> Result<u32> {
+    Ok(match (action, configured) {
+        (LinuxSeccompAction::ScmpActNotify, _) => bail!("SCMP_ACT_NOTIFY is not supported"),
+        (LinuxSeccompAction::ScmpActAllow, _) => libc::SECCOMP_RET_ALLOW,
+        (action, None) => action.as_u32(errno_ret),
+        (action, Some(SeccompAction::Errno)) => {
+            let errno = errno_ret
+                .filter(|_| action == LinuxSeccompAction::ScmpActErrno)
+                .unwrap_or(libc::EPERM as u32);
+            libc::SECCOMP_RET_ERRNO | (errno & libc::SECCOMP_RET_DATA)
+        }
+        (_, Some(SeccompAction::Kill)) => libc::SECCOMP_RET_KILL_PROCESS,
+        (_, Some(SeccompAction::Log)) => libc::SECCOMP_RET_LOG,
+    })
+}
+
+/// Compiles `profile` for the architecture horust runs on, ignoring the syscalls which don't exist
+/// there. `configured` replaces the actions of the profile denying a syscall.
+/// Like with runc, the arguments of a rule must all match, unless several of them compare the same
+/// argument: then any of them is enough.
+pub(super) fn compile(
+    profile: &LinuxSeccomp,
+    configured: Option<SeccompAction>,
+) -> Result<Vec<libc::sock_filter>> {
+    let mut program = Program::default();
+    let native = program.label();
+    program.load(ARCH);
+    program.jump(BPF_JEQ_K, AUDIT_ARCH, Jump::Label(native), Jump::Next);
+    program.ret(libc::SECCOMP_RET_KILL_PROCESS);
+    program.place(native);
+    #[cfg(target_arch = "x86_64")]
+    {
+        let not_x32 = program.label();
+        program.load(NR);
+        program.jump(BPF_JGE_K, X32_SYSCALL_BIT, Jump::Next, Jump::Label(not_x32));
+        program.ret(libc::SECCOMP_RET_KILL_PROCESS);
+        program.place(not_x32);
+    }
+    for rule in profile.syscalls().iter().flatten() {
+        let ret = action(rule.action(), rule.errno_ret(), configured)?;
+        let args = rule.args().as_deref().unwrap_or_default();
+        let any_arg = args
+            .iter()
+            .enumerate()
+            .any(|(i, arg)| args[..i].iter().any(|other| other.index() == arg.index()));
+        for name in rule.names() {
+            let Some(number) = syscalls::number(name) else {
+                if syscalls::exists_on_other_arch(name) {
+                    debug!("Syscall {name} doesn't exist on this architecture, skipping it.");
+                } else {
+                    warn!("Syscall {name} is unknown, skipping it.");
+                }
+                continue;
+            };
+            let next_rule = program.label();
+            program.load(NR);
+            program.jump(BPF_JEQ_K, number, Jump::Next, Jump::Label(next_rule));
+            if any_arg {
+                let matched = program.label();
+                for (i, arg) in args.iter().enumerate() {
+                    let fail = if i + 1 == args.len() {
+                        next_rule
+                    } else {
+                        program.label()
+                    };
+                    compare(&mut program, arg, matched, fail)?;
+                    if fail != next_rule {
+                        program.place(fail);
+                    }
+                }
+                program.place(matched);
+            } else {
+                for arg in args {
+                    let pass = program.label();
+                    compare(&mut program, arg, pass, next_rule)?;
+                    program.place(pass);
+                }
+            }
+            program.ret(ret);
+            program.place(next_rule);
+        }
+    }
+    program.ret(action(
+        profile.default_action(),
+        profile.default_errno_ret(),
+        configured,
+    )?);
+    program.assemble()
+}
+
+#[cfg(test)]
+pub(super) mod test {
+    use oci_spec::runtime::{
+        LinuxSeccomp, LinuxSeccompAction, LinuxSeccompArg, LinuxSeccompArgBuilder,
+        LinuxSeccompBuilder, LinuxSeccompOperator, LinuxSyscallBuilder,
+    };
+
+    use super::{
+        ARCH, ARGS, AUDIT_ARCH, BPF_ALU_AND_K, BPF_JEQ_K, BPF_JGE_K, BPF_JGT_K, BPF_LD_W_ABS,
+        BPF_RET_K, NR, compile,
+    };
+    use crate::horust::formats::SeccompAction;
+
+    /// Runs `program` like the kernel would, on a syscall of the native architecture.
+    pub(in crate::horust::supervisor::seccomp) fn run(
+        program: &[libc::sock_filter],
+        nr: libc::c_long,
+        args: [u64; 6],
+    ) -> u32 {
+        run_on(program, AUDIT_ARCH, nr, args)
+    }
+
+    fn run_on(program: &[libc::sock_filter], arch: u32, nr: libc::c_long, args: [u64; 6]) -> u32 {
+        let mut data = [0u8; 64];
+        data[NR as usize..][..4].copy_from_slice(&(nr as u32).to_ne_bytes());
+        data[ARCH as usize..][..4].copy_from_slice(&arch.to_ne_bytes());
+        for (i, arg) in args.iter().enumerate() {
+            data[ARGS as usize + 8 * i..][..8].copy_from_slice(&arg.to_ne_bytes());
+        }
+        let (mut a, mut pc) = (0u32, 0);
+        loop {
+            let instruction = program[pc];
+            pc += 1;
+            let k = instruction.k;
+            let jump = |condition: bool| {
+                usize::from(if condition {
+                    instruction.jt
+                } else {
+                    instruction.jf
+                })
+            };
+            match instruction.code {
+                BPF_LD_W_ABS => a = u32::from_ne_bytes(data[k as usize..][..4].try_into().unwrap()),
+                BPF_ALU_AND_K => a &= k,
+                BPF_JEQ_K => pc += jump(a == k),
+                BPF_JGT_K => pc += jump(a > k),
+                BPF_JGE_K => pc += jump(a >= k),
+                BPF_RET_K => return k,
+                code => panic!("Unexpected instruction {code:#x}"),
+            }
+        }
+    }
+
+    fn profile(op: LinuxSeccompOperator, value: u64, value_two: Option<u64>) -> LinuxSeccomp {
+        let mut arg = LinuxSeccompArgBuilder::default()
+            .index(1usize)
+            .value(value)
+            .op(op);
+        if let Some(value_two) = value_two {
+            arg = arg.value_two(value_two);
+        }
+        profile_with_args(vec![arg.build().unwrap()])
+    }
+
+    fn profile_with_args(args: Vec<LinuxSeccompArg>) -> LinuxSeccomp {
+        LinuxSeccompBuilder::default()
+            .default_action(LinuxSeccompAction::ScmpActAllow)
+            .syscalls(vec![
+                LinuxSyscallBuilder::default()
+                    .names(vec!["read".to_string(), "not_a_syscall".to_string()])
+                    .action(LinuxSeccompAction::ScmpActErrno)
+                    .errno_ret(libc::EBADF as u32)
+                    .args(args)
+                    .build()
+                    .unwrap(),
+            ])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_compile() {
+        use LinuxSeccompOperator::*;
+
+        let denied = libc::SECCOMP_RET_ERRNO | libc::EBADF as u32;
+        let allowed = libc::SECCOMP_RET_ALLOW;
+        let high = 5 << 32;
+        let cases = [
+            (ScmpCmpEq, high | 7, None, [high | 7], [7, high | 8]),
+            (ScmpCmpNe, high | 7, None, [7], [high | 7, high | 7]),
+            (ScmpCmpGt, high | 7, None, [high | 8], [high | 7, 7]),
+            (ScmpCmpGe, high | 7, None, [6 << 32], [high | 6, 8]),
+            (ScmpCmpLt, high | 7, None, [high | 6], [high | 7, 6 << 32]),
+            (ScmpCmpLe, high | 7, None, [8], [high | 8, 6 << 32]),
+            (
+                ScmpCmpMaskedEq,
+                high | 0xf0,
+                Some(0x30),
+                [0x3f],
+                [high | 0x30, 0x4f],
+            ),
+        ];
+        for (op, value, value_two, matching, not_matching) in cases {
+            let program = compile(&profile(op, value, value_two), None).unwrap();
+            for arg in matching {
+                let args = [0, arg, 0, 0, 0, 0];
+                assert_eq!(
+                    run(&program, libc::SYS_read, args),
+                    denied,
+                    "{op:?} {arg:#x}"
+                );
+                assert_eq!(run(&program, libc::SYS_write, args), allowed);
+            }
+            for arg in not_matching {
+                let args = [0, arg, 0, 0, 0, 0];
+                assert_eq!(
+                    run(&program, libc::SYS_read, args),
+                    allowed,
+                    "{op:?} {arg:#x}"
+                );
+            }
+        }
+
+        let profile = profile(ScmpCmpEq, 1, None);
+        let args = [0, 1, 0, 0, 0, 0];
+        let program = compile(&profile, Some(SeccompAction::Kill)).unwrap();
+        assert_eq!(
+            run(&program, libc::SYS_read, args),
+            libc::SECCOMP_RET_KILL_PROCESS
+        );
+        let program = compile(&profile, Some(SeccompAction::Errno)).unwrap();
+        assert_eq!(run(&program, libc::SYS_read, args), denied);
+        let program = compile(&profile, Some(SeccompAction::Log)).unwrap();
+        assert_eq!(run(&program, libc::SYS_read, args), libc::SECCOMP_RET_LOG);
+        assert_eq!(
+            run_on(&program, 0x4000_0003, libc::SYS_write, args),
+            libc::SECCOMP_RET_KILL_PROCESS
+        );
+    }
+
+    #[test]
+    fn test_compile_args() {
+        let denied = libc::SECCOMP_RET_ERRNO | libc::EBADF as u32;
+        let allowed = libc::SECCOMP_RET_ALLOW;
+        let eq = |index: usize, value: u64| {
+            LinuxSeccompArgBuilder::default()
+                .index(index)
+                .value(value)
+                .op(LinuxSeccompOperator::ScmpCmpEq)
+                .build()
+                .unwrap()
+        };
+        // The arguments of different indexes must all match.
+        let program = compile(&profile_with_args(vec![eq(0, 1), eq(1, 2)]), None).unwrap();
+        assert_eq!(run(&program, libc::SYS_read, [1, 2, 0, 0, 0, 0]), denied);
+        assert_eq!(run(&program, libc::SYS_read, [1, 3, 0, 0, 0, 0]), allowed);
+        assert_eq!(run(&program, libc::SYS_read, [0, 2, 0, 0, 0, 0]), allowed);
+        // Any of them is enough if some have the same index, like with runc.
+        let program =
+            compile(&profile_with_args(vec![eq(1, 1), eq(1, 2), eq(0, 5)]), None).unwrap();
+        for args in [[0, 1, 0, 0, 0, 0], [0, 2, 0, 0, 0, 0], [5, 3, 0, 0, 0, 0]] {
+            assert_eq!(run(&program, libc::SYS_read, args), denied, "{args:?}");
+        }
+        assert_eq!(run(&program, libc::SYS_read, [0, 3, 0, 0, 0, 0]), allowed);
+        assert_eq!(run(&program, libc::SYS_write, [0, 1, 0, 0, 0, 0]), allowed);
+    }
+}
@@ -0,0 +1,132 @@
+//! The `seccomp` filter of a service. The profile is compiled to a BPF program before forking, and
+//! the child loads it right before `execve`, once it doesn't need any other syscall.
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod bpf;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod profiles;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod syscalls;
+
+use anyhow::Result;
+use nix::errno::Errno;
+
+use crate::horust::formats::Service;
+
+#[derive(Default)]
+pub(crate) struct SeccompFilter {
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    program: Option<Vec<libc::sock_filter>>,
+}
+
+impl SeccompFilter {
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    pub(crate) fn new(service: &Service) -> Result<Self> {
+        if service.seccomp.is_some() {
+            anyhow::bail!("seccomp is only supported on Linux, on x86_64 and aarch64");
+        }
+        Ok(Self::default())
+    }
+
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    pub(crate) fn new(service: &Service) -> Result<Self> {
+        use anyhow::Context;
+
+        use crate::horust::formats::SeccompProfile;
+
+        let Some(seccomp) = &service.seccomp else {
+            return Ok(Self::default());
+        };
+        let profile = match &seccomp.profile {
+            SeccompProfile::Default => profiles::default()?,
+            SeccompProfile::NoNetwork => profiles::no_network()?,
+            SeccompProfile::Path(path) => std::fs::read_to_string(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|profile| Ok(serde_json::from_str(&profile)?))
+                .with_context(|| format!("Invalid seccomp profile {}", path.display()))?,
+        };
+        Ok(Self {
+            program: Some(bpf::compile(&profile, seccomp.action)?),
+        })
+    }
+
+    /// Sets `no_new_privs`, which is required to load the filter without privileges, then loads
+    /// it.
+    pub(crate) fn apply(&self) -> Result<(), Errno> {
+        #[cfg(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))]
+        if let Some(program) = &self.program {
+            nix::sys::prctl::set_no_new_privs()?;
+            let prog = libc::sock_fprog {
+                len: program.len() as libc::c_ushort,
+                filter: program.as_ptr().cast_mut(),
+            };
+            // Safety: the program is valid while the filter is loaded, the kernel copies it.
+            let res = unsafe {
+                libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER as libc::c_ulong,
+                    &prog as *const libc::sock_fprog,
+                )
+            };
+            Errno::result(res)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(
+    test,
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod test {
+    use crate::horust::formats::Service;
+    use crate::horust::supervisor::seccomp::SeccompFilter;
+
+    #[test]
+    fn test_seccomp_filter() {
+        let filter = SeccompFilter::new(&Service::from_name("seccomp")).unwrap();
+        assert!(filter.program.is_none());
+
+        let profile = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            profile.path(),
+            r#"{
+                "defaultAction": "SCMP_ACT_ALLOW",
+                "syscalls": [{ "names": ["mkdir", "mkdirat"], "action": "SCMP_ACT_ERRNO" }]
+            }"#,
+        )
+        .unwrap();
+        let service: Service = format!(
+            "command = \"/bin/true\"\nseccomp = \"{}\"",
+            profile.path().display()
+        )
+        .parse()
+        .unwrap();
+        assert!(SeccompFilter::new(&service).unwrap().program.is_some());
+
+        std::fs::write(profile.path(), "not json").unwrap();
+        assert!(SeccompFilter::new(&service).is_err());
+    }
+}
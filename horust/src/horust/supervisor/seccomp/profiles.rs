@@ -0,0 +1,161 @@
+//! The built-in seccomp profiles.
+
+use anyhow::Result;
+use oci_spec::runtime::{
+    LinuxSeccomp, LinuxSeccompAction, LinuxSeccompArgBuilder, LinuxSeccompBuilder,
+    LinuxSeccompOperator, LinuxSyscall, LinuxSyscallBuilder,
+};
+
+/// The syscalls administering the system, which a service shouldn't need.
+const ADMINISTRATION: &[&str] = &[
+    "acct",
+    "add_key",
+    "bpf",
+    "clock_adjtime",
+    "clock_settime",
+    "delete_module",
+    "finit_module",
+    "fsconfig",
+    "fsmount",
+    "fsopen",
+    "fspick",
+    "init_module",
+    "ioperm",
+    "iopl",
+    "kcmp",
+    "kexec_file_load",
+    "kexec_load",
+    "keyctl",
+    "lookup_dcookie",
+    "mount",
+    "move_mount",
+    "name_to_handle_at",
+    "open_by_handle_at",
+    "open_tree",
+    "perf_event_open",
+    "pivot_root",
+    "process_vm_readv",
+    "process_vm_writev",
+    "ptrace",
+    "quotactl",
+    "reboot",
+    "request_key",
+    "setns",
+    "settimeofday",
+    "swapoff",
+    "swapon",
+    "syslog",
+    "umount2",
+    "unshare",
+    "uselib",
+    "userfaultfd",
+    "vhangup",
+];
+
+fn administration() -> Result<LinuxSyscall> {
+    Ok(LinuxSyscallBuilder::default()
+        .names(
+            ADMINISTRATION
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+        )
+        .action(LinuxSeccompAction::ScmpActErrno)
+        .errno_ret(libc::EPERM as u32)
+        .build()?)
+}
+
+fn allow_by_default(syscalls: Vec<LinuxSyscall>) -> Result<LinuxSeccomp> {
+    Ok(LinuxSeccompBuilder::default()
+        .default_action(LinuxSeccompAction::ScmpActAllow)
+        .syscalls(syscalls)
+        .build()?)
+}
+
+pub(super) fn default() -> Result<LinuxSeccomp> {
+    allow_by_default(vec![administration()?])
+}
+
+/// The io_uring syscalls: io_uring can create sockets without calling `socket`.
+const IO_URING: &[&str] = &["io_uring_enter", "io_uring_register", "io_uring_setup"];
+
+/// Only the sockets of the `AF_UNIX` family can be created. io_uring is denied as if the kernel
+/// didn't support it, so that programs fall back to the regular syscalls.
+pub(super) fn no_network() -> Result<LinuxSeccomp> {
+    let socket = LinuxSyscallBuilder::default()
+        .names(vec!["socket".to_string()])
+        .action(LinuxSeccompAction::ScmpActErrno)
+        .errno_ret(libc::EAFNOSUPPORT as u32)
+        .args(vec![
+            LinuxSeccompArgBuilder::default()
+                .index(0usize)
+                .value(libc::AF_UNIX as u64)
+                .op(LinuxSeccompOperator::ScmpCmpNe)
+                .build()?,
+        ])
+        .build()?;
+    let io_uring = LinuxSyscallBuilder::default()
+        .names(IO_URING.iter().map(ToString::to_string).collect::<Vec<_>>())
+        .action(LinuxSeccompAction::ScmpActErrno)
+        .errno_ret(libc::ENOSYS as u32)
+        .build()?;
+    allow_by_default(vec![socket, io_uring, administration()?])
+}
+
+#[cfg(test)]
+mod test {
+    use crate::horust::supervisor::seccomp::bpf::compile;
+    use crate::horust::supervisor::seccomp::bpf::test::run;
+    use crate::horust::supervisor::seccomp::profiles::{
+        ADMINISTRATION, IO_URING, default, no_network,
+    };
+    use crate::horust::supervisor::seccomp::syscalls;
+
+    #[test]
+    fn test_profiles() {
+        // Some of them, like ioperm, don't exist on aarch64.
+        for name in ADMINISTRATION {
+            assert!(
+                syscalls::number(name).is_some() || syscalls::exists_on_other_arch(name),
+                "{name}"
+            );
+            if cfg!(target_arch = "x86_64") {
+                assert!(syscalls::number(name).is_some(), "{name}");
+            }
+        }
+        if cfg!(target_arch = "aarch64") {
+            assert_eq!(syscalls::number("kexec_file_load"), Some(294));
+            assert_eq!(syscalls::number("ioperm"), None);
+        }
+        if cfg!(target_arch = "x86_64") {
+            assert_eq!(syscalls::number("create_module"), Some(174));
+        }
+        assert_eq!(syscalls::number("mount"), Some(libc::SYS_mount as u32));
+        assert_eq!(syscalls::number("not_a_syscall"), None);
+        assert_eq!(default().unwrap().syscalls().as_ref().unwrap().len(), 1);
+        assert_eq!(no_network().unwrap().syscalls().as_ref().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_no_network() {
+        let program = compile(&no_network().unwrap(), None).unwrap();
+        let socket = |family: libc::c_int| {
+            let args = [family as u64, libc::SOCK_STREAM as u64, 0, 0, 0, 0];
+            run(&program, libc::SYS_socket, args)
+        };
+        assert_eq!(socket(libc::AF_UNIX), libc::SECCOMP_RET_ALLOW);
+        assert_eq!(
+            socket(libc::AF_INET),
+            libc::SECCOMP_RET_ERRNO | libc::EAFNOSUPPORT as u32
+        );
+        // IORING_OP_SOCKET would create the socket without calling socket().
+        for name in IO_URING {
+            let number = syscalls::number(name).unwrap();
+            assert_eq!(
+                run(&program, number.into(), [0; 6]),
+                libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32,
+                "{name}"
+            );
+        }
+    }
+}
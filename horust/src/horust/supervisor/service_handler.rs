@@ -4,13 +4,15 @@ use nix::unistd::Pid;
 
 use crate::horust::Event;
 use crate::horust::formats::{
-    FailureStrategy, HealthinessStatus, RestartStrategy, Service, ServiceName, ServiceStatus,
+    FailureStrategy, HealthinessFailureAction, HealthinessStatus, OomStrategy, RestartStrategy,
+    Service, ServiceName, ServiceStatus,
 };
+use crate::horust::supervisor::cgroups;
 use crate::horust::supervisor::repo::Repo;
 
 use super::{LifecycleStatus, ShuttingDown};
 
-#[derive(Clone, Debug, Eq, PartialEq, Default)]
+#[derive(Clone, Debug, PartialEq, Default)]
 pub(crate) struct ServiceHandler {
     service: Service,
     /// Status of this service.
@@ -21,6 +23,16 @@ pub(crate) struct ServiceHandler {
     pub(super) restart_attempts: u32,
     /// Amount of healthiness checks failed, applies only if the service is running
     pub(super) healthiness_checks_failed: Option<i32>,
+    /// Whether the last readiness check has passed, reset every time the service is started.
+    pub(super) readiness_passed: bool,
+    /// Marked unhealthy by `on-failure = "mark-unhealthy"`, until a liveness check passes again.
+    pub(super) degraded: bool,
+    /// The OOM kills counter of the cgroup of the service when it was started, if it has one.
+    pub(super) oom_kills: Option<u64>,
+    /// The last run of the service was OOM-killed.
+    pub(super) oom_killed: bool,
+    /// Horust has sent SIGKILL to the current run of the service.
+    pub(super) sigkilled: bool,
     /// Instant representing at which time we received a shutdown request. Will be used for comparing Service.termination.wait
     pub(super) shutting_down_start: Option<Instant>,
 }
@@ -90,6 +102,54 @@ impl ServiceHandler {
         self.healthiness_checks_failed = Some(previous_hc + new_hc);
     }
 
+    pub fn add_readiness_event(&mut self, check: HealthinessStatus) {
+        if !self.is_alive_state() {
+            return;
+        }
+        let passed = check == HealthinessStatus::Healthy;
+        if passed != self.readiness_passed {
+            if passed {
+                info!(service = self.name().as_str(), event = "ready"; "{} is ready", self.name());
+            } else {
+                warn!(service = self.name().as_str(), event = "not_ready"; "{} is not ready", self.name());
+            }
+        }
+        self.readiness_passed = passed;
+    }
+
+    /// Forgets the checks of the previous run of the service.
+    pub(super) fn reset_healthchecks(&mut self) {
+        self.healthiness_checks_failed = None;
+        self.readiness_passed = false;
+        self.degraded = false;
+    }
+
+    /// The OOM killer has killed a process of the cgroup of the service since it was started. A
+    /// service killed by horust isn't counted, even if the OOM killer has killed one of its
+    /// processes.
+    pub(crate) fn is_oom_killed(&self) -> bool {
+        !self.is_killed_by_horust()
+            && self.oom_kills.is_some_and(|before| {
+                cgroups::oom_kills(self.name()).is_some_and(|now| now > before)
+            })
+    }
+
+    /// Horust is stopping the service, or has sent it SIGKILL.
+    fn is_killed_by_horust(&self) -> bool {
+        self.is_in_killing() || self.sigkilled
+    }
+
+    /// More liveness checks have failed than `max-failed` allows.
+    pub fn has_exceeded_failed_healthchecks(&self) -> bool {
+        self.healthiness_checks_failed.unwrap_or(-1) > self.service.healthiness.max_failed
+    }
+
+    /// A service is ready when it is running and its readiness checks (if any) are passing.
+    pub fn is_ready(&self) -> bool {
+        self.is_running()
+            && (self.readiness_passed || !self.service.readiness.has_any_check_defined())
+    }
+
     pub fn is_finished_failed(&self) -> bool {
         matches!(self.status, ServiceStatus::FinishedFailed)
     }
@@ -151,11 +211,14 @@ fn next_events(repo: &Repo, service_handler: &ServiceHandler) -> Vec<Event> {
         ServiceStatus::Started if !service_handler.has_some_failed_healthchecks() => {
             vev_status(ServiceStatus::Running)
         }
-        // This will kill the service after 3 failed healthchecks in a row.
-        // Maybe this should be parametrized
+        // This will kill the service after `max-failed` failed healthchecks. The other
+        // `on-failure` actions are applied by the supervisor when the healthcheck event arrives.
         ServiceStatus::Running
-            if service_handler.healthiness_checks_failed.unwrap_or(-1)
-                > service_handler.service.healthiness.max_failed =>
+            if service_handler.has_exceeded_failed_healthchecks()
+                && matches!(
+                    service_handler.service.healthiness.on_failure,
+                    HealthinessFailureAction::Restart | HealthinessFailureAction::Stop
+                ) =>
         {
             vec![
                 ev_status(ServiceStatus::InKilling),
@@ -262,6 +325,7 @@ fn handle_status_change(
             Started => {
                 new_service_handler.status = Started;
                 new_service_handler.restart_attempts = 0;
+                new_service_handler.reset_healthchecks();
             }
             InKilling if service_handler.status == Initial => {
                 // Nothing to do here, the service was never started.
@@ -291,6 +355,30 @@ fn handle_status_change(
 
 /// Produces events based on the Restart Strategy of the service.
 fn handle_restart_strategy(service_handler: &ServiceHandler, is_failed: bool) -> Event {
+    if service_handler.service.healthiness.on_failure == HealthinessFailureAction::Stop
+        && service_handler.has_exceeded_failed_healthchecks()
+    {
+        debug!("Stopped because of its failed healthchecks, not restarting it.");
+        let new_status = if is_failed {
+            ServiceStatus::FinishedFailed
+        } else {
+            ServiceStatus::Finished
+        };
+        return Event::new_status_update(service_handler.name(), new_status);
+    }
+    if let Some(on_oom) = service_handler
+        .service
+        .restart
+        .on_oom
+        .filter(|_| service_handler.oom_killed)
+    {
+        let new_status = match on_oom {
+            OomStrategy::Restart => ServiceStatus::Initial,
+            OomStrategy::Stop => ServiceStatus::FinishedFailed,
+        };
+        debug!("OOM-killed, on-oom strategy applied: {:?}", new_status);
+        return Event::new_status_update(service_handler.name(), new_status);
+    }
     let new_status = match service_handler.service.restart.strategy {
         RestartStrategy::Never if is_failed => {
             debug!(
@@ -407,6 +495,65 @@ strategy = "{}"
             });
     }
 
+    #[test]
+    fn test_handle_restart_strategy_on_failure_stop() {
+        let service = r#"name="servicename"
+command = "Not relevant"
+[restart]
+strategy = "always"
+[healthiness]
+on-failure = "stop"
+"#;
+        let service: Service = Service::from_str(service).unwrap();
+        let mut sh: ServiceHandler = service.into();
+        sh.healthiness_checks_failed = Some(4);
+        let new_status = |status| Event::new_status_update("servicename", status);
+        assert_eq!(
+            handle_restart_strategy(&sh, true),
+            new_status(ServiceStatus::FinishedFailed)
+        );
+        assert_eq!(
+            handle_restart_strategy(&sh, false),
+            new_status(ServiceStatus::Finished)
+        );
+        // Exiting for other reasons, it is restarted.
+        sh.healthiness_checks_failed = Some(0);
+        assert_eq!(
+            handle_restart_strategy(&sh, true),
+            new_status(ServiceStatus::Initial)
+        );
+    }
+
+    #[test]
+    fn test_handle_restart_strategy_on_oom() {
+        let new_status = |status| Event::new_status_update("servicename", status);
+        let matrix = [
+            ("never", "restart", ServiceStatus::Initial),
+            ("always", "stop", ServiceStatus::FinishedFailed),
+        ];
+        for (strategy, on_oom, expected) in matrix {
+            let service = format!(
+                r#"name="servicename"
+command = "Not relevant"
+[restart]
+strategy = "{strategy}"
+on-oom = "{on_oom}"
+"#
+            );
+            let mut sh: ServiceHandler = Service::from_str(&service).unwrap().into();
+            sh.oom_killed = true;
+            assert_eq!(handle_restart_strategy(&sh, true), new_status(expected));
+            // Exiting for other reasons, the restart strategy applies.
+            sh.oom_killed = false;
+            let expected = if strategy == "always" {
+                ServiceStatus::Initial
+            } else {
+                ServiceStatus::FinishedFailed
+            };
+            assert_eq!(handle_restart_strategy(&sh, true), new_status(expected));
+        }
+    }
+
     #[test]
     fn test_should_force_kill() {
         let service = r#"command="notrelevant"
@@ -430,6 +577,21 @@ wait = "10s"
         assert!(should_force_kill(&sh, None));
     }
 
+    #[test]
+    fn test_is_killed_by_horust() {
+        let mut sh: ServiceHandler = Service::from_name("a").into();
+        sh.status = ServiceStatus::Running;
+        assert!(!sh.is_killed_by_horust());
+        sh.status = ServiceStatus::InKilling;
+        assert!(sh.is_killed_by_horust());
+        // A force-killed service is already marked as failed when it's reaped.
+        sh.status = ServiceStatus::Failed;
+        assert!(!sh.is_killed_by_horust());
+        sh.sigkilled = true;
+        assert!(sh.is_killed_by_horust());
+        assert!(!sh.is_oom_killed());
+    }
+
     #[test]
     fn test_handle_failed_service() {
         let mut service = Service::from_name("b");
@@ -572,6 +734,16 @@ wait = "10s"
         assert_eq!(new_sh.restart_attempts, 0);
     }
 
+    #[test]
+    fn test_started_transition_resets_healthchecks() {
+        let mut sh = make_handler("svc", ServiceStatus::Starting);
+        sh.healthiness_checks_failed = Some(5);
+        sh.degraded = true;
+        let (new_sh, _) = sh.change_status(ServiceStatus::Started);
+        assert_eq!(new_sh.healthiness_checks_failed, None);
+        assert!(!new_sh.degraded);
+    }
+
     #[test]
     fn test_non_started_transition_preserves_restart_attempts() {
         let mut sh = make_handler("svc", ServiceStatus::Started);
@@ -628,6 +800,31 @@ wait = "10s"
         }
     }
 
+    #[test]
+    fn test_add_readiness_event() {
+        let mut sh = make_handler("svc", ServiceStatus::Running);
+        // Without readiness checks, a running service is ready.
+        assert!(sh.is_ready());
+
+        let mut service = Service::from_name("svc");
+        service.readiness.command = Some("true".into());
+        sh = service.into();
+        sh.status = ServiceStatus::Running;
+        assert!(!sh.is_ready());
+        sh.add_readiness_event(HealthinessStatus::Healthy);
+        assert!(sh.is_ready());
+        // Failing readiness checks never count as failed healthchecks.
+        sh.add_readiness_event(HealthinessStatus::Unhealthy);
+        assert!(!sh.is_ready());
+        assert_eq!(sh.healthiness_checks_failed, None);
+
+        // Readiness is reset when the service is started again.
+        sh.add_readiness_event(HealthinessStatus::Healthy);
+        sh.status = ServiceStatus::Starting;
+        let (sh, _) = sh.change_status(ServiceStatus::Started);
+        assert!(!sh.readiness_passed);
+    }
+
     #[test]
     fn test_has_some_failed_healthchecks() {
         let sh = make_handler("svc", ServiceStatus::Running);
@@ -681,7 +878,7 @@ wait = "10s"
     // FSM event generation tests — next_events (normal operation)
     // ========================================================================
 
-    use crate::horust::formats::RestartStrategy;
+    use crate::horust::formats::{HealthinessFailureAction, RestartStrategy, TerminationSignal};
     use crate::horust::supervisor::LifecycleStatus;
 
     #[test]
@@ -757,6 +954,27 @@ wait = "10s"
         );
     }
 
+    #[test]
+    fn test_next_running_healthchecks_exceeded_not_killing() {
+        // These actions are applied when the healthcheck event is received.
+        for on_failure in [
+            HealthinessFailureAction::MarkUnhealthy,
+            HealthinessFailureAction::RunCommand,
+            HealthinessFailureAction::Signal(TerminationSignal::USR1),
+        ] {
+            let mut service = Service::from_name("svc");
+            service.healthiness.on_failure = on_failure;
+            let mut repo = make_repo_from_services(vec![service]);
+            {
+                let sh = repo.services.get_mut("svc").unwrap();
+                sh.status = ServiceStatus::Running;
+                sh.healthiness_checks_failed = Some(4);
+            }
+            let sh = repo.services.get("svc").unwrap();
+            assert!(sh.next(&repo, LifecycleStatus::Running).is_empty());
+        }
+    }
+
     #[test]
     fn test_next_running_healthchecks_ok_emits_nothing() {
         let mut repo = make_repo(vec![("svc", ServiceStatus::Running)]);
@@ -2,9 +2,7 @@
 extern crate crossbeam;
 #[macro_use]
 extern crate log;
-#[macro_use]
-extern crate maplit;
 
-pub use crate::horust::{Horust, get_sample_service};
+pub use crate::horust::{CustomCheck, CustomCheckParams, Horust, get_sample_service};
 
 pub mod horust;
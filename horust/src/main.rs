@@ -3,7 +3,7 @@ use std::path::PathBuf;
 use anyhow::{Context, Result};
 use clap::Parser;
 use horust::Horust;
-use horust::horust::{ExitStatus, HorustConfig};
+use horust::horust::{ExitStatus, HorustConfig, init_logging};
 use log::{error, info};
 use nix::unistd::getpid;
 
@@ -35,20 +35,24 @@ struct Opts {
     command: Vec<String>,
 }
 
-fn main() -> Result<()> {
-    // Set up logging.
-    let env = env_logger::Env::new()
-        .filter("HORUST_LOG")
-        .write_style("HORUST_LOG_STYLE");
-    env_logger::init_from_env(env);
-
+fn main() {
     let opts = Opts::parse();
 
     if opts.sample_service {
         println!("{}", horust::get_sample_service());
-        return Ok(());
+        return;
+    }
+
+    // Set up logging from the command line first, so that failing to start is logged in the
+    // requested format too.
+    init_logging(opts.horust_config.log_format.unwrap_or_default());
+    if let Err(err) = run(opts) {
+        error!("{:#}", err);
+        std::process::exit(1);
     }
+}
 
+fn run(opts: Opts) -> Result<()> {
     let config = HorustConfig::load_and_merge(&opts.horust_config, &opts.config_path)
         .with_context(|| {
             format!(
@@ -56,6 +60,9 @@ fn main() -> Result<()> {
                 &opts.config_path.display()
             )
         })?;
+    // The format can also be set in the configuration file.
+    init_logging(config.log_format.unwrap_or_default());
+
     if !opts.uds_folder_path.exists() {
         std::fs::create_dir_all(&opts.uds_folder_path).with_context(|| {
             format!(
@@ -89,11 +96,13 @@ fn main() -> Result<()> {
         Horust::from_command(opts.command.join(" "), uds_path)
     };
 
-    if let ExitStatus::SomeServiceFailed = horust.run() {
-        if config.unsuccessful_exit_finished_failed {
-            error!("Some processes have failed.");
-            std::process::exit(101);
-        }
+    horust.apply_config(&config);
+
+    if let ExitStatus::SomeServiceFailed = horust.run()
+        && config.unsuccessful_exit_finished_failed
+    {
+        error!("Some processes have failed.");
+        std::process::exit(101);
     }
     Ok(())
 }
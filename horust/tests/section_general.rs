@@ -2,13 +2,16 @@ use assert_cmd::prelude::*;
 use predicates::str::{contains, is_empty};
 use tempfile::TempDir;
 
+#[allow(dead_code)]
 mod utils;
 use nix::sys::signal::{Signal, kill};
+use std::io::Read;
+use std::net::{TcpListener, UdpSocket};
 use std::thread::sleep;
 use std::time::Duration;
 use utils::*;
 
-fn test_single_output_redirection(stream: &str, to: &str) {
+fn test_single_output_redirection(stream: &str, to: &str, extra_config: &str) {
     let (mut cmd, temp_dir) = get_cli();
     let pattern = "Hello".to_string();
     let to = if to == "FILE" {
@@ -24,7 +27,11 @@ fn test_single_output_redirection(stream: &str, to: &str) {
 printf "{}" {}"#,
         pattern, redir
     );
-    let service = format!(r#"{}="{}""#, stream, to);
+    let service = format!(
+        r#"{}="{}"
+{}"#,
+        stream, to, extra_config
+    );
     store_service_script(
         temp_dir.path(),
         script.as_str(),
@@ -54,7 +61,19 @@ fn test_output_redirection() {
     let to = ["STDOUT", "STDERR", "FILE"];
     from.iter()
         .flat_map(|fr| to.iter().map(move |t| (fr, t)))
-        .for_each(|(stream, to)| test_single_output_redirection(stream, to));
+        .for_each(|(stream, to)| test_single_output_redirection(stream, to, ""));
+}
+
+#[test]
+fn test_output_redirection_with_log_buffer() {
+    // The output goes through horust, but should still reach the same destinations.
+    let from = ["stdout", "stderr"];
+    let to = ["STDOUT", "STDERR", "FILE"];
+    from.iter()
+        .flat_map(|fr| to.iter().map(move |t| (fr, t)))
+        .for_each(|(stream, to)| {
+            test_single_output_redirection(stream, to, r#"log-buffer-size = "1 KiB""#)
+        });
 }
 
 #[test]
@@ -115,6 +134,41 @@ sync
     assert_eq!(content, expected_content);
 }
 
+#[test]
+fn test_output_forwarding() {
+    let (mut cmd, temp_dir) = get_cli();
+    let json_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let syslog_listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+    syslog_listener
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let script = r#"#!/usr/bin/env bash
+echo "to stdout"
+echo "to stderr" 1>&2
+"#;
+    let service = format!(
+        r#"stdout = "json+tcp://{}"
+stderr = "syslog+udp://{}""#,
+        json_listener.local_addr().unwrap(),
+        syslog_listener.local_addr().unwrap()
+    );
+    store_service_script(temp_dir.path(), script, Some(service.as_str()), None);
+    cmd.assert().success().stdout(is_empty());
+
+    let (mut stream, _) = json_listener.accept().unwrap();
+    let mut json = String::new();
+    stream.read_to_string(&mut json).unwrap();
+    assert!(json.ends_with("\n"), "{json}");
+    assert!(json.contains(r#""stream":"stdout""#), "{json}");
+    assert!(json.contains(r#""message":"to stdout""#), "{json}");
+
+    let mut buf = [0; 1024];
+    let read = syslog_listener.recv(&mut buf).unwrap();
+    let syslog = String::from_utf8_lossy(&buf[..read]);
+    assert!(syslog.starts_with("<27>1 "), "{syslog}");
+    assert!(syslog.ends_with("stderr - to stderr"), "{syslog}");
+}
+
 #[test]
 fn test_search_path_not_found() {
     let (mut cmd, temp_dir) = get_cli();
@@ -196,6 +250,262 @@ whoami"#;
     cmd.assert().success().stdout(contains("games"));
 }
 
+#[test]
+#[ignore]
+fn test_group() {
+    // Changing the groups requires running as root.
+    let (mut cmd, temp_dir) = get_cli();
+    let service = r#"group = 4242
+supplementary-groups = [4243]"#;
+    let script = r#"#!/usr/bin/env bash
+echo "gid: $(id -g) groups: $(id -G)""#;
+    store_service_script(temp_dir.path(), script, Some(service), None);
+    cmd.assert()
+        .success()
+        .stdout(contains("gid: 4242 groups: 4242"))
+        .stdout(contains(" 4243"));
+}
+
+#[test]
+fn test_no_new_privileges() {
+    let (mut cmd, temp_dir) = get_cli();
+    let service = r#"no-new-privileges = true"#;
+    let script = r#"#!/usr/bin/env bash
+grep NoNewPrivs /proc/self/status"#;
+    store_service_script(temp_dir.path(), script, Some(service), None);
+    cmd.assert().success().stdout(contains("NoNewPrivs:\t1"));
+}
+
+#[test]
+#[ignore]
+fn test_capabilities() {
+    // Changing the capabilities requires running as root.
+    let (mut cmd, temp_dir) = get_cli();
+    let service = r#"
+command = "/bin/sh -c 'grep Cap /proc/self/status'"
+user = "nobody"
+working-directory = "/"
+capabilities = { keep = ["CAP_CHOWN"], ambient = ["CAP_NET_BIND_SERVICE"] }
+"#;
+    store_service(temp_dir.path(), service, None);
+    cmd.assert()
+        .success()
+        .stdout(contains("CapEff:\t0000000000000400"))
+        .stdout(contains("CapBnd:\t0000000000000401"))
+        .stdout(contains("CapAmb:\t0000000000000400"));
+}
+
+#[test]
+fn test_rlimits() {
+    let (mut cmd, temp_dir) = get_cli();
+    let service = r#"
+[rlimits]
+nofile = { soft = 100 }
+core = 0
+"#;
+    let script = r#"#!/usr/bin/env bash
+echo "nofile $(ulimit -Sn) core $(ulimit -Hc)""#;
+    store_service_script(temp_dir.path(), script, Some(service), None);
+    cmd.assert().success().stdout(contains("nofile 100 core 0"));
+}
+
+#[test]
+fn test_scheduling() {
+    // Lowering the priority doesn't need any privilege.
+    let (mut cmd, temp_dir) = get_cli();
+    let service = r#"
+nice = 5
+io-scheduling = { class = "idle" }
+oom-score-adjust = 500
+cpu-affinity = "0"
+umask = "0027"
+"#;
+    let script = r#"#!/usr/bin/env bash
+echo "nice $(nice) umask $(umask) oom $(cat /proc/self/oom_score_adj)"
+grep Cpus_allowed_list /proc/self/status"#;
+    store_service_script(temp_dir.path(), script, Some(service), None);
+    cmd.assert()
+        .success()
+        .stdout(contains("nice 5 umask 0027 oom 500"))
+        .stdout(contains("Cpus_allowed_list:\t0\n"));
+}
+
+#[test]
+fn test_seccomp() {
+    // With no-new-privileges, loading a filter doesn't need any privilege.
+    let (mut cmd, temp_dir) = get_cli();
+    let profile = temp_dir.path().join("seccomp.json");
+    std::fs::write(
+        &profile,
+        r#"{
+    "defaultAction": "SCMP_ACT_ALLOW",
+    "syscalls": [{ "names": ["mkdir", "mkdirat"], "action": "SCMP_ACT_ERRNO" }]
+}"#,
+    )
+    .unwrap();
+    let service = format!(r#"seccomp = "{}""#, profile.display());
+    let script = format!(
+        r#"#!/usr/bin/env bash
+grep NoNewPrivs /proc/self/status
+mkdir {}/denied"#,
+        temp_dir.path().display()
+    );
+    store_service_script(temp_dir.path(), &script, Some(&service), None);
+    cmd.assert()
+        .success()
+        .stdout(contains("NoNewPrivs:\t1"))
+        .stderr(contains("Operation not permitted"));
+    assert!(!temp_dir.path().join("denied").exists());
+}
+
+#[test]
+fn test_seccomp_no_network() {
+    let (mut cmd, temp_dir) = get_cli();
+    let service = r#"seccomp = "no-network""#;
+    let script = r#"#!/usr/bin/env bash
+echo > /dev/tcp/127.0.0.1/1"#;
+    store_service_script(temp_dir.path(), script, Some(service), None);
+    cmd.assert()
+        .success()
+        .stderr(contains("Address family not supported"));
+}
+
+#[test]
+#[ignore]
+fn test_oom_killed() {
+    // Limiting the memory of a cgroup requires running as root.
+    let (mut cmd, temp_dir) = get_cli();
+    // tail buffers /dev/zero until it finds a newline.
+    let service = r#"
+command = "/bin/sh -c 'sleep 0.5; exec tail /dev/zero'"
+[restart]
+strategy = "always"
+on-oom = "stop"
+[resource-limit]
+memory = "20 MiB"
+memory-swap = "0"
+"#;
+    store_service(temp_dir.path(), service, None);
+    cmd.env("HORUST_LOG", "warn")
+        .args(vec!["--unsuccessful-exit-finished-failed"])
+        .assert()
+        .failure()
+        .stderr(contains("was OOM-killed"));
+}
+
+#[test]
+#[ignore]
+fn test_cgroups() {
+    // Creating cgroups requires running as root.
+    let (mut cmd, temp_dir) = get_cli();
+    let script = r#"echo "horust $PPID"
+cat /proc/self/cgroup"#;
+    store_service_script(temp_dir.path(), script, None, Some("cgroup"));
+    let output = cmd.output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let instance = format!(
+        "horust-{}",
+        stdout
+            .lines()
+            .find_map(|line| line.strip_prefix("horust "))
+            .unwrap()
+    );
+    let service_cgroup = format!("/{}/services/cgroup.toml", instance);
+    assert!(
+        stdout.lines().any(|line| line.ends_with(&service_cgroup)),
+        "{stdout}"
+    );
+
+    // Once empty, the cgroups are removed: the one of the instance is in the current cgroup with
+    // cgroup v2, at the root of every hierarchy with cgroup v1.
+    let cgroup_root = std::path::Path::new("/sys/fs/cgroup");
+    let current = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+    let current = current
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .unwrap_or("/");
+    let mut dirs = vec![cgroup_root.join(current.trim_start_matches('/'))];
+    dirs.extend(
+        std::fs::read_dir(cgroup_root)
+            .unwrap()
+            .map(|entry| entry.unwrap().path()),
+    );
+    for dir in dirs {
+        assert!(!dir.join(&instance).exists(), "{}", dir.display());
+    }
+}
+
+#[test]
+#[ignore]
+fn test_sandbox() {
+    // Mounting requires running as root.
+    let (mut cmd, temp_dir) = get_cli();
+    let data = temp_dir.path().join("data");
+    std::fs::create_dir(&data).unwrap();
+    std::fs::write(data.join("file"), "data").unwrap();
+    let shared = temp_dir.path().join("shared");
+    std::fs::create_dir(&shared).unwrap();
+    std::fs::write(shared.join("file"), "shared").unwrap();
+    let script = r#"echo "tmp $(ls -A /tmp | wc -l)"
+echo "srv $(cat /srv/file)"
+touch /srv/new 2>/dev/null || echo "srv is read-only"
+echo "var-tmp $(cat /var/tmp/file)"
+touch /var/tmp/new 2>/dev/null || echo "var-tmp is read-only"
+echo "hostname [$(cat /etc/hostname)] opt $(ls -A /opt | wc -l)""#;
+    std::fs::write(data.join("script.sh"), script).unwrap();
+    let service = format!(
+        r#"command = "/bin/sh -c '. /srv/script.sh'"
+working-directory = "/"
+[sandbox]
+private-tmp = true
+read-only-paths = ["/var"]
+inaccessible-paths = ["/etc/hostname", "/opt"]
+bind-mounts = [
+    {{ source = "{data}", target = "/srv", read-only = true }},
+    {{ source = "{shared}", target = "/var/tmp" }},
+]
+"#,
+        data = data.display(),
+        shared = shared.display()
+    );
+    store_service(temp_dir.path(), &service, None);
+    cmd.assert()
+        .success()
+        .stdout(contains("tmp 0"))
+        .stdout(contains("srv data"))
+        .stdout(contains("srv is read-only"))
+        // Bound over the private /var/tmp, and read-only as a submount of /var.
+        .stdout(contains("var-tmp shared"))
+        .stdout(contains("var-tmp is read-only"))
+        .stdout(contains("hostname [] opt 0"));
+}
+
+#[test]
+#[ignore]
+fn test_namespaces() {
+    // Creating namespaces requires running as root, or unprivileged user namespaces.
+    let (mut cmd, temp_dir) = get_cli();
+    let service = r#"
+[namespaces]
+network = true
+pid = true
+ipc = true
+uts = true
+hostname = "sandboxed"
+user = true
+"#;
+    let script = r#"#!/usr/bin/env bash
+echo "pid $$ uid $(id -u) hostname $(hostname)"
+echo "processes $(ls /proc | grep -c '^[0-9]')"
+echo "interfaces $(tail -n +3 /proc/net/dev | cut -d: -f1 | tr -d ' ')""#;
+    store_service_script(temp_dir.path(), script, Some(service), None);
+    cmd.assert()
+        .success()
+        .stdout(contains("pid 1 uid 0 hostname sandboxed"))
+        .stdout(contains("interfaces lo\n"));
+}
+
 #[test]
 fn test_termination_with_pending_thread() {
     // start-delay should not interfere with the shutting down.
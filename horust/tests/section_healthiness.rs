@@ -1,3 +1,4 @@
+#[allow(dead_code)]
 mod utils;
 use std::io::{Read, Write};
 use std::net::{Ipv4Addr, SocketAddrV4, TcpListener};
@@ -13,7 +14,14 @@ fn handle_requests(listener: TcpListener, stop: Receiver<()>) -> io::Result<()>
         match stream {
             Ok(mut stream) => {
                 let mut buffer = [0; 512];
-                stream.read(&mut buffer)?;
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer)?;
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                }
                 let response = b"HTTP/1.1 200 OK\r\n\r\n";
                 stream.write_all(response).expect("Stream write");
             }
@@ -67,6 +75,30 @@ http-endpoint = "{}""#,
     Ok(())
 }
 
+#[test]
+fn test_healthcheck_tcp() -> io::Result<()> {
+    let (mut cmd, tempdir) = get_cli();
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let service = format!(
+        r#"
+[termination]
+wait = "1s"
+[restart]
+strategy = "never"
+[healthiness]
+tcp-endpoint = "{}""#,
+        listener.local_addr()?
+    );
+    let script = r#"#!/usr/bin/env bash
+    sleep 2
+    "#;
+    store_service_script(tempdir.path(), script, Some(service.as_str()), None);
+    let cmd = cmd.args(vec!["--unsuccessful-exit-finished-failed"]);
+    run_async(cmd, true).recv_or_kill(Duration::from_secs(15));
+    drop(listener);
+    Ok(())
+}
+
 #[test]
 fn test_healthcheck_file() -> io::Result<()> {
     let (mut cmd, tempdir) = get_cli();
@@ -90,3 +122,205 @@ file-path = "{}""#,
     run_async(cmd, true).recv_or_kill(Duration::from_secs(70));
     Ok(())
 }
+
+#[test]
+fn test_healthcheck_start_period() -> io::Result<()> {
+    // Without a start period, the failed checks before `healthy` is created would prevent `a` from
+    // ever being considered running, and `b` would start only after `a` exited.
+    let (mut cmd, tempdir) = get_cli();
+    let service_a = format!(
+        r#"
+[healthiness]
+file-path = "{}"
+interval = "200ms"
+start-period = "10s""#,
+        tempdir.path().join("healthy").display()
+    );
+    let script_a = r#"#!/usr/bin/env bash
+sleep 1
+touch healthy
+sleep 4
+touch a_done
+"#;
+    store_service_script(
+        tempdir.path(),
+        script_a,
+        Some(service_a.as_str()),
+        Some("a"),
+    );
+    let script_b = r#"#!/usr/bin/env bash
+test ! -f a_done && touch b_started_while_a_running
+"#;
+    store_service_script(
+        tempdir.path(),
+        script_b,
+        Some(r#"start-after = ["a.toml"]"#),
+        None,
+    );
+    run_async(&mut cmd, true).recv_or_kill(Duration::from_secs(15));
+    assert!(tempdir.path().join("b_started_while_a_running").exists());
+    Ok(())
+}
+
+#[test]
+fn test_readiness() -> io::Result<()> {
+    // `b` must wait for `a` to be ready, and failing readiness checks must not kill `a`.
+    let (mut cmd, tempdir) = get_cli();
+    let service_a = format!(
+        r#"
+[readiness]
+file-path = "{}"
+interval = "200ms""#,
+        tempdir.path().join("ready").display()
+    );
+    let script_a = r#"#!/usr/bin/env bash
+sleep 2
+touch ready
+sleep 2
+touch a_done
+"#;
+    store_service_script(
+        tempdir.path(),
+        script_a,
+        Some(service_a.as_str()),
+        Some("a"),
+    );
+    let script_b = r#"#!/usr/bin/env bash
+test -f ready && touch b_started_after_ready
+"#;
+    store_service_script(
+        tempdir.path(),
+        script_b,
+        Some(r#"start-after = ["a.toml"]"#),
+        None,
+    );
+    run_async(&mut cmd, true).recv_or_kill(Duration::from_secs(15));
+    assert!(tempdir.path().join("b_started_after_ready").exists());
+    assert!(tempdir.path().join("a_done").exists());
+    Ok(())
+}
+
+#[test]
+fn test_healthcheck_ready_pattern() -> io::Result<()> {
+    // `b` must start only once `a` has printed that it's ready, and `a`'s output must still reach
+    // its destination.
+    let (mut cmd, tempdir) = get_cli();
+    let service_a = format!(
+        r#"stdout = "{}"
+[healthiness]
+ready-pattern = "ready to accept connections$"
+interval = "200ms""#,
+        tempdir.path().join("a.log").display()
+    );
+    let script_a = r#"#!/usr/bin/env bash
+echo "starting"
+sleep 2
+echo "ready to accept connections"
+sleep 2
+"#;
+    store_service_script(
+        tempdir.path(),
+        script_a,
+        Some(service_a.as_str()),
+        Some("a"),
+    );
+    let script_b = r#"#!/usr/bin/env bash
+grep -q "ready to accept connections" a.log && touch b_started_after_ready
+"#;
+    store_service_script(
+        tempdir.path(),
+        script_b,
+        Some(r#"start-after = ["a.toml"]"#),
+        None,
+    );
+    run_async(&mut cmd, true).recv_or_kill(Duration::from_secs(15));
+    assert!(tempdir.path().join("b_started_after_ready").exists());
+    let output = std::fs::read_to_string(tempdir.path().join("a.log"))?;
+    assert_eq!(output, "starting\nready to accept connections\n");
+    Ok(())
+}
+
+/// The script is healthy for a second, then its `healthy` file disappears.
+const BECOMES_UNHEALTHY: &str = r#"#!/usr/bin/env bash
+touch healthy
+sleep 1
+rm healthy
+"#;
+
+fn on_failure_service(tempdir: &std::path::Path, on_failure: &str) -> String {
+    format!(
+        r#"
+[termination]
+wait = "1s"
+[restart]
+strategy = "always"
+[healthiness]
+file-path = "{}"
+interval = "100ms"
+max-failed = 0
+{on_failure}"#,
+        tempdir.join("healthy").display()
+    )
+}
+
+#[test]
+fn test_healthcheck_on_failure_stop() -> io::Result<()> {
+    // Even with `restart = always`, the service is not restarted.
+    let (mut cmd, tempdir) = get_cli();
+    let service = on_failure_service(tempdir.path(), r#"on-failure = "stop""#);
+    let script = format!("{BECOMES_UNHEALTHY}echo run >> runs\nsleep 30\n");
+    store_service_script(tempdir.path(), &script, Some(service.as_str()), None);
+    let cmd = cmd.args(vec!["--unsuccessful-exit-finished-failed"]);
+    run_async(cmd, false).recv_or_kill(Duration::from_secs(15));
+    assert_eq!(
+        std::fs::read_to_string(tempdir.path().join("runs"))?,
+        "run\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_healthcheck_on_failure_mark_unhealthy() -> io::Result<()> {
+    // The service is not killed, and it exits on its own.
+    let (mut cmd, tempdir) = get_cli();
+    let service = on_failure_service(tempdir.path(), r#"on-failure = "mark-unhealthy""#)
+        .replace(r#"strategy = "always""#, r#"strategy = "never""#);
+    let script = format!("{BECOMES_UNHEALTHY}sleep 2\ntouch survived\n");
+    store_service_script(tempdir.path(), &script, Some(service.as_str()), None);
+    run_async(&mut cmd, true).recv_or_kill(Duration::from_secs(15));
+    assert!(tempdir.path().join("survived").exists());
+    Ok(())
+}
+
+#[test]
+fn test_healthcheck_on_failure_signal() -> io::Result<()> {
+    let (mut cmd, tempdir) = get_cli();
+    let service = on_failure_service(tempdir.path(), r#"on-failure = "signal:USR1""#)
+        .replace(r#"strategy = "always""#, r#"strategy = "never""#);
+    let script = format!(
+        "trap 'touch got_usr1; exit 0' USR1\n{BECOMES_UNHEALTHY}for i in $(seq 100); do sleep 0.1; done\n"
+    );
+    store_service_script(tempdir.path(), &script, Some(service.as_str()), None);
+    run_async(&mut cmd, true).recv_or_kill(Duration::from_secs(15));
+    assert!(tempdir.path().join("got_usr1").exists());
+    Ok(())
+}
+
+#[test]
+fn test_healthcheck_on_failure_run_command() -> io::Result<()> {
+    // The command runs before the service is killed.
+    let (mut cmd, tempdir) = get_cli();
+    let service = on_failure_service(
+        tempdir.path(),
+        r#"on-failure = "run-command"
+on-failure-command = "touch remediated""#,
+    )
+    .replace(r#"strategy = "always""#, r#"strategy = "never""#);
+    let script = format!(
+        "trap 'test -f remediated && touch killed_after_command; exit 1' TERM\n{BECOMES_UNHEALTHY}for i in $(seq 100); do sleep 0.1; done\n"
+    );
+    store_service_script(tempdir.path(), &script, Some(service.as_str()), None);
+    run_async(&mut cmd, true).recv_or_kill(Duration::from_secs(15));
+    assert!(tempdir.path().join("killed_after_command").exists());
+    Ok(())
+}
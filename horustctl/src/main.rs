@@ -4,8 +4,14 @@ use env_logger::Env;
 use horust_commands_lib::{ClientHandler, get_path};
 use log::debug;
 use std::fs::read_dir;
+use std::io::Write;
 use std::os::unix::fs::FileTypeExt;
 use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// How often `logs --follow` asks horust for new output.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
 /// Simple program to greet a person
 #[derive(Parser, Debug)]
@@ -29,6 +35,8 @@ struct HorustctlArgs {
 #[derive(Subcommand, Debug)]
 enum Commands {
     Status(StatusArgs),
+    /// Print the output of a service, kept in memory by horust (requires `log-buffer-size`).
+    Logs(LogsArgs),
 }
 
 #[derive(Args, Debug)]
@@ -36,6 +44,17 @@ struct StatusArgs {
     service_name: Option<String>,
 }
 
+#[derive(Args, Debug)]
+struct LogsArgs {
+    service_name: String,
+    /// Keep printing the output as the service produces it.
+    #[arg(short, long)]
+    follow: bool,
+    /// Only print the last N lines.
+    #[arg(short = 'n', long)]
+    tail: Option<u32>,
+}
+
 fn main() -> Result<()> {
     env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
     let args = HorustctlArgs::parse();
@@ -49,17 +68,63 @@ fn main() -> Result<()> {
         Commands::Status(status_args) => {
             debug!("Status command received: {status_args:?}");
             debug!("uds path : {uds_path:?}");
-            let (service_name, service_status) =
-                uds_handler.send_status_request(status_args.service_name.clone().unwrap())?;
+            let response = uds_handler
+                .send_status_details_request(status_args.service_name.clone().unwrap())?;
+            let service_name = &response.service_name;
             println!(
                 "Current status for '{service_name}' is: '{}'.",
-                service_status.as_str_name()
+                response.service_status().as_str_name()
             );
+            if response.degraded {
+                println!("'{service_name}' is degraded: its health checks are failing.");
+            }
+            if response.oom_killed {
+                println!(
+                    "The last run of '{service_name}' was OOM-killed: it ran out of memory in its cgroup."
+                );
+            }
+            for health_check in &response.health_checks {
+                let exit_code = health_check
+                    .exit_code
+                    .map_or_else(|| "none".to_string(), |code| code.to_string());
+                println!(
+                    "Last run of health check `{}`: exit code {exit_code}.",
+                    health_check.command
+                );
+                for line in health_check.output.lines() {
+                    println!("  {line}");
+                }
+            }
+        }
+        Commands::Logs(logs_args) => {
+            debug!("Logs command received: {logs_args:?}");
+            let service_name = logs_args.service_name.clone();
+            let tail = logs_args.tail.unwrap_or(0);
+            let (content, mut offset) =
+                uds_handler.send_logs_request(service_name.clone(), tail, None)?;
+            print_logs(&content)?;
+            if logs_args.follow {
+                loop {
+                    thread::sleep(FOLLOW_POLL_INTERVAL);
+                    let mut uds_handler = ClientHandler::new_client(&uds_path)?;
+                    let (content, next_offset) =
+                        uds_handler.send_logs_request(service_name.clone(), 0, Some(offset))?;
+                    print_logs(&content)?;
+                    offset = next_offset;
+                }
+            }
         }
     }
     Ok(())
 }
 
+fn print_logs(content: &[u8]) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(content)?;
+    stdout.flush()?;
+    Ok(())
+}
+
 fn get_uds_path(pid: Option<i32>, sockets_folder_path: PathBuf) -> Result<PathBuf> {
     if !sockets_folder_path.exists() {
         bail!("the specified sockets folder path '{sockets_folder_path:?}' does not exists.");
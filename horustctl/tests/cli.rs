@@ -128,3 +128,69 @@ done"#,
         .success()
         .stdout(contains("running"));
 }
+
+#[test]
+fn test_cli_logs() {
+    let temp_dir = TempDir::with_prefix("horustctl").unwrap();
+    let mut horust_cmd = escargot::CargoBuild::new()
+        .package("horust")
+        .current_release()
+        .current_target()
+        .run()
+        .expect("Building Horust binary")
+        .command();
+
+    horust_cmd.current_dir(&temp_dir).args(vec![
+        "--services-path",
+        temp_dir.path().display().to_string().as_str(),
+        "--uds-folder-path",
+        temp_dir.path().display().to_string().as_str(),
+    ]);
+
+    store_service_script(
+        temp_dir.path(),
+        r#"#!/usr/bin/env bash
+echo "first line"
+sleep 0.5
+echo "second line" 1>&2
+touch file
+sleep 3"#,
+        Some(r#"log-buffer-size = "1 KiB""#),
+        Some("buffered"),
+    );
+    thread::spawn(move || {
+        horust_cmd.assert().success().stdout(contains("first line"));
+    });
+    let mut total_wait = 0;
+    const MAX_WAIT_TIME: u32 = 1000;
+    // created by running script
+    while !temp_dir.path().join("file").exists() && total_wait < MAX_WAIT_TIME {
+        total_wait += 50;
+        thread::sleep(Duration::from_millis(50));
+    }
+    Command::new(cargo_bin!("horustctl"))
+        .current_dir(&temp_dir)
+        .args(vec![
+            "--uds-folder-path",
+            temp_dir.path().display().to_string().as_str(),
+            "logs",
+            "buffered.toml",
+        ])
+        .assert()
+        .success()
+        .stdout(contains("first line").and(contains("second line")));
+
+    Command::new(cargo_bin!("horustctl"))
+        .current_dir(&temp_dir)
+        .args(vec![
+            "--uds-folder-path",
+            temp_dir.path().display().to_string().as_str(),
+            "logs",
+            "--tail",
+            "1",
+            "buffered.toml",
+        ])
+        .assert()
+        .success()
+        .stdout(contains("first line").not());
+}